@@ -1,9 +1,15 @@
 fn main() -> shadow_rs::SdResult<()> {
-    println!("cargo:rustc-link-search=native=/usr/local/lib/rats-tls");
-    println!("cargo:rustc-link-lib=dylib=rats_tls");
+    // The mock-rats-tls feature replaces librats_tls with a Rust mock
+    if std::env::var_os("CARGO_FEATURE_MOCK_RATS_TLS").is_none() {
+        println!("cargo:rustc-link-search=native=/usr/local/lib/rats-tls");
+        println!("cargo:rustc-link-lib=dylib=rats_tls");
+    }
 
-    println!("cargo:rustc-link-search=native=./src/policy_engine/opa");
-    println!("cargo:rustc-link-lib=dylib=opa");
+    // Without the opa-go feature policies are evaluated by the Rust engine
+    if std::env::var_os("CARGO_FEATURE_OPA_GO").is_some() {
+        println!("cargo:rustc-link-search=native=./src/policy_engine/opa");
+        println!("cargo:rustc-link-lib=dylib=opa");
+    }
 
     tonic_build::compile_protos("proto/keyprovider.proto")?;
     tonic_build::compile_protos("proto/clientapi.proto")?;
@@ -0,0 +1,25 @@
+use crate::client_api::audit_service_client::AuditServiceClient;
+use crate::client_api::{QueryAuditLogRequest, QueryAuditLogResponse};
+
+pub async fn query_audit_log_cmd(from: u64, to: u64, decision: Option<&str>, addr: &str) {
+    let request = QueryAuditLogRequest {
+        from,
+        to,
+        decision: decision.unwrap_or("").as_bytes().to_vec(),
+    };
+
+    let mut client = AuditServiceClient::connect(format!("http://{}", addr))
+        .await
+        .unwrap();
+
+    let response: QueryAuditLogResponse =
+        client.query_audit_log(request).await.unwrap().into_inner();
+    info!(
+        "query_audit_log status is: {:?}",
+        String::from_utf8(response.status).unwrap()
+    );
+    if !response.intact {
+        warn!("the audit log has been tampered with");
+    }
+    info!("entries:\n{}", String::from_utf8(response.entries).unwrap());
+}
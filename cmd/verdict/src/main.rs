@@ -4,9 +4,13 @@ pub mod client_api {
     tonic::include_proto!("clientapi");
 }
 
+mod audit;
 mod gpg;
 mod image;
 mod opa;
+mod resource;
+mod secret;
+mod token;
 
 #[macro_use]
 extern crate log;
@@ -102,6 +106,101 @@ async fn main() {
                 .help("test OPA's remote policy and local reference")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("set_policy_binding")
+                .long("set-policy-binding")
+                .value_name("BINDING_NAME")
+                .value_name("POLICY_NAME")
+                .value_name("REFERENCE_NAME")
+                .help("Evaluate the evidence matched by --tee, --listener and --signer against <POLICY_NAME> and <REFERENCE_NAME>.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("tee")
+                .long("tee")
+                .value_name("TEE")
+                .help("TEE type the policy binding matches, e.g. sgx, must be used with '--set-policy-binding' or '--bind-opa-bundle'.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("listener")
+                .long("listener")
+                .value_name("ADDRESS")
+                .help("Attestation listener the policy binding matches, must be used with '--set-policy-binding' or '--bind-opa-bundle'.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("signer")
+                .long("signer")
+                .value_name("SIGNER")
+                .help("Workload signer the policy binding matches, e.g. base64 mrSigner, must be used with '--set-policy-binding' or '--bind-opa-bundle'.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("list_policy_bindings")
+                .long("list-policy-bindings")
+                .help("list all policy bindings")
+        )
+        .arg(
+            Arg::with_name("delete_policy_binding")
+                .long("delete-policy-binding")
+                .value_name("BINDING_NAME")
+                .help("delete policy binding <BINDING_NAME>")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("import_sgx_reference")
+                .long("import-sgx-reference")
+                .value_name("REFERENCE_NAME")
+                .value_name("ENCLAVE_PATH")
+                .help("Merge mrEnclave, mrSigner, productId and svn of the signed enclave or SIGSTRUCT <ENCLAVE_PATH> into <REFERENCE_NAME>.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("replace")
+                .long("replace")
+                .help("Replace the values accepted by the reference instead of appending to them, must be used with '--import-sgx-reference'.")
+        )
+        .arg(
+            Arg::with_name("set_opa_bundle")
+                .long("set-opa-bundle")
+                .value_name("BUNDLE_NAME")
+                .value_name("BUNDLE_PATH")
+                .help("Store the OPA bundle <BUNDLE_PATH>, a tar.gz of Rego modules, data.json files and a .manifest, as <BUNDLE_NAME>.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("export_opa_bundle")
+                .long("export-opa-bundle")
+                .value_name("BUNDLE_NAME")
+                .help("Export the OPA bundle named <BUNDLE_NAME> to <BUNDLE_NAME>.tar.gz.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("list_opa_bundles")
+                .long("list-opa-bundles")
+                .help("list all OPA bundles")
+        )
+        .arg(
+            Arg::with_name("delete_opa_bundle")
+                .long("delete-opa-bundle")
+                .value_name("BUNDLE_NAME")
+                .help("delete OPA bundle <BUNDLE_NAME>")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("bind_opa_bundle")
+                .long("bind-opa-bundle")
+                .value_name("BINDING_NAME")
+                .value_name("BUNDLE_NAME")
+                .help("Evaluate the evidence matched by --tee, --listener and --signer against the OPA bundle <BUNDLE_NAME>.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("policy_cache_metrics")
+                .long("policy-cache-metrics")
+                .help("get the hits, misses and hit rate of verdictd's prepared policy cache")
+        )
         .arg(
             Arg::with_name("list_gpg_keys")
                 .long("list-gpg-keys")
@@ -150,6 +249,103 @@ async fn main() {
                 .help("set image policy according to the contents in <POLICY_PATH>.")
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("set_resource")
+                .long("set-resource")
+                .value_name("RESOURCE_NAME")
+                .value_name("RESOURCE_PATH")
+                .help("Upload the contents in <RESOURCE_PATH> as resource <RESOURCE_NAME>, in <repository>/<type>/<tag> format.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("list_resources")
+                .long("list-resources")
+                .value_name("REPOSITORY")
+                .help("list all resources, or only the ones in <REPOSITORY>")
+                .takes_value(true)
+                .min_values(0),
+        )
+        .arg(
+            Arg::with_name("delete_resource")
+                .long("delete-resource")
+                .value_name("RESOURCE_NAME")
+                .help("delete resource <RESOURCE_NAME>")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("create_secret")
+                .long("create-secret")
+                .value_name("SECRET_ID")
+                .value_name("SECRET_PATH")
+                .help("Create secret <SECRET_ID> with the contents in <SECRET_PATH>, limited by --max-uses and/or --ttl.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max_uses")
+                .long("max-uses")
+                .value_name("NUMBER")
+                .help("Max number of times the secret is released, must be used with '--create-secret'.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("ttl")
+                .long("ttl")
+                .value_name("SECONDS")
+                .help("Lifetime of the secret, must be used with '--create-secret'.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("secret_status")
+                .long("secret-status")
+                .value_name("SECRET_ID")
+                .help("get the usage status of secret <SECRET_ID>")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("delete_secret")
+                .long("delete-secret")
+                .value_name("SECRET_ID")
+                .help("delete secret <SECRET_ID>")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("get_jwks")
+                .long("get-jwks")
+                .help("get the public keys verifying attestation tokens, in JWKS format")
+        )
+        .arg(
+            Arg::with_name("verify_token")
+                .long("verify-token")
+                .value_name("TOKEN_PATH")
+                .help("verify the attestation token in <TOKEN_PATH> and print its claims")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("query_audit_log")
+                .long("query-audit-log")
+                .help("query the attestation audit log, optionally filtered by --from, --to and --decision")
+        )
+        .arg(
+            Arg::with_name("from")
+                .long("from")
+                .value_name("UNIX_TIME")
+                .help("Only the audit log entries decided at or after <UNIX_TIME>, must be used with '--query-audit-log'.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("to")
+                .long("to")
+                .value_name("UNIX_TIME")
+                .help("Only the audit log entries decided at or before <UNIX_TIME>, must be used with '--query-audit-log'.")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("decision")
+                .long("decision")
+                .value_name("DECISION")
+                .help("Only the audit log entries with <DECISION>: allow, deny or none, must be used with '--query-audit-log'.")
+                .takes_value(true),
+        )
         .get_matches();
 
     let client_api = if matches.is_present("client_api") {
@@ -251,6 +447,86 @@ async fn main() {
         .await;
     }
 
+    if matches.is_present("set_policy_binding") {
+        opa::set_policy_binding_cmd(
+            matches.values_of("set_policy_binding").unwrap().collect(),
+            matches.value_of("tee"),
+            matches.value_of("listener"),
+            matches.value_of("signer"),
+            &client_api,
+        )
+        .await;
+    }
+
+    if matches.is_present("list_policy_bindings") {
+        opa::list_policy_bindings_cmd(&client_api).await;
+    }
+
+    if matches.is_present("delete_policy_binding") {
+        opa::delete_policy_binding_cmd(
+            matches.value_of("delete_policy_binding").unwrap(),
+            &client_api,
+        )
+        .await;
+    }
+
+    if matches.is_present("import_sgx_reference") {
+        opa::import_sgx_reference_cmd(
+            matches.values_of("import_sgx_reference").unwrap().collect(),
+            matches.is_present("replace"),
+            &client_api,
+        )
+        .await;
+    }
+
+    if matches.is_present("set_opa_bundle") {
+        opa::set_bundle_cmd(
+            matches.values_of("set_opa_bundle").unwrap().collect(),
+            &client_api,
+        )
+        .await;
+    }
+
+    if matches.is_present("export_opa_bundle") {
+        let mut path: String = if matches.is_present("path") {
+            matches.value_of("path").unwrap().to_string()
+        } else {
+            "./".to_string()
+        };
+        if !path.ends_with("/") {
+            path = format!("{}/", path);
+        }
+        opa::export_bundle_cmd(
+            matches.value_of("export_opa_bundle").unwrap(),
+            path,
+            &client_api,
+        )
+        .await;
+    }
+
+    if matches.is_present("list_opa_bundles") {
+        opa::list_bundles_cmd(&client_api).await;
+    }
+
+    if matches.is_present("delete_opa_bundle") {
+        opa::delete_bundle_cmd(matches.value_of("delete_opa_bundle").unwrap(), &client_api).await;
+    }
+
+    if matches.is_present("bind_opa_bundle") {
+        opa::bind_opa_bundle_cmd(
+            matches.values_of("bind_opa_bundle").unwrap().collect(),
+            matches.value_of("tee"),
+            matches.value_of("listener"),
+            matches.value_of("signer"),
+            &client_api,
+        )
+        .await;
+    }
+
+    if matches.is_present("policy_cache_metrics") {
+        opa::get_policy_cache_metrics_cmd(&client_api).await;
+    }
+
     if matches.is_present("list_gpg_keys") {
         gpg::list_gpg_keys_cmd(&client_api).await;
     }
@@ -314,4 +590,67 @@ async fn main() {
         )
         .await;
     }
+
+    if matches.is_present("set_resource") {
+        resource::set_resource_cmd(
+            matches.values_of("set_resource").unwrap().collect(),
+            &client_api,
+        )
+        .await;
+    }
+
+    if matches.is_present("list_resources") {
+        resource::list_resources_cmd(matches.value_of("list_resources"), &client_api).await;
+    }
+
+    if matches.is_present("delete_resource") {
+        resource::delete_resource_cmd(matches.value_of("delete_resource").unwrap(), &client_api)
+            .await;
+    }
+
+    if matches.is_present("create_secret") {
+        let max_uses = matches
+            .value_of("max_uses")
+            .map(|v| v.parse::<u32>().expect("--max-uses must be a number"))
+            .unwrap_or(0);
+        let ttl = matches
+            .value_of("ttl")
+            .map(|v| v.parse::<u64>().expect("--ttl must be a number of seconds"))
+            .unwrap_or(0);
+        secret::create_secret_cmd(
+            matches.values_of("create_secret").unwrap().collect(),
+            max_uses,
+            ttl,
+            &client_api,
+        )
+        .await;
+    }
+
+    if matches.is_present("secret_status") {
+        secret::secret_status_cmd(matches.value_of("secret_status").unwrap(), &client_api).await;
+    }
+
+    if matches.is_present("delete_secret") {
+        secret::delete_secret_cmd(matches.value_of("delete_secret").unwrap(), &client_api).await;
+    }
+
+    if matches.is_present("get_jwks") {
+        token::get_jwks_cmd(&client_api).await;
+    }
+
+    if matches.is_present("verify_token") {
+        token::verify_token_cmd(matches.value_of("verify_token").unwrap(), &client_api).await;
+    }
+
+    if matches.is_present("query_audit_log") {
+        let from = matches
+            .value_of("from")
+            .map(|v| v.parse::<u64>().expect("--from must be a unix time"))
+            .unwrap_or(0);
+        let to = matches
+            .value_of("to")
+            .map(|v| v.parse::<u64>().expect("--to must be a unix time"))
+            .unwrap_or(0);
+        audit::query_audit_log_cmd(from, to, matches.value_of("decision"), &client_api).await;
+    }
 }
@@ -3,10 +3,19 @@ use std::fs;
 use std::io::prelude::*;
 
 use crate::client_api::opa_service_client::OpaServiceClient;
+use crate::client_api::{DeleteOpaBundleRequest, DeleteOpaBundleResponse};
+use crate::client_api::{DeletePolicyBindingRequest, DeletePolicyBindingResponse};
+use crate::client_api::{ExportOpaBundleRequest, ExportOpaBundleResponse};
 use crate::client_api::{ExportOpaPolicyRequest, ExportOpaPolicyResponse};
 use crate::client_api::{ExportOpaReferenceRequest, ExportOpaReferenceResponse};
+use crate::client_api::{GetPolicyCacheMetricsRequest, GetPolicyCacheMetricsResponse};
+use crate::client_api::{ImportSgxReferenceRequest, ImportSgxReferenceResponse};
+use crate::client_api::{ListOpaBundlesRequest, ListOpaBundlesResponse};
+use crate::client_api::{ListPolicyBindingsRequest, ListPolicyBindingsResponse};
+use crate::client_api::{SetOpaBundleRequest, SetOpaBundleResponse};
 use crate::client_api::{SetOpaPolicyRequest, SetOpaPolicyResponse};
 use crate::client_api::{SetOpaReferenceRequest, SetOpaReferenceResponse};
+use crate::client_api::{SetPolicyBindingRequest, SetPolicyBindingResponse};
 use crate::client_api::{TestOpaRequest, TestOpaResponse};
 
 pub async fn set_policy_cmd(vals: Vec<&str>, addr: &str) {
@@ -31,6 +40,18 @@ pub async fn set_policy_cmd(vals: Vec<&str>, addr: &str) {
         "set_opa_policy status is: {:?}",
         String::from_utf8(response.status).unwrap()
     );
+
+    // Point at the problems in the uploaded file
+    let diagnostics: Vec<Value> = serde_json::from_slice(&response.diagnostics).unwrap_or_default();
+    for d in diagnostics {
+        error!(
+            "{}:{}:{}: {}",
+            vals[1],
+            d["line"],
+            d["column"],
+            d["message"].as_str().unwrap_or_default()
+        );
+    }
 }
 
 pub async fn export_policy_cmd(name: &str, path: String, addr: &str) {
@@ -284,3 +305,249 @@ pub async fn test_localreference_cmd(vals: Vec<&str>, addr: &str) {
         String::from_utf8(response.status).unwrap()
     );
 }
+
+pub async fn set_policy_binding_cmd(
+    vals: Vec<&str>,
+    tee: Option<&str>,
+    listener: Option<&str>,
+    signer: Option<&str>,
+    addr: &str,
+) {
+    let request = SetPolicyBindingRequest {
+        name: vals[0].as_bytes().to_vec(),
+        tee: tee.unwrap_or("").as_bytes().to_vec(),
+        listener: listener.unwrap_or("").as_bytes().to_vec(),
+        signer: signer.unwrap_or("").as_bytes().to_vec(),
+        policy: vals[1].as_bytes().to_vec(),
+        reference: vals[2].as_bytes().to_vec(),
+        bundle: Vec::new(),
+    };
+
+    send_policy_binding(request, addr).await;
+}
+
+pub async fn bind_opa_bundle_cmd(
+    vals: Vec<&str>,
+    tee: Option<&str>,
+    listener: Option<&str>,
+    signer: Option<&str>,
+    addr: &str,
+) {
+    let request = SetPolicyBindingRequest {
+        name: vals[0].as_bytes().to_vec(),
+        tee: tee.unwrap_or("").as_bytes().to_vec(),
+        listener: listener.unwrap_or("").as_bytes().to_vec(),
+        signer: signer.unwrap_or("").as_bytes().to_vec(),
+        policy: Vec::new(),
+        reference: Vec::new(),
+        bundle: vals[1].as_bytes().to_vec(),
+    };
+
+    send_policy_binding(request, addr).await;
+}
+
+async fn send_policy_binding(request: SetPolicyBindingRequest, addr: &str) {
+    let mut client = OpaServiceClient::connect(format!("http://{}", addr))
+        .await
+        .unwrap();
+
+    let response: SetPolicyBindingResponse = client
+        .set_policy_binding(request)
+        .await
+        .unwrap()
+        .into_inner();
+    info!(
+        "set_policy_binding status is: {:?}",
+        String::from_utf8(response.status).unwrap()
+    );
+}
+
+pub async fn list_policy_bindings_cmd(addr: &str) {
+    let request = ListPolicyBindingsRequest {};
+
+    let mut client = OpaServiceClient::connect(format!("http://{}", addr))
+        .await
+        .unwrap();
+
+    let response: ListPolicyBindingsResponse = client
+        .list_policy_bindings(request)
+        .await
+        .unwrap()
+        .into_inner();
+    info!(
+        "list_policy_bindings status is: {:?}",
+        String::from_utf8(response.status).unwrap()
+    );
+    info!(
+        "policy bindings:\n{}",
+        String::from_utf8(response.content).unwrap()
+    );
+}
+
+pub async fn delete_policy_binding_cmd(name: &str, addr: &str) {
+    let request = DeletePolicyBindingRequest {
+        name: name.as_bytes().to_vec(),
+    };
+
+    let mut client = OpaServiceClient::connect(format!("http://{}", addr))
+        .await
+        .unwrap();
+
+    let response: DeletePolicyBindingResponse = client
+        .delete_policy_binding(request)
+        .await
+        .unwrap()
+        .into_inner();
+    info!(
+        "delete_policy_binding status is: {:?}",
+        String::from_utf8(response.status).unwrap()
+    );
+}
+
+pub async fn import_sgx_reference_cmd(vals: Vec<&str>, replace: bool, addr: &str) {
+    let content =
+        fs::read(vals[1]).unwrap_or_else(|_| panic!("Failed to read the file named {}.", vals[1]));
+
+    let request = ImportSgxReferenceRequest {
+        name: vals[0].as_bytes().to_vec(),
+        content,
+        replace,
+    };
+
+    let mut client = OpaServiceClient::connect(format!("http://{}", addr))
+        .await
+        .unwrap();
+
+    let response: ImportSgxReferenceResponse = client
+        .import_sgx_reference(request)
+        .await
+        .unwrap()
+        .into_inner();
+    info!(
+        "import_sgx_reference status is: {:?}",
+        String::from_utf8(response.status).unwrap()
+    );
+    info!(
+        "reference: {} content is:\n{}",
+        vals[0],
+        String::from_utf8(response.content).unwrap()
+    );
+}
+
+pub async fn get_policy_cache_metrics_cmd(addr: &str) {
+    let request = GetPolicyCacheMetricsRequest {};
+
+    let mut client = OpaServiceClient::connect(format!("http://{}", addr))
+        .await
+        .unwrap();
+
+    let response: GetPolicyCacheMetricsResponse = client
+        .get_policy_cache_metrics(request)
+        .await
+        .unwrap()
+        .into_inner();
+    info!(
+        "get_policy_cache_metrics status is: {:?}",
+        String::from_utf8(response.status).unwrap()
+    );
+    info!(
+        "policy cache metrics: {}",
+        String::from_utf8(response.metrics).unwrap()
+    );
+}
+
+pub async fn set_bundle_cmd(vals: Vec<&str>, addr: &str) {
+    let content =
+        fs::read(vals[1]).unwrap_or_else(|_| panic!("Failed to read the file named {}.", vals[1]));
+
+    let request = SetOpaBundleRequest {
+        name: vals[0].as_bytes().to_vec(),
+        content,
+    };
+
+    let mut client = OpaServiceClient::connect(format!("http://{}", addr))
+        .await
+        .unwrap();
+
+    let response: SetOpaBundleResponse = client.set_opa_bundle(request).await.unwrap().into_inner();
+    info!(
+        "set_opa_bundle status is: {:?}",
+        String::from_utf8(response.status).unwrap()
+    );
+
+    // Point at the problems in the modules of the bundle
+    let diagnostics: Vec<Value> = serde_json::from_slice(&response.diagnostics).unwrap_or_default();
+    for d in diagnostics {
+        error!(
+            "{}:{}:{}: {}",
+            d["file"].as_str().unwrap_or_default(),
+            d["line"],
+            d["column"],
+            d["message"].as_str().unwrap_or_default()
+        );
+    }
+}
+
+pub async fn export_bundle_cmd(name: &str, path: String, addr: &str) {
+    let request = ExportOpaBundleRequest {
+        name: name.as_bytes().to_vec(),
+    };
+
+    let mut client = OpaServiceClient::connect(format!("http://{}", addr))
+        .await
+        .unwrap();
+
+    let response: ExportOpaBundleResponse = client
+        .export_opa_bundle(request)
+        .await
+        .unwrap()
+        .into_inner();
+    let status = String::from_utf8(response.status).unwrap();
+    info!("export_opa_bundle status is: {:?}", status);
+    if status != "OK" {
+        return;
+    }
+
+    let file = path + name + ".tar.gz";
+    fs::write(&file, response.content).expect("Faied to write the bundle into the file.");
+    info!("bundle: {} is exported to {}", name, file);
+}
+
+pub async fn list_bundles_cmd(addr: &str) {
+    let request = ListOpaBundlesRequest {};
+
+    let mut client = OpaServiceClient::connect(format!("http://{}", addr))
+        .await
+        .unwrap();
+
+    let response: ListOpaBundlesResponse =
+        client.list_opa_bundles(request).await.unwrap().into_inner();
+    info!(
+        "list_opa_bundles status is: {:?}",
+        String::from_utf8(response.status).unwrap()
+    );
+    info!(
+        "OPA bundles:\n{}",
+        String::from_utf8(response.content).unwrap()
+    );
+}
+
+pub async fn delete_bundle_cmd(name: &str, addr: &str) {
+    let request = DeleteOpaBundleRequest {
+        name: name.as_bytes().to_vec(),
+    };
+
+    let mut client = OpaServiceClient::connect(format!("http://{}", addr))
+        .await
+        .unwrap();
+
+    let response: DeleteOpaBundleResponse = client
+        .delete_opa_bundle(request)
+        .await
+        .unwrap()
+        .into_inner();
+    info!(
+        "delete_opa_bundle status is: {:?}",
+        String::from_utf8(response.status).unwrap()
+    );
+}
@@ -0,0 +1,63 @@
+use std::fs;
+
+use crate::client_api::resource_service_client::ResourceServiceClient;
+use crate::client_api::{DeleteResourceRequest, DeleteResourceResponse};
+use crate::client_api::{ListResourcesRequest, ListResourcesResponse};
+use crate::client_api::{SetResourceRequest, SetResourceResponse};
+
+pub async fn set_resource_cmd(vals: Vec<&str>, addr: &str) {
+    let content =
+        fs::read(vals[1]).unwrap_or_else(|_| panic!("Failed to read the file named {}.", vals[1]));
+
+    let request = SetResourceRequest {
+        name: vals[0].as_bytes().to_vec(),
+        content,
+    };
+
+    let mut client = ResourceServiceClient::connect(format!("http://{}", addr))
+        .await
+        .unwrap();
+
+    let response: SetResourceResponse = client.set_resource(request).await.unwrap().into_inner();
+    info!(
+        "set_resource status is: {:?}",
+        String::from_utf8(response.status).unwrap()
+    );
+}
+
+pub async fn list_resources_cmd(repository: Option<&str>, addr: &str) {
+    let request = ListResourcesRequest {
+        repository: repository.unwrap_or("").as_bytes().to_vec(),
+    };
+
+    let mut client = ResourceServiceClient::connect(format!("http://{}", addr))
+        .await
+        .unwrap();
+
+    let response: ListResourcesResponse =
+        client.list_resources(request).await.unwrap().into_inner();
+    info!(
+        "list_resources status is: {:?}",
+        String::from_utf8(response.status).unwrap()
+    );
+    for name in response.names {
+        info!("{}", String::from_utf8(name).unwrap());
+    }
+}
+
+pub async fn delete_resource_cmd(name: &str, addr: &str) {
+    let request = DeleteResourceRequest {
+        name: name.as_bytes().to_vec(),
+    };
+
+    let mut client = ResourceServiceClient::connect(format!("http://{}", addr))
+        .await
+        .unwrap();
+
+    let response: DeleteResourceResponse =
+        client.delete_resource(request).await.unwrap().into_inner();
+    info!(
+        "delete_resource status is: {:?}",
+        String::from_utf8(response.status).unwrap()
+    );
+}
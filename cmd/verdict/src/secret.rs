@@ -0,0 +1,68 @@
+use std::fs;
+
+use crate::client_api::secret_service_client::SecretServiceClient;
+use crate::client_api::{CreateSecretRequest, CreateSecretResponse};
+use crate::client_api::{DeleteSecretRequest, DeleteSecretResponse};
+use crate::client_api::{GetSecretStatusRequest, GetSecretStatusResponse};
+
+pub async fn create_secret_cmd(vals: Vec<&str>, max_uses: u32, ttl: u64, addr: &str) {
+    let content =
+        fs::read(vals[1]).unwrap_or_else(|_| panic!("Failed to read the file named {}.", vals[1]));
+
+    let request = CreateSecretRequest {
+        id: vals[0].as_bytes().to_vec(),
+        content,
+        max_uses,
+        ttl,
+    };
+
+    let mut client = SecretServiceClient::connect(format!("http://{}", addr))
+        .await
+        .unwrap();
+
+    let response: CreateSecretResponse = client.create_secret(request).await.unwrap().into_inner();
+    info!(
+        "create_secret status is: {:?}",
+        String::from_utf8(response.status).unwrap()
+    );
+}
+
+pub async fn secret_status_cmd(id: &str, addr: &str) {
+    let request = GetSecretStatusRequest {
+        id: id.as_bytes().to_vec(),
+    };
+
+    let mut client = SecretServiceClient::connect(format!("http://{}", addr))
+        .await
+        .unwrap();
+
+    let response: GetSecretStatusResponse = client
+        .get_secret_status(request)
+        .await
+        .unwrap()
+        .into_inner();
+    info!(
+        "get_secret_status status is: {:?}",
+        String::from_utf8(response.status).unwrap()
+    );
+    info!(
+        "uses: {}, max uses: {}, created at: {}, expires at: {}",
+        response.uses, response.max_uses, response.created_at, response.expires_at
+    );
+}
+
+pub async fn delete_secret_cmd(id: &str, addr: &str) {
+    let request = DeleteSecretRequest {
+        id: id.as_bytes().to_vec(),
+    };
+
+    let mut client = SecretServiceClient::connect(format!("http://{}", addr))
+        .await
+        .unwrap();
+
+    let response: DeleteSecretResponse = client.delete_secret(request).await.unwrap().into_inner();
+    info!(
+        "delete_secret status is: {:?}",
+        String::from_utf8(response.status).unwrap()
+    );
+}
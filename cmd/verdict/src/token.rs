@@ -0,0 +1,38 @@
+use std::fs;
+
+use crate::client_api::token_service_client::TokenServiceClient;
+use crate::client_api::{GetJwksRequest, GetJwksResponse};
+use crate::client_api::{VerifyTokenRequest, VerifyTokenResponse};
+
+pub async fn get_jwks_cmd(addr: &str) {
+    let request = GetJwksRequest {};
+
+    let mut client = TokenServiceClient::connect(format!("http://{}", addr))
+        .await
+        .unwrap();
+
+    let response: GetJwksResponse = client.get_jwks(request).await.unwrap().into_inner();
+    info!(
+        "get_jwks status is: {:?}",
+        String::from_utf8(response.status).unwrap()
+    );
+    info!("jwks: {}", String::from_utf8(response.jwks).unwrap());
+}
+
+pub async fn verify_token_cmd(path: &str, addr: &str) {
+    let token =
+        fs::read(path).unwrap_or_else(|_| panic!("Failed to read the file named {}.", path));
+
+    let request = VerifyTokenRequest { token };
+
+    let mut client = TokenServiceClient::connect(format!("http://{}", addr))
+        .await
+        .unwrap();
+
+    let response: VerifyTokenResponse = client.verify_token(request).await.unwrap().into_inner();
+    info!(
+        "verify_token status is: {:?}",
+        String::from_utf8(response.status).unwrap()
+    );
+    info!("claims: {}", String::from_utf8(response.claims).unwrap());
+}
@@ -0,0 +1,106 @@
+//! Authorize the key and resource requests of an attested peer.
+//!
+//! Each request is evaluated by the `authPolicy.rego` policy with the
+//! `authData` reference, the input contains the peer's claims and the
+//! requested kid or resource:
+//!
+//! ```json
+//! {
+//!     "command": "Get KEK",
+//!     "tee": "sgx",
+//!     "claims": {"mrEnclave": "xxx", "mrSigner": "xxx", ...},
+//!     "kid": "xxx"
+//! }
+//! ```
+use crate::attestation_agent::session::Session;
+use crate::policy_engine;
+use crate::resources;
+use serde_json::Value;
+
+pub enum Target<'a> {
+    Kid(&'a str),
+    Resource(&'a str),
+    Secret(&'a str),
+}
+
+pub fn input(session: &Session, command: &str, target: &Target) -> Value {
+    let mut input = serde_json::json!({
+        "command": command,
+        "tee": session.tee(),
+        "claims": session.claims(),
+    });
+
+    match target {
+        Target::Kid(kid) => input["kid"] = Value::String(kid.to_string()),
+        Target::Resource(name) => input["resource"] = Value::String(name.to_string()),
+        Target::Secret(id) => input["secret"] = Value::String(id.to_string()),
+    }
+
+    input
+}
+
+pub fn authorize(session: &Session, command: &str, target: Target) -> Result<(), String> {
+    let input = input(session, command, &target);
+
+    let decision = policy_engine::opa::opa_engine::make_decision(
+        resources::opa::OPA_POLICY_AUTH,
+        resources::opa::OPA_DATA_AUTH,
+        &input.to_string(),
+    )
+    .map_err(|e| format!("make_decision error: {}", e));
+
+    match decision {
+        Ok(decision) if decision.allow => Ok(()),
+        Ok(decision) => {
+            error!(
+                "unauthorized request: {}: {}",
+                input,
+                decision.deny_reasons.join("; ")
+            );
+            Err(match target {
+                Target::Kid(kid) => format!("kid: {} is not authorized for this peer", kid),
+                Target::Resource(name) => {
+                    format!("resource: {} is not authorized for this peer", name)
+                }
+                Target::Secret(id) => format!("secret: {} is not authorized for this peer", id),
+            })
+        }
+        Err(e) => {
+            error!("authorization failed: {}", e);
+            Err("authorization failed".to_string())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rats_tls::PeerEvidence;
+
+    #[test]
+    fn test_input() {
+        let session = Session::new(Some(PeerEvidence {
+            tee: "sgx".to_string(),
+            claims: serde_json::json!({"mrSigner": "c2lnbmVy"}),
+            policy: "sgxPolicy.rego".to_string(),
+            ..Default::default()
+        }));
+
+        let kek_input = input(&session, "Get KEK", &Target::Kid("kid1"));
+        assert_eq!(
+            kek_input,
+            serde_json::json!({
+                "command": "Get KEK",
+                "tee": "sgx",
+                "claims": {"mrSigner": "c2lnbmVy"},
+                "kid": "kid1"
+            })
+        );
+
+        let session = Session::new(None);
+        let resource_input = input(&session, "Get Resource", &Target::Resource("a/b/c"));
+        assert_eq!(resource_input["tee"], "none");
+        assert_eq!(resource_input["claims"], serde_json::json!({}));
+        assert_eq!(resource_input["resource"], "a/b/c");
+    }
+}
@@ -0,0 +1,347 @@
+//! Length-prefixed framing for the rats-tls request channel.
+//!
+//! A framed message is a sequence of frames. Every frame starts with an
+//! 8 bytes header followed by `length` bytes of payload:
+//!
+//! ```text
+//! +--------+--------+---------+-------+------------------+
+//! | 'V'    | 'D'    | version | flags | length (u32, BE) |
+//! +--------+--------+---------+-------+------------------+
+//! ```
+//!
+//! Large messages are split into frames of at most `MAX_FRAME_PAYLOAD`
+//! bytes. All frames but the last one carry `FRAME_FLAG_MORE`. The frames
+//! are reassembled in memory, not streamed to the handler, so a message
+//! can't exceed `MAX_MESSAGE_SIZE` in either direction. That bounds the
+//! resources served as well, their base64 content is sent in one message.
+//!
+//! Peers that don't speak the framed format send a bare JSON request. This
+//! is detected on the first read of a connection and the connection then
+//! stays in legacy mode: one `receive` of at most 4096 bytes is one request,
+//! a longer legacy request is cut off, and the response is sent without a
+//! header.
+
+use crate::rats_tls;
+
+pub const FRAME_MAGIC: [u8; 2] = *b"VD";
+pub const FRAME_VERSION: u8 = 1;
+pub const FRAME_FLAG_MORE: u8 = 0x1;
+pub const FRAME_HEADER_LEN: usize = 8;
+
+/// Max payload carried by a single frame, larger messages are chunked
+pub const MAX_FRAME_PAYLOAD: usize = 64 * 1024;
+/// Max size of a reassembled message, it's held in memory as a whole
+pub const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+const RECEIVE_BUFFER_SIZE: usize = 16 * 1024;
+const LEGACY_BUFFER_SIZE: usize = 4096;
+
+/// Byte channel the framing is layered on
+pub trait Channel {
+    fn receive(&self, buf: &mut [u8]) -> Result<usize, String>;
+    fn transmit(&self, buf: &[u8]) -> Result<usize, String>;
+}
+
+impl Channel for rats_tls::RatsTls {
+    fn receive(&self, buf: &mut [u8]) -> Result<usize, String> {
+        rats_tls::RatsTls::receive(self, buf)
+            .map_err(|e| format!("tls client disconnect, code: {:?}", e))
+    }
+
+    fn transmit(&self, buf: &[u8]) -> Result<usize, String> {
+        rats_tls::RatsTls::transmit(self, buf).map_err(|e| format!("tls transmit error: {:?}", e))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    Framed,
+    Legacy,
+}
+
+pub struct Connection<'a, C: Channel + ?Sized> {
+    channel: &'a C,
+    mode: Option<Mode>,
+    pending: Vec<u8>,
+}
+
+impl<'a, C: Channel + ?Sized> Connection<'a, C> {
+    pub fn new(channel: &'a C) -> Self {
+        Connection {
+            channel,
+            mode: None,
+            pending: Vec::new(),
+        }
+    }
+
+    /// The wire mode, known once the first message is read or written
+    pub fn mode(&self) -> Option<Mode> {
+        self.mode
+    }
+
+    /// Read one complete message from the peer
+    pub fn read_message(&mut self) -> Result<Vec<u8>, String> {
+        if self.mode.is_none() {
+            self.fill(1)?;
+            self.mode = if self.pending[0] == FRAME_MAGIC[0] {
+                Some(Mode::Framed)
+            } else {
+                info!("peer doesn't use framing, fall back to legacy mode");
+                Some(Mode::Legacy)
+            };
+        }
+
+        match self.mode {
+            Some(Mode::Legacy) => self.read_legacy(),
+            _ => self.read_framed(),
+        }
+    }
+
+    /// Send one complete message to the peer
+    pub fn write_message(&mut self, message: &[u8]) -> Result<(), String> {
+        if self.mode.is_none() {
+            self.mode = Some(Mode::Framed);
+        }
+
+        match self.mode {
+            Some(Mode::Legacy) => self.transmit_all(message),
+            _ if message.len() > MAX_MESSAGE_SIZE => Err(format!(
+                "message exceeds the limit of {} bytes",
+                MAX_MESSAGE_SIZE
+            )),
+            _ => {
+                let mut chunks = message.chunks(MAX_FRAME_PAYLOAD).peekable();
+                if chunks.peek().is_none() {
+                    return self.transmit_all(&encode_header(0, 0));
+                }
+                while let Some(chunk) = chunks.next() {
+                    let flags = if chunks.peek().is_some() {
+                        FRAME_FLAG_MORE
+                    } else {
+                        0
+                    };
+                    let mut frame = encode_header(flags, chunk.len()).to_vec();
+                    frame.extend_from_slice(chunk);
+                    self.transmit_all(&frame)?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn read_legacy(&mut self) -> Result<Vec<u8>, String> {
+        if self.pending.is_empty() {
+            let mut buffer = [0u8; LEGACY_BUFFER_SIZE];
+            let n = self.receive(&mut buffer)?;
+            self.pending.extend_from_slice(&buffer[..n]);
+        }
+
+        Ok(std::mem::take(&mut self.pending))
+    }
+
+    fn read_framed(&mut self) -> Result<Vec<u8>, String> {
+        let mut message = Vec::new();
+
+        loop {
+            self.fill(FRAME_HEADER_LEN)?;
+            let (flags, length) = decode_header(&self.pending[..FRAME_HEADER_LEN])?;
+            if message.len() + length > MAX_MESSAGE_SIZE {
+                return Err(format!(
+                    "message exceeds the limit of {} bytes",
+                    MAX_MESSAGE_SIZE
+                ));
+            }
+
+            self.fill(FRAME_HEADER_LEN + length)?;
+            message.extend_from_slice(&self.pending[FRAME_HEADER_LEN..FRAME_HEADER_LEN + length]);
+            self.pending.drain(..FRAME_HEADER_LEN + length);
+
+            if flags & FRAME_FLAG_MORE == 0 {
+                return Ok(message);
+            }
+        }
+    }
+
+    /// Receive until at least `len` bytes are pending
+    fn fill(&mut self, len: usize) -> Result<(), String> {
+        let mut buffer = vec![0u8; RECEIVE_BUFFER_SIZE];
+        while self.pending.len() < len {
+            let n = self.receive(&mut buffer)?;
+            self.pending.extend_from_slice(&buffer[..n]);
+        }
+        Ok(())
+    }
+
+    fn receive(&self, buffer: &mut [u8]) -> Result<usize, String> {
+        match self.channel.receive(buffer)? {
+            0 => Err("connection closed by peer".to_string()),
+            n => Ok(n),
+        }
+    }
+
+    fn transmit_all(&self, mut buf: &[u8]) -> Result<(), String> {
+        while !buf.is_empty() {
+            let n = self.channel.transmit(buf)?;
+            if n == 0 {
+                return Err("connection closed by peer".to_string());
+            }
+            buf = &buf[n..];
+        }
+        Ok(())
+    }
+}
+
+fn encode_header(flags: u8, length: usize) -> [u8; FRAME_HEADER_LEN] {
+    let mut header = [0u8; FRAME_HEADER_LEN];
+    header[..2].copy_from_slice(&FRAME_MAGIC);
+    header[2] = FRAME_VERSION;
+    header[3] = flags;
+    header[4..].copy_from_slice(&(length as u32).to_be_bytes());
+    header
+}
+
+fn decode_header(header: &[u8]) -> Result<(u8, usize), String> {
+    if header[..2] != FRAME_MAGIC {
+        return Err("invalid frame magic".to_string());
+    }
+    if header[2] != FRAME_VERSION {
+        return Err(format!("unsupported frame version {}", header[2]));
+    }
+
+    let mut length = [0u8; 4];
+    length.copy_from_slice(&header[4..FRAME_HEADER_LEN]);
+    let length = u32::from_be_bytes(length) as usize;
+    if length > MAX_FRAME_PAYLOAD {
+        return Err(format!("frame payload {} exceeds the limit", length));
+    }
+
+    Ok((header[3], length))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    // Hands out the scripted input at most `step` bytes per receive
+    struct MockChannel {
+        input: RefCell<Vec<u8>>,
+        output: RefCell<Vec<u8>>,
+        step: usize,
+    }
+
+    impl MockChannel {
+        fn new(input: Vec<u8>, step: usize) -> Self {
+            MockChannel {
+                input: RefCell::new(input),
+                output: RefCell::new(Vec::new()),
+                step,
+            }
+        }
+    }
+
+    impl Channel for MockChannel {
+        fn receive(&self, buf: &mut [u8]) -> Result<usize, String> {
+            let mut input = self.input.borrow_mut();
+            let n = input.len().min(buf.len()).min(self.step);
+            buf[..n].copy_from_slice(&input[..n]);
+            input.drain(..n);
+            Ok(n)
+        }
+
+        fn transmit(&self, buf: &[u8]) -> Result<usize, String> {
+            let n = buf.len().min(self.step);
+            self.output.borrow_mut().extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+    }
+
+    fn frame(message: &[u8]) -> Vec<u8> {
+        let sender = MockChannel::new(Vec::new(), usize::MAX);
+        Connection::new(&sender).write_message(message).unwrap();
+        let output = sender.output.borrow().clone();
+        output
+    }
+
+    #[test]
+    fn test_legacy_request() {
+        let request = br#"{"command": "version"}"#.to_vec();
+        let channel = MockChannel::new(request.clone(), usize::MAX);
+        let mut conn = Connection::new(&channel);
+
+        assert_eq!(conn.read_message().unwrap(), request);
+        assert_eq!(conn.mode(), Some(Mode::Legacy));
+
+        conn.write_message(b"response").unwrap();
+        assert_eq!(*channel.output.borrow(), b"response".to_vec());
+    }
+
+    #[test]
+    fn test_framed_large_message() {
+        let message: Vec<u8> = (0..3 * MAX_FRAME_PAYLOAD + 17)
+            .map(|i| (i % 251) as u8)
+            .collect();
+        let mut input = frame(&message);
+        input.extend(frame(b"second"));
+
+        // Deliver the frames in small pieces to exercise reassembly
+        let channel = MockChannel::new(input, 1000);
+        let mut conn = Connection::new(&channel);
+
+        assert_eq!(conn.read_message().unwrap(), message);
+        assert_eq!(conn.mode(), Some(Mode::Framed));
+        assert_eq!(conn.read_message().unwrap(), b"second".to_vec());
+
+        conn.write_message(&message).unwrap();
+        let output = channel.output.borrow().clone();
+        let reader = MockChannel::new(output, usize::MAX);
+        assert_eq!(Connection::new(&reader).read_message().unwrap(), message);
+    }
+
+    #[test]
+    fn test_message_limit() {
+        let message = vec![0u8; MAX_MESSAGE_SIZE + 1];
+        let channel = MockChannel::new(Vec::new(), usize::MAX);
+        assert!(Connection::new(&channel).write_message(&message).is_err());
+        assert!(channel.output.borrow().is_empty());
+
+        // The reader stops at the frame crossing the limit
+        let mut input = Vec::new();
+        for _ in 0..MAX_MESSAGE_SIZE / MAX_FRAME_PAYLOAD {
+            input.extend(encode_header(FRAME_FLAG_MORE, MAX_FRAME_PAYLOAD));
+            input.extend(vec![0u8; MAX_FRAME_PAYLOAD]);
+        }
+        input.extend(encode_header(0, 1));
+        input.push(0);
+        let channel = MockChannel::new(input, usize::MAX);
+        let e = Connection::new(&channel).read_message().unwrap_err();
+        assert!(e.contains("exceeds the limit"));
+    }
+
+    #[test]
+    fn test_empty_message() {
+        let channel = MockChannel::new(frame(b""), usize::MAX);
+        assert_eq!(Connection::new(&channel).read_message().unwrap(), b"");
+    }
+
+    #[test]
+    fn test_bad_header() {
+        let mut input = frame(b"payload");
+        input[2] = FRAME_VERSION + 1;
+        let channel = MockChannel::new(input, usize::MAX);
+        assert!(Connection::new(&channel).read_message().is_err());
+
+        let mut input = encode_header(0, MAX_FRAME_PAYLOAD + 1).to_vec();
+        input.extend(vec![0u8; 16]);
+        let channel = MockChannel::new(input, usize::MAX);
+        assert!(Connection::new(&channel).read_message().is_err());
+    }
+
+    #[test]
+    fn test_truncated_frame() {
+        let mut input = frame(b"payload");
+        input.truncate(FRAME_HEADER_LEN + 3);
+        let channel = MockChannel::new(input, usize::MAX);
+        assert!(Connection::new(&channel).read_message().is_err());
+    }
+}
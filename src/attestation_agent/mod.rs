@@ -1,2 +1,7 @@
+mod authorization;
+mod framing;
 mod protocol;
 pub mod rats_tls;
+mod session;
+pub mod transport;
+pub mod upstream;
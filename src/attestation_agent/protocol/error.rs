@@ -0,0 +1,57 @@
+use serde::Serialize;
+use serde_json::Value;
+
+/// Numeric error codes of protocol v2
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorCode {
+    /// The request isn't valid JSON or misses parameters
+    InvalidRequest = 1000,
+    UnknownCommand = 1001,
+    UnsupportedVersion = 1002,
+    /// The authorization policy rejected the request
+    Unauthorized = 2000,
+    KeyNotFound = 3000,
+    ResourceNotFound = 3001,
+    /// The secret doesn't exist, is used up or expired
+    SecretNotFound = 3002,
+    /// Encryption or decryption failed
+    CryptoError = 4000,
+    /// No cipher is registered for the algorithm, mode and key length
+    UnsupportedAlgorithm = 4001,
+    InternalError = 5000,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProtocolError {
+    pub code: ErrorCode,
+    pub message: String,
+}
+
+impl ProtocolError {
+    pub fn new(code: ErrorCode, message: String) -> Self {
+        ProtocolError { code, message }
+    }
+
+    /// The error envelope of protocol v2
+    pub fn envelope(&self) -> Value {
+        #[derive(Serialize)]
+        struct Error<'a> {
+            code: u32,
+            message: &'a str,
+        }
+
+        serde_json::json!({
+            "status": "Fail",
+            "error": Error {
+                code: self.code as u32,
+                message: &self.message,
+            }
+        })
+    }
+}
+
+impl std::fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
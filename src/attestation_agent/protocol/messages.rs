@@ -0,0 +1,142 @@
+use serde::{Deserialize, Serialize};
+
+pub const PROTOCOL_V1: &str = "v1";
+pub const PROTOCOL_V2: &str = "v2";
+pub const SUPPORTED_VERSIONS: [&str; 2] = [PROTOCOL_V1, PROTOCOL_V2];
+
+/// Optional protocol features announced by the `version` command
+pub const CAPABILITIES: [&str; 3] = ["framing", "resource-catalog", "batch"];
+
+/// Max number of commands carried by one `Batch` request
+pub const MAX_BATCH_SIZE: usize = 64;
+
+/// Requests of the attestation protocol, the same for v1 and v2. A v2
+/// request carries `"protocol": "v2"` next to the command.
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(tag = "command")]
+pub enum Request {
+    #[serde(rename = "version")]
+    Version,
+    #[serde(rename = "echo")]
+    Echo { data: String },
+    #[serde(rename = "Decrypt")]
+    Decrypt { blobs: Vec<DecryptBlob> },
+    #[serde(rename = "Encrypt")]
+    Encrypt {
+        kid: Option<String>,
+        algorithm: Option<String>,
+        mode: Option<String>,
+        blobs: Vec<EncryptBlob>,
+    },
+    #[serde(rename = "Get KEK")]
+    GetKek { kids: Vec<String> },
+    #[serde(rename = "Get Policy")]
+    GetPolicy,
+    #[serde(rename = "Get Sigstore Config")]
+    GetSigstoreConfig,
+    #[serde(rename = "Get GPG Keyring")]
+    GetGpgKeyring,
+    #[serde(rename = "Get Cosign Key")]
+    GetCosignKey,
+    #[serde(rename = "Get Credential")]
+    GetCredential,
+    #[serde(rename = "Get Resource")]
+    GetResource { name: String },
+    #[serde(rename = "Get Resource Info")]
+    GetResourceInfo { name: String },
+    #[serde(rename = "Get Attestation Token")]
+    GetAttestationToken { nonce: Option<String> },
+    #[serde(rename = "Consume Secret")]
+    ConsumeSecret { id: String },
+    /// Ordered list of requests, each one is handled on its own
+    #[serde(rename = "Batch")]
+    Batch { requests: Vec<serde_json::Value> },
+}
+
+pub const COMMANDS: [&str; 15] = [
+    "version",
+    "echo",
+    "Decrypt",
+    "Encrypt",
+    "Get KEK",
+    "Get Policy",
+    "Get Sigstore Config",
+    "Get GPG Keyring",
+    "Get Cosign Key",
+    "Get Credential",
+    "Get Resource",
+    "Get Resource Info",
+    "Get Attestation Token",
+    "Consume Secret",
+    "Batch",
+];
+
+/// Also the shape of the blobs returned by `Encrypt`
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+pub struct DecryptBlob {
+    pub kid: String,
+    pub encrypted_data: String,
+    pub algorithm: String,
+    /// Cipher mode, the algorithm's default mode if absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
+    pub key_length: u32,
+    pub iv: String,
+}
+
+#[derive(Debug, Deserialize, PartialEq)]
+pub struct EncryptBlob {
+    /// Base64 encoded plaintext
+    pub data: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_request_deserialize() {
+        let request: Request = serde_json::from_str(
+            r#"{"command": "Decrypt", "protocol": "v2", "blobs": [
+                {"kid": "k", "encrypted_data": "ZA==", "algorithm": "AES", "key_length": 256, "iv": "aXY="}
+            ]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            request,
+            Request::Decrypt {
+                blobs: vec![DecryptBlob {
+                    kid: "k".to_string(),
+                    encrypted_data: "ZA==".to_string(),
+                    algorithm: "AES".to_string(),
+                    mode: None,
+                    key_length: 256,
+                    iv: "aXY=".to_string(),
+                }]
+            }
+        );
+
+        let request: Request =
+            serde_json::from_str(r#"{"command": "Get Policy", "optional": {}}"#).unwrap();
+        assert_eq!(request, Request::GetPolicy);
+
+        assert!(serde_json::from_str::<Request>(r#"{"command": "Get KEK"}"#).is_err());
+        assert!(serde_json::from_str::<Request>(r#"{"command": "unknown"}"#).is_err());
+    }
+
+    #[test]
+    fn test_commands() {
+        for command in COMMANDS.iter() {
+            let request = serde_json::json!({
+                "command": command,
+                "data": "",
+                "blobs": [],
+                "kids": [],
+                "name": "",
+                "id": "",
+                "requests": [],
+            });
+            assert!(serde_json::from_value::<Request>(request).is_ok());
+        }
+    }
+}
@@ -0,0 +1,823 @@
+mod error;
+mod messages;
+
+use crate::attestation_agent::authorization::{self, Target};
+use crate::attestation_agent::rats_tls;
+use crate::attestation_agent::session::Session;
+use crate::attestation_agent::upstream;
+use crate::crypto::registry::{self, Cipher};
+use crate::resources;
+use error::{ErrorCode, ProtocolError};
+use messages::*;
+use rand::RngCore;
+use serde_json::Value;
+use uuid::Uuid;
+
+const KEY_LEN: usize = 32;
+
+/// Claims identifying the workload of an SGX, TDX or CSV peer
+const IDENTITY_CLAIMS: [&str; 4] = ["mrEnclave", "mrSigner", "mrTd", "measure"];
+
+fn invalid_request(message: &str) -> ProtocolError {
+    ProtocolError::new(ErrorCode::InvalidRequest, message.to_string())
+}
+
+fn authorize(session: &Session, command: &str, target: Target) -> Result<(), ProtocolError> {
+    authorization::authorize(session, command, target)
+        .map_err(|e| ProtocolError::new(ErrorCode::Unauthorized, e))
+}
+
+/// `local` or, when the item is `missing` locally and an upstream verdictd
+/// is configured, the item fetched from the upstream. Other local failures
+/// aren't forwarded.
+fn or_upstream<T>(
+    local: Result<T, String>,
+    missing: bool,
+    fetch: impl FnOnce() -> Result<T, String>,
+) -> Result<T, String> {
+    match local {
+        Err(e) if missing && upstream::enabled() => fetch().map_err(|upstream_error| {
+            warn!("upstream: {}", upstream_error);
+            format!("{}, upstream: {}", e, upstream_error)
+        }),
+        local => local,
+    }
+}
+
+fn get_key(kid: &str) -> Result<Vec<u8>, String> {
+    let local = resources::directory_key_manager::get_key(&kid.to_string());
+    let missing = matches!(&local, Err(e) if e.kind() == std::io::ErrorKind::NotFound);
+    or_upstream(local.map_err(|e| e.to_string()), missing, || {
+        upstream::get_key(kid)
+    })
+}
+
+fn handle_version() -> Result<Value, ProtocolError> {
+    Ok(serde_json::json!({
+        "versions": SUPPORTED_VERSIONS,
+        "capabilities": CAPABILITIES,
+        "commands": COMMANDS,
+    }))
+}
+
+fn cipher(
+    algorithm: &str,
+    mode: Option<&str>,
+    key_length: u32,
+) -> Result<&'static Cipher, ProtocolError> {
+    registry::lookup(algorithm, mode, key_length)
+        .map_err(|e| ProtocolError::new(ErrorCode::UnsupportedAlgorithm, e))
+}
+
+fn handle_decrypt(blobs: &[DecryptBlob], session: &Session) -> Result<Value, ProtocolError> {
+    let mut data = serde_json::Map::new();
+
+    for blob in blobs {
+        let cipher = cipher(&blob.algorithm, blob.mode.as_deref(), blob.key_length)?;
+
+        authorize(session, "Decrypt", Target::Kid(&blob.kid))?;
+
+        let key = get_key(&blob.kid).map_err(|_| {
+            ProtocolError::new(
+                ErrorCode::KeyNotFound,
+                format!("kid: {:?}'s key not found", blob.kid),
+            )
+        })?;
+        let iv = base64::decode(&blob.iv).map_err(|_| invalid_request("parameters error"))?;
+        let encrypted_data = base64::decode(&blob.encrypted_data)
+            .map_err(|_| invalid_request("parameters error"))?;
+
+        let decrypted_data = cipher
+            .decrypt(&encrypted_data, key.as_slice(), &iv)
+            .map_err(|e| ProtocolError::new(ErrorCode::CryptoError, e))?;
+        session.release("key", &blob.kid);
+        data.insert(
+            blob.encrypted_data.clone(),
+            Value::String(base64::encode(decrypted_data)),
+        );
+    }
+
+    Ok(Value::Object(data))
+}
+
+/// Binding matching the TEE and the identity claims of the session's peer
+fn creator_binding(session: &Session) -> Value {
+    let claims = session.claims();
+    let mut binding = serde_json::Map::new();
+    binding.insert("tee".to_string(), Value::String(session.tee().to_string()));
+    for name in IDENTITY_CLAIMS {
+        if let Some(value) = claims.get(name) {
+            binding.insert(name.to_string(), value.clone());
+        }
+    }
+    Value::Object(binding)
+}
+
+/// Store a fresh key as `kid` with `store`, bound to the session's peer so
+/// only the same workload recovers the data after re-attesting. The key is
+/// bound before it's stored and the binding dropped if storing fails.
+fn create_key(
+    kid: &str,
+    session: &Session,
+    store: impl FnOnce(&String, &[u8]) -> std::io::Result<()>,
+) -> Result<Vec<u8>, ProtocolError> {
+    resources::opa::bind_kid(kid, creator_binding(session)).map_err(|e| {
+        ProtocolError::new(ErrorCode::InternalError, format!("bind key failed: {}", e))
+    })?;
+    let mut key = vec![0u8; KEY_LEN];
+    rand::rngs::OsRng.fill_bytes(&mut key);
+    if let Err(e) = store(&kid.to_string(), &key) {
+        if let Err(e) = resources::opa::unbind_kid(kid) {
+            error!("unbind kid: {} failed: {}", kid, e);
+        }
+        return Err(ProtocolError::new(
+            ErrorCode::InternalError,
+            format!("create key failed: {}", e),
+        ));
+    }
+    Ok(key)
+}
+
+fn handle_encrypt(
+    kid: &Option<String>,
+    algorithm: &Option<String>,
+    mode: &Option<String>,
+    blobs: &[EncryptBlob],
+    session: &Session,
+) -> Result<Value, ProtocolError> {
+    let cipher = cipher(
+        algorithm.as_deref().unwrap_or("AES"),
+        mode.as_deref(),
+        (KEY_LEN * 8) as u32,
+    )?;
+    let plaintexts = blobs
+        .iter()
+        .map(|blob| base64::decode(&blob.data))
+        .collect::<Result<Vec<Vec<u8>>, _>>()
+        .map_err(|_| invalid_request("parameters error"))?;
+
+    let (kid, key) = match kid {
+        Some(kid) => {
+            authorize(session, "Encrypt", Target::Kid(kid))?;
+            let key = get_key(kid).map_err(|_| {
+                ProtocolError::new(
+                    ErrorCode::KeyNotFound,
+                    format!("kid: {:?}'s key not found", kid),
+                )
+            })?;
+            (kid.clone(), key)
+        }
+        None => {
+            // Seal under a fresh key, the peer keeps the kid to recover the data
+            let kid = Uuid::new_v4().to_string();
+            authorize(session, "Encrypt", Target::Kid(&kid))?;
+            let key = create_key(&kid, session, resources::directory_key_manager::set_key)?;
+            info!("created kid: {} for Encrypt", kid);
+            (kid, key)
+        }
+    };
+
+    let mut sealed = Vec::new();
+    for plaintext in plaintexts {
+        let mut iv = vec![0u8; cipher.iv_length];
+        rand::rngs::OsRng.fill_bytes(&mut iv);
+        let encrypted_data = cipher
+            .encrypt(&plaintext, &key, &iv)
+            .map_err(|e| ProtocolError::new(ErrorCode::CryptoError, e))?;
+        sealed.push(DecryptBlob {
+            kid: kid.clone(),
+            encrypted_data: base64::encode(encrypted_data),
+            algorithm: cipher.algorithm.to_string(),
+            mode: Some(cipher.mode.to_string()),
+            key_length: cipher.key_length,
+            iv: base64::encode(iv),
+        });
+    }
+
+    Ok(serde_json::json!({
+        "kid": kid,
+        "blobs": sealed,
+    }))
+}
+
+fn handle_getKek(kids: &[String], session: &Session) -> Result<Value, ProtocolError> {
+    let mut data = serde_json::Map::new();
+
+    for kid in kids {
+        authorize(session, "Get KEK", Target::Kid(kid))?;
+
+        let key = get_key(kid).map_err(|_| {
+            ProtocolError::new(
+                ErrorCode::KeyNotFound,
+                format!("kid: {}'s key not found", kid),
+            )
+        })?;
+        session.release("key", kid);
+        data.insert(kid.to_string(), Value::String(base64::encode(key)));
+    }
+
+    Ok(Value::Object(data))
+}
+
+fn export_resource(session: &Session, name: &str) -> Result<String, ProtocolError> {
+    let name = resource_alias(name);
+    authorize(session, "Get Resource", Target::Resource(name))?;
+    let local = resources::catalog::export_base64(name);
+    let missing = local.is_err() && resources::catalog::missing(name);
+    let content = or_upstream(local, missing, || {
+        upstream::get_resource(name).map(base64::encode)
+    })
+    .map_err(|e| ProtocolError::new(ErrorCode::ResourceNotFound, e))?;
+    session.release("resource", name);
+    Ok(content)
+}
+
+fn handle_get_file(session: &Session, name: &str, file: &str) -> Result<Value, ProtocolError> {
+    export_resource(session, name)
+        .map(Value::String)
+        .map_err(|e| match e.code {
+            ErrorCode::ResourceNotFound => ProtocolError::new(
+                e.code,
+                format!("Can't fetch {} file, error:{}", file, e.message),
+            ),
+            _ => e,
+        })
+}
+
+fn handle_get_resource(name: &str, session: &Session) -> Result<Value, ProtocolError> {
+    let content = export_resource(session, name).map_err(|e| match e.code {
+        ErrorCode::ResourceNotFound => ProtocolError::new(
+            e.code,
+            format!("Can't fetch resource {}, error:{}", name, e.message),
+        ),
+        _ => e,
+    })?;
+
+    Ok(serde_json::json!({
+        "name": name,
+        "content": content,
+    }))
+}
+
+fn resource_alias(name: &str) -> &str {
+    match name {
+        "GPG Keyring" => resources::catalog::GPG_KEYRING,
+        "Policy" => resources::catalog::POLICY,
+        "Sigstore Config" => resources::catalog::SIGSTORE,
+        "Cosign Key" => resources::catalog::COSIGN,
+        "Credential" => resources::catalog::CREDENTIAL,
+        _ => name,
+    }
+}
+
+fn handle_get_resource_info(name: &str, session: &Session) -> Result<Value, ProtocolError> {
+    let name = resource_alias(name);
+    authorize(session, "Get Resource Info", Target::Resource(name))?;
+
+    let local = resources::catalog::size_base64(name);
+    let missing = local.is_err() && resources::catalog::missing(name);
+    let size = or_upstream(local, missing, || upstream::get_resource_size(name))
+        .map_err(|e| ProtocolError::new(ErrorCode::ResourceNotFound, e))?;
+
+    Ok(serde_json::json!({ "base64size": size.to_string() }))
+}
+
+fn handle_get_attestation_token(
+    nonce: &Option<String>,
+    session: &Session,
+) -> Result<Value, ProtocolError> {
+    let evidence = session.evidence().ok_or_else(|| {
+        ProtocolError::new(
+            ErrorCode::Unauthorized,
+            "peer's evidence isn't verified".to_string(),
+        )
+    })?;
+
+    let token = resources::token::issue(
+        &evidence.tee,
+        &evidence.claims,
+        &evidence.policy,
+        nonce.as_deref(),
+    )
+    .map_err(|e| ProtocolError::new(ErrorCode::InternalError, e))?;
+
+    Ok(serde_json::json!({ "token": token }))
+}
+
+fn handle_consume_secret(id: &str, session: &Session) -> Result<Value, ProtocolError> {
+    authorize(session, "Consume Secret", Target::Secret(id))?;
+
+    let (content, secret) = resources::secret::consume(id)
+        .map_err(|e| ProtocolError::new(ErrorCode::SecretNotFound, e))?;
+    session.release("secret", id);
+
+    Ok(serde_json::json!({
+        "id": id,
+        "content": base64::encode(content),
+        "remaining_uses": secret.remaining_uses(),
+        "expires_at": secret.expires_at,
+    }))
+}
+
+/// Handle the sub-requests of a `Batch` in order. A failed sub-request
+/// doesn't stop the others, its error is reported in its own result.
+fn handle_batch(
+    requests: &[Value],
+    version: &str,
+    session: &Session,
+) -> Result<Value, ProtocolError> {
+    if requests.len() > MAX_BATCH_SIZE {
+        return Err(invalid_request(&format!(
+            "Batch carries more than {} requests",
+            MAX_BATCH_SIZE
+        )));
+    }
+
+    let results: Vec<Value> = requests
+        .iter()
+        .map(|request| {
+            let command = request["command"].as_str().unwrap_or("").to_string();
+            let result = parse(request.clone(), &command).and_then(|request| match request {
+                Request::Batch { .. } => Err(invalid_request("Batch can't be nested")),
+                request => dispatch(&request, version, session),
+            });
+
+            let mut result = match (version, result) {
+                (_, Ok(data)) => serde_json::json!({"status": "OK", "data": data}),
+                (PROTOCOL_V1, Err(e)) => serde_json::json!({"status": "Fail", "error": e.message}),
+                (_, Err(e)) => e.envelope(),
+            };
+            result["command"] = Value::String(command);
+            result
+        })
+        .collect();
+
+    Ok(serde_json::json!({ "results": results }))
+}
+
+fn dispatch(request: &Request, version: &str, session: &Session) -> Result<Value, ProtocolError> {
+    match request {
+        Request::Version => handle_version(),
+        Request::Echo { data } => Ok(Value::String(data.clone())),
+        Request::Decrypt { blobs } => handle_decrypt(blobs, session),
+        Request::Encrypt {
+            kid,
+            algorithm,
+            mode,
+            blobs,
+        } => handle_encrypt(kid, algorithm, mode, blobs, session),
+        Request::GetKek { kids } => handle_getKek(kids, session),
+        Request::GetPolicy => handle_get_file(session, resources::catalog::POLICY, "policy.json"),
+        Request::GetSigstoreConfig => {
+            handle_get_file(session, resources::catalog::SIGSTORE, "sigstore.yaml")
+        }
+        Request::GetGpgKeyring => {
+            handle_get_file(session, resources::catalog::GPG_KEYRING, "gpg keyring")
+        }
+        Request::GetCosignKey => handle_get_file(session, resources::catalog::COSIGN, "cosign key"),
+        Request::GetCredential => {
+            handle_get_file(session, resources::catalog::CREDENTIAL, "credential")
+        }
+        Request::GetResource { name } => handle_get_resource(name, session),
+        Request::GetResourceInfo { name } => handle_get_resource_info(name, session),
+        Request::GetAttestationToken { nonce } => handle_get_attestation_token(nonce, session),
+        Request::ConsumeSecret { id } => handle_consume_secret(id, session),
+        Request::Batch { requests } => handle_batch(requests, version, session),
+    }
+}
+
+fn parse(request: Value, command: &str) -> Result<Request, ProtocolError> {
+    if !COMMANDS.contains(&command) {
+        return Err(ProtocolError::new(
+            ErrorCode::UnknownCommand,
+            format!("unknown command {:?}", command),
+        ));
+    }
+
+    serde_json::from_value::<Request>(request).map_err(|e| {
+        ProtocolError::new(
+            ErrorCode::InvalidRequest,
+            format!("{} parameters error: {}", command, e),
+        )
+    })
+}
+
+fn action(command: &str) -> u8 {
+    match command {
+        "echo" => rats_tls::ACTION_DISCONNECT,
+        _ => rats_tls::ACTION_NONE,
+    }
+}
+
+fn error_message(e: String) -> Result<String, ()> {
+    let msg = serde_json::json!({
+        "status": "Fail",
+        "data": {},
+        "error": e
+    })
+    .to_string();
+    Ok(msg)
+}
+
+fn error_message2(e: String) -> Result<String, ()> {
+    let msg = serde_json::json!({
+        "status": "Fail",
+        "error": e
+    })
+    .to_string();
+    Ok(msg)
+}
+
+/// Encode the result of `command` in the response formats of protocol v1
+fn v1_response(command: &str, result: Result<Value, ProtocolError>) -> String {
+    match command {
+        "version" => serde_json::json!({
+            "status": "OK",
+            "version": PROTOCOL_V1,
+            "versions": SUPPORTED_VERSIONS,
+            "capabilities": CAPABILITIES,
+        })
+        .to_string(),
+        "echo" => match result {
+            Ok(Value::String(data)) => data,
+            Ok(data) => data.to_string(),
+            Err(_) => "Echo parameters error".to_string(),
+        },
+        "Get Policy"
+        | "Get Sigstore Config"
+        | "Get GPG Keyring"
+        | "Get Cosign Key"
+        | "Get Credential" => match result {
+            Ok(Value::String(content)) => content,
+            Ok(content) => content.to_string(),
+            Err(e) => base64::encode(error_message2(e.message).unwrap()),
+        },
+        "Get Resource Info" => match result {
+            Ok(data) => serde_json::json!({"status": "OK", "data": data}).to_string(),
+            Err(e) => error_message2(e.message).unwrap(),
+        },
+        _ => match result {
+            Ok(data) => serde_json::json!({"status": "OK", "data": data}).to_string(),
+            Err(e) => error_message(e.message).unwrap(),
+        },
+    }
+}
+
+/// Encode the result in the response envelope of protocol v2
+fn v2_response(result: Result<Value, ProtocolError>) -> String {
+    match result {
+        Ok(data) => serde_json::json!({"status": "OK", "data": data}).to_string(),
+        Err(e) => e.envelope().to_string(),
+    }
+}
+
+pub fn handle(request: &[u8], session: &Session) -> Result<(String, u8), String> {
+    let parsed_request: Value = match serde_json::from_slice(request) {
+        Ok(r) => r,
+        Err(_) => return Err("Parse request failed".to_string()),
+    };
+    info!("Request: {:?}", parsed_request);
+
+    let command = parsed_request["command"].as_str().unwrap_or("").to_string();
+    let version = match &parsed_request["protocol"] {
+        Value::Null => PROTOCOL_V1,
+        Value::String(version) if version == PROTOCOL_V1 => PROTOCOL_V1,
+        Value::String(version) if version == PROTOCOL_V2 => PROTOCOL_V2,
+        version => {
+            let e = ProtocolError::new(
+                ErrorCode::UnsupportedVersion,
+                format!("unsupported protocol version {}", version),
+            );
+            return Ok((v2_response(Err(e)), rats_tls::ACTION_NONE));
+        }
+    };
+
+    if version == PROTOCOL_V1 && !COMMANDS.contains(&command.as_str()) {
+        return Err("Command error".to_string());
+    }
+
+    let result =
+        parse(parsed_request, &command).and_then(|request| dispatch(&request, version, session));
+
+    let response = match version {
+        PROTOCOL_V1 => v1_response(&command, result),
+        _ => v2_response(result),
+    };
+
+    Ok((response, action(&command)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn attested() -> Session {
+        Session::new(Some(crate::rats_tls::PeerEvidence {
+            tee: "sgx".to_string(),
+            claims: serde_json::json!({"mrEnclave": "xxx"}),
+            policy: resources::opa::OPA_POLICY_SGX.to_string(),
+            ..Default::default()
+        }))
+    }
+
+    fn handle_str(request: &str) -> Result<(String, u8), String> {
+        handle(request.as_bytes(), &attested())
+    }
+
+    #[test]
+    fn test_version() {
+        let (response, action) = handle_str(r#"{"command": "version"}"#).unwrap();
+        let response: Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["status"], "OK");
+        assert_eq!(response["version"], "v1");
+        assert_eq!(response["versions"], serde_json::json!(["v1", "v2"]));
+        assert_eq!(action, rats_tls::ACTION_NONE);
+
+        let (response, _) = handle_str(r#"{"command": "version", "protocol": "v2"}"#).unwrap();
+        let response: Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["status"], "OK");
+        assert_eq!(
+            response["data"]["versions"],
+            serde_json::json!(["v1", "v2"])
+        );
+        assert!(response["data"]["capabilities"].is_array());
+    }
+
+    #[test]
+    fn test_echo() {
+        let (response, action) = handle_str(r#"{"command": "echo", "data": "hello"}"#).unwrap();
+        assert_eq!(response, "hello");
+        assert_eq!(action, rats_tls::ACTION_DISCONNECT);
+    }
+
+    #[test]
+    fn test_malformed_request() {
+        assert!(handle_str("not json").is_err());
+        assert!(handle_str(r#"{"command": "unknown"}"#).is_err());
+        assert!(handle_str(r#"{"no command": 1}"#).is_err());
+
+        let (response, _) = handle_str(r#"{"command": "Get KEK"}"#).unwrap();
+        let response: Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["status"], "Fail");
+        assert!(response["error"].is_string());
+
+        let (response, _) =
+            handle_str(r#"{"command": "Get KEK", "protocol": "v2", "kids": 1}"#).unwrap();
+        let response: Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["status"], "Fail");
+        assert_eq!(response["error"]["code"], ErrorCode::InvalidRequest as u32);
+    }
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        resources::opa::default().unwrap();
+
+        let request = serde_json::json!({
+            "command": "Encrypt",
+            "blobs": [{"data": base64::encode("sealed state")}],
+        });
+        let (response, _) = handle_str(&request.to_string()).unwrap();
+        let response: Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["status"], "OK");
+        let kid = response["data"]["kid"].as_str().unwrap().to_string();
+        let blob = response["data"]["blobs"][0].clone();
+        assert_eq!(blob["kid"], kid.as_str());
+        assert_eq!(blob["algorithm"], "AES");
+
+        // The returned blobs are accepted by Decrypt as they are
+        let request = serde_json::json!({
+            "command": "Decrypt",
+            "blobs": [blob.clone()],
+        });
+        let (response, _) = handle_str(&request.to_string()).unwrap();
+        let response: Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["status"], "OK");
+        assert_eq!(
+            response["data"][blob["encrypted_data"].as_str().unwrap()],
+            base64::encode("sealed state")
+        );
+
+        // The fresh kid is bound to its creator, other peers can't recover
+        let other = Session::new(Some(crate::rats_tls::PeerEvidence {
+            tee: "sgx".to_string(),
+            claims: serde_json::json!({"mrEnclave": "yyy"}),
+            ..Default::default()
+        }));
+        let request = serde_json::json!({"command": "Decrypt", "blobs": [blob.clone()]});
+        let (response, _) = handle(request.to_string().as_bytes(), &other).unwrap();
+        let response: Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["status"], "Fail");
+        let request = serde_json::json!({"command": "Get KEK", "kids": [kid]});
+        let (response, _) = handle(request.to_string().as_bytes(), &other).unwrap();
+        let response: Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["status"], "Fail");
+
+        // Encrypt again under the same kid
+        let request = serde_json::json!({
+            "command": "Encrypt",
+            "protocol": "v2",
+            "kid": kid,
+            "blobs": [{"data": base64::encode("more state")}],
+        });
+        let (response, _) = handle_str(&request.to_string()).unwrap();
+        let response: Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["data"]["blobs"][0]["kid"], kid.as_str());
+
+        // Other registered ciphers round trip as well
+        for (algorithm, mode) in [("AES", "CBC"), ("ChaCha20-Poly1305", "AEAD")] {
+            let request = serde_json::json!({
+                "command": "Encrypt",
+                "kid": kid,
+                "algorithm": algorithm,
+                "mode": mode,
+                "blobs": [{"data": base64::encode("state")}],
+            });
+            let (response, _) = handle_str(&request.to_string()).unwrap();
+            let response: Value = serde_json::from_str(&response).unwrap();
+            let blob = response["data"]["blobs"][0].clone();
+            assert_eq!(blob["mode"], mode);
+
+            let request = serde_json::json!({"command": "Decrypt", "blobs": [blob.clone()]});
+            let (response, _) = handle_str(&request.to_string()).unwrap();
+            let response: Value = serde_json::from_str(&response).unwrap();
+            assert_eq!(
+                response["data"][blob["encrypted_data"].as_str().unwrap()],
+                base64::encode("state")
+            );
+        }
+
+        // Unsupported combinations are rejected with a dedicated code
+        let request = serde_json::json!({
+            "command": "Decrypt",
+            "protocol": "v2",
+            "blobs": [{"kid": kid, "encrypted_data": "ZA==", "algorithm": "AES",
+                       "mode": "CTR", "key_length": 256, "iv": "aXY="}],
+        });
+        let (response, _) = handle_str(&request.to_string()).unwrap();
+        let response: Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(
+            response["error"]["code"],
+            ErrorCode::UnsupportedAlgorithm as u32
+        );
+
+        let _ = std::fs::remove_file(format!("/opt/verdictd/keys/{}", kid));
+    }
+
+    #[test]
+    fn test_create_key_failure() {
+        resources::opa::default().unwrap();
+        let kid = "verdictd-test-create-key-failure";
+        let session = attested();
+        resources::opa::unbind_kid(kid).unwrap();
+
+        // A key that can't be stored leaves no binding behind
+        let e = create_key(kid, &session, |_, _| {
+            Err(std::io::Error::other("disk full"))
+        })
+        .unwrap_err();
+        assert!(e.message.contains("disk full"));
+        let data: Value =
+            serde_json::from_str(&resources::opa::export(resources::opa::OPA_DATA_AUTH).unwrap())
+                .unwrap();
+        assert!(data["kids"].get(kid).is_none());
+
+        // Stored, the key is bound to its creator only
+        let key = create_key(kid, &session, |_, _| Ok(())).unwrap();
+        assert_eq!(key.len(), KEY_LEN);
+        let data: Value =
+            serde_json::from_str(&resources::opa::export(resources::opa::OPA_DATA_AUTH).unwrap())
+                .unwrap();
+        assert_eq!(
+            data["kids"][kid],
+            serde_json::json!([{"tee": "sgx", "mrEnclave": "xxx"}])
+        );
+        resources::opa::unbind_kid(kid).unwrap();
+    }
+
+    #[test]
+    fn test_attestation_token() {
+        resources::token::default().unwrap();
+
+        let request = r#"{"command": "Get Attestation Token"}"#;
+        let (response, _) = handle(request.as_bytes(), &Session::new(None)).unwrap();
+        let response: Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["status"], "Fail");
+
+        let session = attested();
+        let request = r#"{"command": "Get Attestation Token", "nonce": "n0"}"#;
+        let (response, _) = handle(request.as_bytes(), &session).unwrap();
+        let response: Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["status"], "OK");
+
+        let claims = resources::token::verify(response["data"]["token"].as_str().unwrap()).unwrap();
+        assert_eq!(claims["tee"], "sgx");
+        assert_eq!(claims["evidence"]["mrEnclave"], "xxx");
+        assert_eq!(claims["nonce"], "n0");
+    }
+
+    #[test]
+    fn test_consume_secret() {
+        resources::opa::default().unwrap();
+        resources::secret::default().unwrap();
+        let id = "verdictd-test-protocol";
+        let _ = resources::secret::delete(id);
+        resources::secret::create(id, b"join token", 1, 0).unwrap();
+
+        // Unattested peers don't get unbound secrets
+        let request = r#"{"command": "Consume Secret", "id": "verdictd-test-protocol"}"#;
+        let (response, _) = handle(request.as_bytes(), &Session::new(None)).unwrap();
+        let response: Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["status"], "Fail");
+
+        let session = attested();
+        let (response, _) = handle(request.as_bytes(), &session).unwrap();
+        let response: Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["status"], "OK");
+        assert_eq!(response["data"]["content"], base64::encode("join token"));
+        assert_eq!(response["data"]["remaining_uses"], 0);
+
+        let request = r#"{"command": "Consume Secret", "protocol": "v2",
+                          "id": "verdictd-test-protocol"}"#;
+        let (response, _) = handle(request.as_bytes(), &session).unwrap();
+        let response: Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["error"]["code"], ErrorCode::SecretNotFound as u32);
+
+        let released = session.released();
+        assert_eq!(released.len(), 1);
+        assert_eq!(released[0].kind, "secret");
+        assert_eq!(released[0].name, id);
+    }
+
+    #[test]
+    fn test_batch() {
+        let request = r#"{"command": "Batch", "requests": [
+            {"command": "echo", "data": "first"},
+            {"command": "Get KEK"},
+            {"command": "unknown"},
+            {"command": "Batch", "requests": []},
+            {"command": "Get Resource Info", "name": "verdictd-test/none/none"}
+        ]}"#;
+        let (response, action) = handle_str(request).unwrap();
+        let response: Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["status"], "OK");
+        assert_eq!(action, rats_tls::ACTION_NONE);
+
+        let results = response["data"]["results"].as_array().unwrap();
+        assert_eq!(results.len(), 5);
+        assert_eq!(results[0]["command"], "echo");
+        assert_eq!(results[0]["status"], "OK");
+        assert_eq!(results[0]["data"], "first");
+        for result in &results[1..] {
+            assert_eq!(result["status"], "Fail");
+            assert!(result["error"].is_string());
+        }
+
+        let request = r#"{"command": "Batch", "protocol": "v2", "requests": [
+            {"command": "unknown"},
+            {"command": "echo", "data": "second"}
+        ]}"#;
+        let (response, _) = handle_str(request).unwrap();
+        let response: Value = serde_json::from_str(&response).unwrap();
+        let results = response["data"]["results"].as_array().unwrap();
+        assert_eq!(
+            results[0]["error"]["code"],
+            ErrorCode::UnknownCommand as u32
+        );
+        assert_eq!(results[1]["data"], "second");
+    }
+
+    #[test]
+    fn test_resource_alias() {
+        resources::opa::default().unwrap();
+        resources::image::default().unwrap();
+        resources::catalog::default().unwrap();
+
+        let session = attested();
+        let request = r#"{"command": "Get Resource", "name": "Policy"}"#;
+        let (response, _) = handle(request.as_bytes(), &session).unwrap();
+        let response: Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["status"], "OK");
+        assert_eq!(response["data"]["name"], "Policy");
+        let content = response["data"]["content"].as_str().unwrap().to_string();
+
+        let request = r#"{"command": "Get Resource Info", "name": "Policy"}"#;
+        let (response, _) = handle(request.as_bytes(), &session).unwrap();
+        let response: Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["status"], "OK");
+        assert_eq!(response["data"]["base64size"], content.len().to_string());
+    }
+
+    #[test]
+    fn test_v2_errors() {
+        let (response, _) = handle_str(r#"{"command": "unknown", "protocol": "v2"}"#).unwrap();
+        let response: Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(response["error"]["code"], ErrorCode::UnknownCommand as u32);
+
+        let (response, _) = handle_str(r#"{"command": "version", "protocol": "v9"}"#).unwrap();
+        let response: Value = serde_json::from_str(&response).unwrap();
+        assert_eq!(
+            response["error"]["code"],
+            ErrorCode::UnsupportedVersion as u32
+        );
+    }
+}
@@ -1,8 +1,14 @@
-use crate::attestation_agent::protocol;
+use crate::attestation_agent::transport;
 use crate::rats_tls;
-use std::net::TcpListener;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::future::Future;
+use std::net::{Shutdown, SocketAddr};
 use std::os::unix::io::{AsRawFd, RawFd};
+use std::time::Duration;
 use std::{sync::Arc, u64};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Semaphore;
 
 pub const ACTION_NONE: u8 = 0;
 pub const ACTION_DISCONNECT: u8 = 1;
@@ -26,62 +32,334 @@ fn handle_client(
         return Err(format!("tls_negotiate() failed, sockfd = {}", sockfd));
     }
 
-    loop {
-        /* get client request */
-        let mut buffer = [0u8; 4096];
+    transport::serve(&tls).map_err(|e| format!("{} sockfd:{}", e, sockfd))
+}
+
+/// Limits of the attestation listener
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub listen: String,
+    /// Max number of concurrent sessions, further connections wait in the
+    /// listen backlog until a session ends
+    pub max_sessions: usize,
+    /// A session without any request for this long is closed
+    pub idle_timeout: Duration,
+    /// A session is closed once it lasts this long
+    pub session_timeout: Duration,
+    /// How long in-flight sessions may take to finish on shutdown
+    pub shutdown_timeout: Duration,
+}
 
-        let n = tls
-            .receive(&mut buffer)
-            .map_err(|e| format!("tls client disconnect, code: {:?}", e))?;
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            listen: "127.0.0.1:1234".to_string(),
+            max_sessions: 64,
+            idle_timeout: Duration::from_secs(300),
+            session_timeout: Duration::from_secs(3600),
+            shutdown_timeout: Duration::from_secs(30),
+        }
+    }
+}
 
-        let (response, action) = protocol::handle(&buffer[..n])
-            .map_err(|e| format!("handle request err: {} sockfd:{}", e, sockfd))?;
-        info!("response: {}", response);
+// Settings shared by all sessions of a listener
+struct Options {
+    config: ServerConfig,
+    tls_type: Option<String>,
+    crypto: Option<String>,
+    attester: Option<String>,
+    verifier: Option<String>,
+    mutual: bool,
+    /// Sockets of the running sessions, shut down when they don't drain
+    sockets: Mutex<HashMap<SocketAddr, std::net::TcpStream>>,
+}
+
+async fn session(socket: TcpStream, addr: SocketAddr, options: Arc<Options>) {
+    let config = &options.config;
+    // rats-tls works on blocking sockets, the idle timeout makes a pending
+    // receive fail once the peer stays silent for too long.
+    let socket = socket
+        .into_std()
+        .and_then(|socket| socket.set_nonblocking(false).map(|_| socket))
+        .and_then(|socket| {
+            socket
+                .set_read_timeout(Some(config.idle_timeout))
+                .map(|_| socket)
+        })
+        .and_then(|socket| {
+            socket
+                .set_write_timeout(Some(config.idle_timeout))
+                .map(|_| socket)
+        });
+    let socket = match socket {
+        Ok(socket) => socket,
+        Err(e) => {
+            error!("set up socket of {} failed: {}", addr, e);
+            return;
+        }
+    };
+    let watchdog = match socket.try_clone().and_then(|watchdog| {
+        let registered = watchdog.try_clone()?;
+        options.sockets.lock().insert(addr, registered);
+        Ok(watchdog)
+    }) {
+        Ok(watchdog) => watchdog,
+        Err(e) => {
+            error!("set up socket of {} failed: {}", addr, e);
+            return;
+        }
+    };
 
-        tls.transmit(response.as_bytes())
-            .map_err(|e| format!("tls transmit error: {:?}", e))?;
+    info!("session for {} {:?}", socket.as_raw_fd(), addr);
+    let session_timeout = config.session_timeout;
+    let session_options = options.clone();
+    let mut handle = tokio::task::spawn_blocking(move || {
+        let options = session_options;
+        rats_tls::RatsTls::set_connection(&options.config.listen, &addr.to_string());
+        handle_client(
+            socket.as_raw_fd(),
+            &options.tls_type,
+            &options.crypto,
+            &options.attester,
+            &options.verifier,
+            options.mutual,
+            0,
+        )
+    });
 
-        if action == ACTION_DISCONNECT {
-            return Ok(());
+    let res = match tokio::time::timeout(session_timeout, &mut handle).await {
+        Ok(res) => res,
+        Err(_) => {
+            warn!("session of {:?} exceeds its lifetime, close it", addr);
+            let _ = watchdog.shutdown(Shutdown::Both);
+            handle.await
         }
+    };
+    options.sockets.lock().remove(&addr);
+
+    match res {
+        Ok(Ok(_)) => {}
+        Ok(Err(e)) => error!("{}", e),
+        Err(e) => error!("session of {:?} aborted: {}", addr, e),
     }
 }
 
-pub fn server(
-    sockaddr: &str,
+pub async fn server(
+    config: ServerConfig,
     tls_type: String,
     crypto: String,
     attester: String,
     verifier: String,
     mutual: bool,
-) {
-    let tls_type = Arc::new(Some(tls_type));
-    let crypto = Arc::new(Some(crypto));
-    let attester = Arc::new(Some(attester));
-    let verifier = Arc::new(Some(verifier));
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), String> {
+    let options = Arc::new(Options {
+        config,
+        tls_type: Some(tls_type),
+        crypto: Some(crypto),
+        attester: Some(attester),
+        verifier: Some(verifier),
+        mutual,
+        sockets: Mutex::new(HashMap::new()),
+    });
 
     /* tcp */
-    let listener = TcpListener::bind(sockaddr).unwrap();
+    let listener = TcpListener::bind(&options.config.listen)
+        .await
+        .map_err(|e| format!("bind {} failed: {}", options.config.listen, e))?;
+    serve(listener, options, shutdown).await;
+    Ok(())
+}
+
+/// Accept sessions on `listener` until `shutdown`, then drain them
+async fn serve(listener: TcpListener, options: Arc<Options>, shutdown: impl Future<Output = ()>) {
+    let config = &options.config;
+    let max_sessions = config.max_sessions.max(1);
+    let sessions = Arc::new(Semaphore::new(max_sessions));
+    tokio::pin!(shutdown);
+
     loop {
-        let (socket, addr) = listener.accept().unwrap();
-        info!("thread for {} {:?}", socket.as_raw_fd(), addr);
-        let tls_type = tls_type.clone();
-        let crypto = crypto.clone();
-        let attester = attester.clone();
-        let verifier = verifier.clone();
-        std::thread::spawn(move || {
-            match handle_client(
-                socket.as_raw_fd(),
-                &tls_type,
-                &crypto,
-                &attester,
-                &verifier,
-                mutual,
-                0,
-            ) {
-                Ok(_) => {}
-                Err(e) => error!("{}", e),
-            }
+        // Don't accept more connections than sessions can be served
+        let permit = tokio::select! {
+            permit = sessions.clone().acquire_owned() => permit.unwrap(),
+            _ = &mut shutdown => break,
+        };
+
+        let (socket, addr) = tokio::select! {
+            res = listener.accept() => match res {
+                Ok(res) => res,
+                Err(e) => {
+                    error!("accept failed: {}", e);
+                    // Back off, e.g. when running out of file descriptors
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                    continue;
+                }
+            },
+            _ = &mut shutdown => break,
+        };
+
+        let options = options.clone();
+        tokio::spawn(async move {
+            session(socket, addr, options).await;
+            drop(permit);
+        });
+    }
+
+    drop(listener);
+    let active = max_sessions - sessions.available_permits();
+    info!("Attestation listener stopped, drain {} sessions", active);
+    let drained = tokio::time::timeout(
+        config.shutdown_timeout,
+        sessions.acquire_many(max_sessions as u32),
+    )
+    .await;
+    if drained.is_err() {
+        warn!(
+            "{} sessions are still running after {:?}, close them",
+            max_sessions - sessions.available_permits(),
+            config.shutdown_timeout
+        );
+        // A closed socket fails the pending receive, the sessions end
+        // without waiting for their idle timeout
+        for socket in options.sockets.lock().values() {
+            let _ = socket.shutdown(Shutdown::Both);
+        }
+        let _ = sessions.acquire_many(max_sessions as u32).await;
+    }
+    info!("All sessions are drained");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(listen: &str) -> ServerConfig {
+        ServerConfig {
+            listen: listen.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_server_shutdown() {
+        let res = server(
+            config("127.0.0.1:0"),
+            "".to_string(),
+            "".to_string(),
+            "".to_string(),
+            "".to_string(),
+            false,
+            async {},
+        )
+        .await;
+        assert!(res.is_ok());
+    }
+
+    #[cfg(feature = "mock-rats-tls")]
+    #[tokio::test]
+    async fn test_server_shutdown_closes_sessions() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let options = Arc::new(Options {
+            config: ServerConfig {
+                idle_timeout: Duration::from_secs(60),
+                shutdown_timeout: Duration::from_millis(100),
+                ..Default::default()
+            },
+            tls_type: None,
+            crypto: None,
+            attester: None,
+            verifier: None,
+            mutual: false,
+            sockets: Mutex::new(HashMap::new()),
+        });
+        let (connected, shutdown) = tokio::sync::oneshot::channel::<()>();
+        let client = std::thread::spawn(move || {
+            // A silent peer keeps its session waiting for a request
+            let client = std::net::TcpStream::connect(addr).ok();
+            std::thread::sleep(Duration::from_millis(200));
+            let _ = connected.send(());
+            client
         });
+
+        let started = std::time::Instant::now();
+        serve(listener, options, async {
+            let _ = shutdown.await;
+        })
+        .await;
+        assert!(started.elapsed() < Duration::from_secs(10));
+
+        // The session's socket is closed instead of waiting for the peer
+        let mut client = client.join().unwrap().unwrap();
+        client
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let mut buf = [0u8; 1];
+        assert_eq!(std::io::Read::read(&mut client, &mut buf).unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_server_bind_error() {
+        let res = server(
+            config("not an address"),
+            "".to_string(),
+            "".to_string(),
+            "".to_string(),
+            "".to_string(),
+            false,
+            std::future::pending(),
+        )
+        .await;
+        assert!(res.is_err());
+    }
+
+    #[cfg(feature = "mock-rats-tls")]
+    #[test]
+    fn test_handle_client() {
+        use crate::attestation_agent::framing;
+        use crate::rats_tls::{fixtures, mock};
+        use crate::resources;
+        use std::os::unix::net::UnixStream;
+
+        resources::opa::default().unwrap();
+        resources::token::default().unwrap();
+
+        let (server, client) = UnixStream::pair().unwrap();
+        mock::script_evidence(server.as_raw_fd(), fixtures::sgx(0x4, ""));
+        let server = std::thread::spawn(move || {
+            handle_client(server.as_raw_fd(), &None, &None, &None, &None, true, 0)
+        });
+
+        let tls = rats_tls::RatsTls::new(false, 0, &None, &None, &None, &None, false).unwrap();
+        assert!(tls.negotiate(client.as_raw_fd()).is_ok());
+        let mut conn = framing::Connection::new(&tls);
+
+        conn.write_message(br#"{"command": "Get Attestation Token"}"#)
+            .unwrap();
+        let response: serde_json::Value =
+            serde_json::from_slice(&conn.read_message().unwrap()).unwrap();
+        assert_eq!(response["status"], "OK");
+        let claims = resources::token::verify(response["data"]["token"].as_str().unwrap()).unwrap();
+        assert_eq!(claims["tee"], "sgx");
+        assert_eq!(claims["evidence"]["mrEnclave"], base64::encode([1u8; 32]));
+
+        // echo closes the conversation
+        conn.write_message(br#"{"command": "echo", "data": "bye"}"#)
+            .unwrap();
+        assert_eq!(conn.read_message().unwrap(), b"bye".to_vec());
+        assert!(server.join().unwrap().is_ok());
+    }
+
+    #[cfg(feature = "mock-rats-tls")]
+    #[test]
+    fn test_handle_client_rejected() {
+        use crate::rats_tls::{fixtures, mock};
+        use std::os::unix::net::UnixStream;
+
+        let (server, _client) = UnixStream::pair().unwrap();
+        let mut evidence = fixtures::sgx(0x4, "");
+        evidence.evidence.type_ = 0;
+        mock::script_evidence(server.as_raw_fd(), evidence);
+        assert!(handle_client(server.as_raw_fd(), &None, &None, &None, &None, true, 0).is_err());
     }
 }
@@ -0,0 +1,53 @@
+use crate::rats_tls::PeerEvidence;
+use crate::resources::audit::Release;
+use serde_json::Value;
+use std::cell::RefCell;
+
+/// State of one attestation agent connection
+pub struct Session {
+    evidence: Option<PeerEvidence>,
+    /// Keys, resources and secrets released to the peer
+    released: RefCell<Vec<Release>>,
+}
+
+impl Session {
+    pub fn new(evidence: Option<PeerEvidence>) -> Self {
+        Session {
+            evidence,
+            released: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Record that `name` of `kind` was released to the peer
+    pub fn release(&self, kind: &str, name: &str) {
+        self.released.borrow_mut().push(Release {
+            kind: kind.to_string(),
+            name: name.to_string(),
+        });
+    }
+
+    pub fn released(&self) -> Vec<Release> {
+        self.released.borrow().clone()
+    }
+
+    /// TEE type of the peer, "none" if its evidence wasn't verified
+    pub fn tee(&self) -> &str {
+        match &self.evidence {
+            Some(evidence) => &evidence.tee,
+            None => "none",
+        }
+    }
+
+    /// Verified evidence of the peer, if any
+    pub fn evidence(&self) -> Option<&PeerEvidence> {
+        self.evidence.as_ref()
+    }
+
+    /// Verified evidence claims of the peer
+    pub fn claims(&self) -> Value {
+        match &self.evidence {
+            Some(evidence) => evidence.claims.clone(),
+            None => Value::Object(serde_json::Map::new()),
+        }
+    }
+}
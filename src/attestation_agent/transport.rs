@@ -0,0 +1,257 @@
+//! Transports the attestation protocol runs over.
+//!
+//! A transport is a byte channel whose peer may have been attested while
+//! the transport was established. `serve` runs the protocol conversation
+//! over any transport, so it is the same for rats-tls connections and for
+//! the insecure Unix socket transport used in development and CI.
+//!
+//! The insecure transport doesn't attest its peer at all, it injects
+//! synthetic evidence instead. Its listener only exists when verdictd is
+//! built with the `insecure-transport` feature.
+use crate::attestation_agent::framing::{self, Channel};
+use crate::attestation_agent::protocol;
+use crate::attestation_agent::rats_tls::ACTION_DISCONNECT;
+use crate::attestation_agent::session::Session;
+use crate::rats_tls::{PeerEvidence, RatsTls};
+use crate::resources::audit;
+
+pub trait Transport: Channel {
+    /// Evidence of the peer verified while establishing the transport,
+    /// called once before the first request
+    fn peer_evidence(&self) -> Option<PeerEvidence>;
+
+    /// Address of the peer recorded in the audit log
+    fn peer_address(&self) -> String;
+}
+
+impl Transport for RatsTls {
+    fn peer_evidence(&self) -> Option<PeerEvidence> {
+        RatsTls::take_verified_evidence()
+    }
+
+    fn peer_address(&self) -> String {
+        RatsTls::peer_address()
+    }
+}
+
+/// Serve the requests of the peer until it disconnects, then record the
+/// session in the audit log
+pub fn serve<T: Transport + ?Sized>(transport: &T) -> Result<(), String> {
+    let session = Session::new(transport.peer_evidence());
+
+    let res = converse(transport, &session);
+
+    let peer = transport.peer_address();
+    let mut record = match session.evidence() {
+        Some(evidence) => evidence.audit_record(&peer, audit::DECISION_ALLOW),
+        None => audit::Record {
+            timestamp: audit::now(),
+            peer,
+            tee: session.tee().to_string(),
+            decision: audit::DECISION_NONE.to_string(),
+            ..Default::default()
+        },
+    };
+    record.released = session.released();
+    if let Err(e) = audit::append(record) {
+        error!("audit the session failed: {}", e);
+    }
+
+    res
+}
+
+fn converse<T: Transport + ?Sized>(transport: &T, session: &Session) -> Result<(), String> {
+    let mut conn = framing::Connection::new(transport);
+    loop {
+        /* get client request */
+        let request = conn.read_message()?;
+
+        let (response, action) = protocol::handle(&request, session)
+            .map_err(|e| format!("handle request err: {}", e))?;
+        info!("response: {}", response);
+
+        conn.write_message(response.as_bytes())?;
+
+        if action == ACTION_DISCONNECT {
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(any(test, feature = "insecure-transport"))]
+pub mod insecure {
+    use super::*;
+    use serde::Deserialize;
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixStream;
+
+    /// Synthetic evidence injected by the insecure transport, e.g.
+    /// `{"tee": "sgx", "claims": {"mrEnclave": "xxx"}}`
+    #[derive(Debug, Clone, Deserialize)]
+    pub struct SyntheticEvidence {
+        pub tee: String,
+        #[serde(default)]
+        pub claims: serde_json::Value,
+    }
+
+    impl SyntheticEvidence {
+        pub fn load(path: &str) -> Result<SyntheticEvidence, String> {
+            std::fs::read(path)
+                .map_err(|e| format!("read {} failed: {}", path, e))
+                .and_then(|content| {
+                    serde_json::from_slice(&content)
+                        .map_err(|e| format!("parse {} failed: {}", path, e))
+                })
+        }
+    }
+
+    /// Unix socket transport without any attestation
+    pub struct UnixTransport {
+        stream: UnixStream,
+        evidence: Option<SyntheticEvidence>,
+    }
+
+    impl UnixTransport {
+        pub fn new(stream: UnixStream, evidence: Option<SyntheticEvidence>) -> Self {
+            UnixTransport { stream, evidence }
+        }
+    }
+
+    impl Channel for UnixTransport {
+        fn receive(&self, buf: &mut [u8]) -> Result<usize, String> {
+            (&self.stream)
+                .read(buf)
+                .map_err(|e| format!("unix socket receive error: {}", e))
+        }
+
+        fn transmit(&self, buf: &[u8]) -> Result<usize, String> {
+            (&self.stream)
+                .write(buf)
+                .map_err(|e| format!("unix socket transmit error: {}", e))
+        }
+    }
+
+    impl Transport for UnixTransport {
+        fn peer_evidence(&self) -> Option<PeerEvidence> {
+            self.evidence.as_ref().map(|evidence| PeerEvidence {
+                tee: evidence.tee.clone(),
+                claims: evidence.claims.clone(),
+                policy: "insecure-transport".to_string(),
+                verified_at: audit::now(),
+                ..Default::default()
+            })
+        }
+
+        fn peer_address(&self) -> String {
+            "unix".to_string()
+        }
+    }
+
+    /// Serve the attestation protocol on the Unix socket `path`, every peer
+    /// gets `evidence` without being attested
+    #[cfg(feature = "insecure-transport")]
+    pub async fn server(
+        path: String,
+        evidence: Option<SyntheticEvidence>,
+        shutdown: impl std::future::Future<Output = ()>,
+    ) -> Result<(), String> {
+        warn!(
+            "INSECURE: serving the attestation protocol on {} without attestation, \
+             for development only",
+            path
+        );
+
+        let _ = std::fs::remove_file(&path);
+        let listener = tokio::net::UnixListener::bind(&path)
+            .map_err(|e| format!("bind {} failed: {}", path, e))?;
+        tokio::pin!(shutdown);
+
+        loop {
+            let stream = tokio::select! {
+                res = listener.accept() => match res {
+                    Ok((stream, _)) => stream,
+                    Err(e) => {
+                        error!("accept failed: {}", e);
+                        continue;
+                    }
+                },
+                _ = &mut shutdown => break,
+            };
+
+            let evidence = evidence.clone();
+            tokio::task::spawn_blocking(move || {
+                let res = stream
+                    .into_std()
+                    .and_then(|stream| stream.set_nonblocking(false).map(|_| stream))
+                    .map_err(|e| format!("set up unix socket failed: {}", e))
+                    .and_then(|stream| serve(&UnixTransport::new(stream, evidence)));
+                if let Err(e) = res {
+                    error!("{}", e);
+                }
+            });
+        }
+
+        let _ = std::fs::remove_file(&path);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::insecure::*;
+    use super::*;
+    use serde_json::Value;
+    use std::os::unix::net::UnixStream;
+
+    fn request(conn: &mut framing::Connection<UnixTransport>, request: Value) -> Value {
+        conn.write_message(request.to_string().as_bytes()).unwrap();
+        serde_json::from_slice(&conn.read_message().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_conversation() {
+        let (server, client) = UnixStream::pair().unwrap();
+        let evidence = SyntheticEvidence {
+            tee: "sgx".to_string(),
+            claims: serde_json::json!({"mrEnclave": "xxx"}),
+        };
+        let server = std::thread::spawn(move || serve(&UnixTransport::new(server, Some(evidence))));
+
+        let client = UnixTransport::new(client, None);
+        let mut conn = framing::Connection::new(&client);
+
+        let response = request(&mut conn, serde_json::json!({"command": "version"}));
+        assert_eq!(response["status"], "OK");
+
+        let response = request(
+            &mut conn,
+            serde_json::json!({"command": "Batch", "protocol": "v2", "requests": [
+                {"command": "version"},
+                {"command": "unknown"}
+            ]}),
+        );
+        assert_eq!(response["data"]["results"][0]["status"], "OK");
+        assert_eq!(response["data"]["results"][1]["status"], "Fail");
+
+        // echo closes the conversation
+        conn.write_message(br#"{"command": "echo", "data": "bye"}"#)
+            .unwrap();
+        assert_eq!(conn.read_message().unwrap(), b"bye".to_vec());
+        assert!(server.join().unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_legacy_client() {
+        let (server, client) = UnixStream::pair().unwrap();
+        let server = std::thread::spawn(move || serve(&UnixTransport::new(server, None)));
+
+        let client = UnixTransport::new(client, None);
+        client
+            .transmit(br#"{"command": "echo", "data": "legacy"}"#)
+            .unwrap();
+        let mut buf = [0u8; 64];
+        let n = client.receive(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"legacy");
+        assert!(server.join().unwrap().is_ok());
+    }
+}
@@ -0,0 +1,431 @@
+//! Proxy to an upstream verdictd.
+//!
+//! An edge verdictd started with `--upstream <sockaddr>` forwards the keys
+//! and resources it doesn't hold itself to the upstream verdictd. Every
+//! fetch opens a rats-tls client session in mutual mode, so the upstream
+//! attests the edge instance before it releases anything, and the edge
+//! verifies the upstream with its own attestation policy.
+//!
+//! Only missing items are forwarded. Other local failures, e.g. an I/O error
+//! or an invalid name, are returned as they are.
+//!
+//! Fetched items are kept in memory for the cache TTL if the cache policy
+//! covers their kind. The size of a resource is asked to the upstream with
+//! `Get Resource Info` unless the resource itself is cached. Secrets are
+//! never proxied: consuming a secret must happen on the verdictd holding it.
+use crate::attestation_agent::framing::{self, Channel};
+use crate::rats_tls::RatsTls;
+use lazy_static::lazy_static;
+use parking_lot::{Mutex, RwLock};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::net::{TcpStream, ToSocketAddrs};
+use std::os::unix::io::AsRawFd;
+use std::time::{Duration, Instant};
+
+/// Kinds of upstream items kept in the cache
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum CachePolicy {
+    #[default]
+    None,
+    Keys,
+    Resources,
+    All,
+}
+
+impl CachePolicy {
+    pub fn parse(policy: &str) -> Result<CachePolicy, String> {
+        match policy {
+            "none" => Ok(CachePolicy::None),
+            "keys" => Ok(CachePolicy::Keys),
+            "resources" => Ok(CachePolicy::Resources),
+            "all" => Ok(CachePolicy::All),
+            _ => Err(format!(
+                "cache policy {} isn't one of none, keys, resources, all",
+                policy
+            )),
+        }
+    }
+
+    fn covers(&self, kind: Kind) -> bool {
+        matches!(
+            (self, kind),
+            (CachePolicy::All, _)
+                | (CachePolicy::Keys, Kind::Key)
+                | (CachePolicy::Resources, Kind::Resource)
+        )
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UpstreamConfig {
+    /// Address of the upstream verdictd's attestation listener
+    pub addr: String,
+    pub tls_type: Option<String>,
+    pub crypto: Option<String>,
+    pub attester: Option<String>,
+    pub verifier: Option<String>,
+    /// Connect, receive and transmit timeout of the upstream session
+    pub timeout: Duration,
+    pub cache: CachePolicy,
+    pub cache_ttl: Duration,
+}
+
+impl Default for UpstreamConfig {
+    fn default() -> Self {
+        UpstreamConfig {
+            addr: String::new(),
+            tls_type: None,
+            crypto: None,
+            attester: None,
+            verifier: None,
+            timeout: Duration::from_secs(10),
+            cache: CachePolicy::None,
+            cache_ttl: Duration::from_secs(300),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Key,
+    Resource,
+}
+
+struct Cached {
+    content: Vec<u8>,
+    expires: Instant,
+}
+
+lazy_static! {
+    static ref UPSTREAM: RwLock<Option<UpstreamConfig>> = RwLock::new(None);
+    static ref CACHE: Mutex<HashMap<String, Cached>> = Mutex::new(HashMap::new());
+}
+
+/// Set the upstream verdictd, `None` turns the proxy off. The cache is
+/// emptied either way.
+pub fn configure(config: Option<UpstreamConfig>) {
+    if let Some(config) = &config {
+        info!(
+            "upstream verdictd: {}, cache: {:?} for {:?}",
+            config.addr, config.cache, config.cache_ttl
+        );
+    }
+    *UPSTREAM.write() = config;
+    CACHE.lock().clear();
+}
+
+pub fn enabled() -> bool {
+    UPSTREAM.read().is_some()
+}
+
+fn current() -> Result<UpstreamConfig, String> {
+    UPSTREAM
+        .read()
+        .clone()
+        .ok_or_else(|| "no upstream verdictd is configured".to_string())
+}
+
+/// Fetch the key of `kid` from the upstream verdictd
+pub fn get_key(kid: &str) -> Result<Vec<u8>, String> {
+    fetch_key(&current()?, kid)
+}
+
+/// Fetch the content of the resource `name` from the upstream verdictd
+pub fn get_resource(name: &str) -> Result<Vec<u8>, String> {
+    fetch_resource(&current()?, name)
+}
+
+/// Base64 size of the resource `name` held by the upstream verdictd
+pub fn get_resource_size(name: &str) -> Result<usize, String> {
+    fetch_resource_size(&current()?, name)
+}
+
+fn fetch_key(config: &UpstreamConfig, kid: &str) -> Result<Vec<u8>, String> {
+    let request = serde_json::json!({
+        "command": "Get KEK",
+        "protocol": "v2",
+        "kids": [kid],
+    });
+    fetch(config, Kind::Key, kid, request, |data| data[kid].as_str())
+}
+
+fn fetch_resource(config: &UpstreamConfig, name: &str) -> Result<Vec<u8>, String> {
+    let request = serde_json::json!({
+        "command": "Get Resource",
+        "protocol": "v2",
+        "name": name,
+    });
+    fetch(config, Kind::Resource, name, request, |data| {
+        data["content"].as_str()
+    })
+}
+
+fn fetch_resource_size(config: &UpstreamConfig, name: &str) -> Result<usize, String> {
+    if let Some(content) = cached(Kind::Resource, name) {
+        return Ok(base64::encode(content).len());
+    }
+
+    info!("fetch resource info {} from upstream {}", name, config.addr);
+    let request = serde_json::json!({
+        "command": "Get Resource Info",
+        "protocol": "v2",
+        "name": name,
+    });
+    let data = request_upstream(config, &request)?;
+    data["base64size"]
+        .as_str()
+        .and_then(|size| size.parse::<usize>().ok())
+        .ok_or_else(|| format!("upstream response misses the size of resource {}", name))
+}
+
+/// Unexpired cached content of `name`
+fn cached(kind: Kind, name: &str) -> Option<Vec<u8>> {
+    let cache_key = format!("{:?}/{}", kind, name);
+    let mut cache = CACHE.lock();
+    match cache.get(&cache_key) {
+        Some(cached) if cached.expires > Instant::now() => Some(cached.content.clone()),
+        Some(_) => {
+            cache.remove(&cache_key);
+            None
+        }
+        None => None,
+    }
+}
+
+fn fetch(
+    config: &UpstreamConfig,
+    kind: Kind,
+    name: &str,
+    request: Value,
+    content: impl Fn(&Value) -> Option<&str>,
+) -> Result<Vec<u8>, String> {
+    if let Some(content) = cached(kind, name) {
+        info!("{:?} {} is served from the upstream cache", kind, name);
+        return Ok(content);
+    }
+
+    info!("fetch {:?} {} from upstream {}", kind, name, config.addr);
+    let data = request_upstream(config, &request)?;
+    let content = content(&data)
+        .ok_or_else(|| format!("upstream response misses {:?} {}", kind, name))
+        .and_then(|content| {
+            base64::decode(content).map_err(|e| format!("upstream response isn't base64: {}", e))
+        })?;
+
+    if config.cache.covers(kind) && !config.cache_ttl.is_zero() {
+        CACHE.lock().insert(
+            format!("{:?}/{}", kind, name),
+            Cached {
+                content: content.clone(),
+                expires: Instant::now() + config.cache_ttl,
+            },
+        );
+    }
+
+    Ok(content)
+}
+
+/// Send `request` in a new attested session to the upstream and return the
+/// data of its response
+fn request_upstream(config: &UpstreamConfig, request: &Value) -> Result<Value, String> {
+    let socket = config
+        .addr
+        .to_socket_addrs()
+        .map_err(|e| format!("resolve upstream {} failed: {}", config.addr, e))?
+        .find_map(|addr| TcpStream::connect_timeout(&addr, config.timeout).ok())
+        .ok_or_else(|| format!("connect to upstream {} failed", config.addr))?;
+    socket
+        .set_read_timeout(Some(config.timeout))
+        .and_then(|_| socket.set_write_timeout(Some(config.timeout)))
+        .map_err(|e| format!("set up upstream socket failed: {}", e))?;
+
+    // Mutual mode makes the upstream attest this instance
+    let tls = RatsTls::new(
+        false,
+        0,
+        &config.tls_type,
+        &config.crypto,
+        &config.attester,
+        &config.verifier,
+        true,
+    )
+    .map_err(|e| format!("new RatsTls failed with error {:?}", e))?;
+    RatsTls::with_connection("", &config.addr, || tls.negotiate(socket.as_raw_fd()))
+        .map_err(|e| format!("negotiate with upstream {} failed: {:?}", config.addr, e))?;
+
+    exchange(&tls, request)
+}
+
+fn exchange<C: Channel + ?Sized>(channel: &C, request: &Value) -> Result<Value, String> {
+    let mut conn = framing::Connection::new(channel);
+    conn.write_message(request.to_string().as_bytes())?;
+    let response: Value = serde_json::from_slice(&conn.read_message()?)
+        .map_err(|e| format!("parse upstream response failed: {}", e))?;
+
+    match response["status"].as_str() {
+        Some("OK") => Ok(response["data"].clone()),
+        _ => Err(format!(
+            "upstream error {}: {}",
+            response["error"]["code"], response["error"]["message"]
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    // Channel answering every request with a canned response
+    struct Canned {
+        request: RefCell<Vec<u8>>,
+        response: RefCell<Vec<u8>>,
+    }
+
+    impl Canned {
+        fn new(response: Value) -> Self {
+            let canned = Canned {
+                request: RefCell::new(Vec::new()),
+                response: RefCell::new(Vec::new()),
+            };
+            // Frame the response by writing it to the channel
+            framing::Connection::new(&canned)
+                .write_message(response.to_string().as_bytes())
+                .unwrap();
+            canned.response.replace(canned.request.take());
+            canned
+        }
+    }
+
+    impl Channel for Canned {
+        fn receive(&self, buf: &mut [u8]) -> Result<usize, String> {
+            let mut response = self.response.borrow_mut();
+            let len = buf.len().min(response.len());
+            buf[..len].copy_from_slice(&response[..len]);
+            response.drain(..len);
+            Ok(len)
+        }
+
+        fn transmit(&self, buf: &[u8]) -> Result<usize, String> {
+            self.request.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+    }
+
+    #[test]
+    fn test_exchange() {
+        let channel = Canned::new(serde_json::json!({"status": "OK", "data": {"k": "a2V5"}}));
+        let request = serde_json::json!({"command": "Get KEK", "protocol": "v2", "kids": ["k"]});
+        assert_eq!(
+            exchange(&channel, &request).unwrap(),
+            serde_json::json!({"k": "a2V5"})
+        );
+        assert_eq!(
+            &channel.request.borrow()[framing::FRAME_HEADER_LEN..],
+            request.to_string().as_bytes()
+        );
+
+        let channel = Canned::new(serde_json::json!({
+            "status": "Fail",
+            "error": {"code": 3000, "message": "kid: k's key not found"},
+        }));
+        let e = exchange(&channel, &request).unwrap_err();
+        assert!(e.contains("3000"));
+    }
+
+    #[cfg(feature = "mock-rats-tls")]
+    #[test]
+    fn test_fetch_key() {
+        use crate::attestation_agent::transport;
+        use crate::rats_tls::{fixtures, mock};
+        use crate::resources::{directory_key_manager, opa};
+        use std::net::TcpListener;
+
+        opa::default().unwrap();
+        let kid = "verdictd-upstream-test".to_string();
+        directory_key_manager::set_key(&kid, b"upstream key").unwrap();
+
+        // Upstream serving a single session of an attested edge
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = UpstreamConfig {
+            addr: listener.local_addr().unwrap().to_string(),
+            cache: CachePolicy::Keys,
+            ..Default::default()
+        };
+        let server = std::thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            mock::script_evidence(socket.as_raw_fd(), fixtures::sgx(0x4, ""));
+            let tls = RatsTls::new(true, 0, &None, &None, &None, &None, true).unwrap();
+            tls.negotiate(socket.as_raw_fd()).unwrap();
+            let _ = transport::serve(&tls);
+        });
+
+        assert_eq!(fetch_key(&config, &kid).unwrap(), b"upstream key".to_vec());
+        server.join().unwrap();
+
+        // The upstream is gone, the key is served from the cache
+        let _ = std::fs::remove_file(format!("/opt/verdictd/keys/{}", kid));
+        assert_eq!(fetch_key(&config, &kid).unwrap(), b"upstream key".to_vec());
+        assert!(fetch_resource(&config, "verdictd-test/secret/none").is_err());
+    }
+
+    #[cfg(feature = "mock-rats-tls")]
+    #[test]
+    fn test_fetch_resource_size() {
+        use crate::attestation_agent::transport;
+        use crate::rats_tls::{fixtures, mock};
+        use crate::resources::{catalog, opa};
+        use std::net::TcpListener;
+
+        opa::default().unwrap();
+        catalog::default().unwrap();
+        let name = "verdictd-upstream-test/resource/size";
+        catalog::set(name, b"upstream resource").unwrap();
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = UpstreamConfig {
+            addr: listener.local_addr().unwrap().to_string(),
+            cache: CachePolicy::Resources,
+            ..Default::default()
+        };
+        let server = std::thread::spawn(move || {
+            let (socket, _) = listener.accept().unwrap();
+            mock::script_evidence(socket.as_raw_fd(), fixtures::sgx(0x4, ""));
+            let tls = RatsTls::new(true, 0, &None, &None, &None, &None, true).unwrap();
+            tls.negotiate(socket.as_raw_fd()).unwrap();
+            let _ = transport::serve(&tls);
+        });
+
+        // The size is asked to the upstream, the resource isn't downloaded
+        let size = base64::encode(b"upstream resource").len();
+        assert_eq!(fetch_resource_size(&config, name).unwrap(), size);
+        server.join().unwrap();
+        assert!(cached(Kind::Resource, name).is_none());
+        assert!(fetch_resource_size(&config, name).is_err());
+
+        // A cached resource answers without the upstream
+        CACHE.lock().insert(
+            format!("{:?}/{}", Kind::Resource, name),
+            Cached {
+                content: b"cached".to_vec(),
+                expires: Instant::now() + config.cache_ttl,
+            },
+        );
+        assert_eq!(
+            fetch_resource_size(&config, name).unwrap(),
+            base64::encode(b"cached").len()
+        );
+        catalog::delete(name).unwrap();
+    }
+
+    #[test]
+    fn test_cache_policy() {
+        assert_eq!(CachePolicy::parse("keys").unwrap(), CachePolicy::Keys);
+        assert!(CachePolicy::parse("secrets").is_err());
+        assert!(CachePolicy::All.covers(Kind::Resource));
+        assert!(CachePolicy::Keys.covers(Kind::Key));
+        assert!(!CachePolicy::Keys.covers(Kind::Resource));
+        assert!(!CachePolicy::None.covers(Kind::Key));
+    }
+}
@@ -8,6 +8,9 @@ pub struct AnnotationPacket {
     pub wrapped_data: Vec<u8>,
     pub iv: Vec<u8>,
     pub algorithm: String,
+    /// Cipher mode, the algorithm's default mode if absent
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<String>,
     pub key_length: u16,
 }
 
@@ -21,6 +24,7 @@ mod tests {
             wrapped_data: vec![0x01, 0x02, 0x03],
             iv: vec![0x04, 0x05, 0x06],
             algorithm: "AES".to_string(),
+            mode: None,
             key_length: 256,
         };
 
@@ -1,31 +1,47 @@
 use crate::client_api;
+use std::future::Future;
 use tonic::transport::Server;
 
+use clientApi::audit_service_server::AuditServiceServer;
 use clientApi::gpg_service_server::GpgServiceServer;
 use clientApi::image_service_server::ImageServiceServer;
 use clientApi::key_manager_service_server::KeyManagerServiceServer;
 use clientApi::opa_service_server::OpaServiceServer;
+use clientApi::resource_service_server::ResourceServiceServer;
+use clientApi::secret_service_server::SecretServiceServer;
+use clientApi::token_service_server::TokenServiceServer;
 use client_api::key_provider::keyProvider::key_provider_service_server::KeyProviderServiceServer;
 
 pub mod clientApi {
     tonic::include_proto!("clientapi");
 }
 
-pub async fn server(addr: &str) -> Result<(), Box<dyn std::error::Error>> {
+pub async fn server(
+    addr: &str,
+    shutdown: impl Future<Output = ()>,
+) -> Result<(), Box<dyn std::error::Error>> {
     let addr = addr.parse()?;
+    let audit_service = client_api::audit::auditService::default();
     let gpg_service = client_api::gpg::gpgService::default();
     let image_service = client_api::image::imageService::default();
     let key_manager_service = client_api::key_manager::keyManagerService::default();
     let key_provider_service = client_api::key_provider::keyProviderService::default();
     let opa_service = client_api::opa::opaService::default();
+    let resource_service = client_api::resource::resourceService::default();
+    let secret_service = client_api::secret::secretService::default();
+    let token_service = client_api::token::tokenService::default();
 
     Server::builder()
+        .add_service(AuditServiceServer::new(audit_service))
         .add_service(GpgServiceServer::new(gpg_service))
         .add_service(ImageServiceServer::new(image_service))
         .add_service(KeyManagerServiceServer::new(key_manager_service))
         .add_service(KeyProviderServiceServer::new(key_provider_service))
         .add_service(OpaServiceServer::new(opa_service))
-        .serve(addr)
+        .add_service(ResourceServiceServer::new(resource_service))
+        .add_service(SecretServiceServer::new(secret_service))
+        .add_service(TokenServiceServer::new(token_service))
+        .serve_with_shutdown(addr, shutdown)
         .await?;
 
     Ok(())
@@ -0,0 +1,49 @@
+use crate::client_api::api;
+use crate::resources::audit;
+use tonic::{Request, Response, Status};
+
+use api::clientApi::audit_service_server::AuditService;
+use api::clientApi::{QueryAuditLogRequest, QueryAuditLogResponse};
+
+#[derive(Debug, Default)]
+pub struct auditService {}
+
+#[tonic::async_trait]
+impl AuditService for auditService {
+    async fn query_audit_log(
+        &self,
+        request: Request<QueryAuditLogRequest>,
+    ) -> Result<Response<QueryAuditLogResponse>, Status> {
+        let request: QueryAuditLogRequest = request.into_inner();
+        let decision = String::from_utf8(request.decision).unwrap_or_else(|_| {
+            error!("parse decision failed");
+            "".to_string()
+        });
+        let decision = match decision.as_str() {
+            "" => None,
+            decision => Some(decision),
+        };
+
+        let res = audit::query(request.from, request.to, decision)
+            .and_then(|(entries, chain)| {
+                let entries = serde_json::to_vec_pretty(&entries).map_err(|e| e.to_string())?;
+                let res = QueryAuditLogResponse {
+                    status: chain
+                        .clone()
+                        .map(|_| "OK".to_string())
+                        .unwrap_or_else(|e| e)
+                        .into_bytes(),
+                    entries,
+                    intact: chain.is_ok(),
+                };
+                Ok(res)
+            })
+            .unwrap_or_else(|e| QueryAuditLogResponse {
+                status: e.into_bytes(),
+                entries: Vec::new(),
+                intact: false,
+            });
+
+        Ok(Response::new(res))
+    }
+}
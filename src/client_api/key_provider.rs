@@ -1,6 +1,6 @@
 use crate::client_api::annotation;
 use crate::client_api::messages::*;
-use crate::crypto::aes256_gcm;
+use crate::crypto::registry;
 use crate::resources::directory_key_manager;
 use base64;
 use rand::*;
@@ -17,8 +17,10 @@ pub mod keyProvider {
 #[derive(Debug, Default)]
 pub struct keyProviderService {}
 
-const IV_LEN: usize = 12;
 const KEY_LEN: usize = 32;
+// Cipher used to wrap new keys
+const WRAP_ALGORITHM: &str = "AES";
+const WRAP_MODE: &str = "GCM";
 
 #[tonic::async_trait]
 impl KeyProviderService for keyProviderService {
@@ -58,18 +60,20 @@ impl KeyProviderService for keyProviderService {
             rand::rngs::OsRng.fill_bytes(&mut key);
             directory_key_manager::set_key(&kid, &key)?;
         }
-        let mut iv = [0; IV_LEN];
+        let cipher =
+            registry::lookup(WRAP_ALGORITHM, Some(WRAP_MODE), (KEY_LEN * 8) as u32).unwrap();
+        let mut iv = vec![0; cipher.iv_length];
         rand::rngs::OsRng.fill_bytes(&mut iv);
 
         let encrypted_data = directory_key_manager::get_key(&kid)
             .and_then(|key| {
                 info!("key: {:?}", key);
-                let encrypted_data =
-                    aes256_gcm::encrypt(&base64::decode(optsdata).unwrap(), key.as_slice(), &iv)
-                        .unwrap_or_else(|e| {
-                            error!("encrypt data failed with error:{:?}", e);
-                            vec![0]
-                        });
+                let encrypted_data = cipher
+                    .encrypt(&base64::decode(optsdata).unwrap(), key.as_slice(), &iv)
+                    .unwrap_or_else(|e| {
+                        error!("encrypt data failed with error:{:?}", e);
+                        vec![0]
+                    });
                 Ok(encrypted_data)
             })
             .unwrap_or_else(|_| {
@@ -80,9 +84,10 @@ impl KeyProviderService for keyProviderService {
         let annotation = annotation::AnnotationPacket {
             kid: kid.to_string(),
             wrapped_data: encrypted_data,
-            iv: iv.to_vec(),
-            algorithm: String::from("AES"),
-            key_length: 256,
+            iv,
+            algorithm: cipher.algorithm.to_string(),
+            mode: Some(cipher.mode.to_string()),
+            key_length: cipher.key_length as u16,
         };
 
         let key_wrap_output = KeyWrapOutput {
@@ -138,11 +143,18 @@ impl KeyProviderService for keyProviderService {
             .and_then(|annotation| {
                 let decrypted_data = directory_key_manager::get_key(&annotation.kid)
                     .and_then(|key| {
-                        let a = aes256_gcm::decrypt(
-                            &annotation.wrapped_data[..],
-                            key.as_slice(),
-                            &annotation.iv[..],
+                        let a = registry::lookup(
+                            &annotation.algorithm,
+                            annotation.mode.as_deref(),
+                            annotation.key_length as u32,
                         )
+                        .and_then(|cipher| {
+                            cipher.decrypt(
+                                &annotation.wrapped_data[..],
+                                key.as_slice(),
+                                &annotation.iv[..],
+                            )
+                        })
                         .unwrap_or_else(|e| {
                             error!("decrypt data failed with error:{:?}", e);
                             vec![0]
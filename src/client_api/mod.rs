@@ -1,8 +1,12 @@
 pub mod annotation;
 pub mod api;
+pub mod audit;
 pub mod gpg;
 pub mod image;
 pub mod key_manager;
 pub mod key_provider;
 pub mod messages;
 pub mod opa;
+pub mod resource;
+pub mod secret;
+pub mod token;
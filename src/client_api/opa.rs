@@ -1,18 +1,48 @@
 use crate::client_api::api;
 use crate::policy_engine;
 use crate::resources;
+use crate::resources::policy_binding::{self, Binding};
+use crate::resources::sigstruct::{self, Merge};
 use tonic::{Request, Response, Status};
 
 use api::clientApi::opa_service_server::OpaService;
+use api::clientApi::{DeleteOpaBundleRequest, DeleteOpaBundleResponse};
+use api::clientApi::{DeletePolicyBindingRequest, DeletePolicyBindingResponse};
+use api::clientApi::{ExportOpaBundleRequest, ExportOpaBundleResponse};
 use api::clientApi::{ExportOpaPolicyRequest, ExportOpaPolicyResponse};
 use api::clientApi::{ExportOpaReferenceRequest, ExportOpaReferenceResponse};
+use api::clientApi::{GetPolicyCacheMetricsRequest, GetPolicyCacheMetricsResponse};
+use api::clientApi::{ImportSgxReferenceRequest, ImportSgxReferenceResponse};
+use api::clientApi::{ListOpaBundlesRequest, ListOpaBundlesResponse};
+use api::clientApi::{ListPolicyBindingsRequest, ListPolicyBindingsResponse};
+use api::clientApi::{SetOpaBundleRequest, SetOpaBundleResponse};
 use api::clientApi::{SetOpaPolicyRequest, SetOpaPolicyResponse};
 use api::clientApi::{SetOpaReferenceRequest, SetOpaReferenceResponse};
+use api::clientApi::{SetPolicyBindingRequest, SetPolicyBindingResponse};
 use api::clientApi::{TestOpaRequest, TestOpaResponse};
 
 #[derive(Debug, Default)]
 pub struct opaService {}
 
+/// None if the field is empty
+fn optional(field: Vec<u8>) -> Result<Option<String>, String> {
+    String::from_utf8(field)
+        .map_err(|_| "parse SetPolicyBindingRequest failed".to_string())
+        .map(|s| if s.is_empty() { None } else { Some(s) })
+}
+
+fn binding(request: SetPolicyBindingRequest) -> Result<Binding, String> {
+    Ok(Binding {
+        name: optional(request.name)?.unwrap_or_default(),
+        tee: optional(request.tee)?,
+        listener: optional(request.listener)?,
+        signer: optional(request.signer)?,
+        policy: optional(request.policy)?.unwrap_or_default(),
+        reference: optional(request.reference)?.unwrap_or_default(),
+        bundle: optional(request.bundle)?,
+    })
+}
+
 #[tonic::async_trait]
 impl OpaService for opaService {
     async fn set_opa_policy(
@@ -31,14 +61,22 @@ impl OpaService for opaService {
         });
 
         let res = resources::opa::set_policy(name, content)
-            .and_then(|_| {
-                let res = SetOpaPolicyResponse {
+            .map(|diagnostics| match diagnostics.is_empty() {
+                true => SetOpaPolicyResponse {
                     status: "OK".as_bytes().to_vec(),
-                };
-                Ok(res)
+                    diagnostics: "[]".as_bytes().to_vec(),
+                },
+                false => {
+                    error!("policy {} is rejected: {:?}", name, diagnostics);
+                    SetOpaPolicyResponse {
+                        status: "Policy syntax check failed".as_bytes().to_vec(),
+                        diagnostics: serde_json::to_vec(&diagnostics).unwrap_or_default(),
+                    }
+                }
             })
             .unwrap_or_else(|e| SetOpaPolicyResponse {
                 status: e.into_bytes(),
+                diagnostics: "[]".as_bytes().to_vec(),
             });
 
         Ok(Response::new(res))
@@ -204,8 +242,7 @@ impl OpaService for opaService {
             &input,
         )
         .map_err(|e| format!("make_decision error: {}", e))
-        .and_then(|res| serde_json::from_str(&res).map_err(|_| res))
-        .and_then(|res: serde_json::Value| Ok(res.to_string()));
+        .and_then(|decision| serde_json::to_string(&decision).map_err(|e| e.to_string()));
 
         let msg = match msg {
             Ok(msg) => msg,
@@ -218,4 +255,201 @@ impl OpaService for opaService {
 
         Ok(Response::new(res))
     }
+
+    async fn set_policy_binding(
+        &self,
+        request: Request<SetPolicyBindingRequest>,
+    ) -> Result<Response<SetPolicyBindingResponse>, Status> {
+        let res = binding(request.into_inner())
+            .and_then(|binding| {
+                info!("set policy binding: {:?}", binding);
+                policy_binding::set(binding)
+            })
+            .map(|_| SetPolicyBindingResponse {
+                status: "OK".as_bytes().to_vec(),
+            })
+            .unwrap_or_else(|e| SetPolicyBindingResponse {
+                status: e.into_bytes(),
+            });
+
+        Ok(Response::new(res))
+    }
+
+    async fn list_policy_bindings(
+        &self,
+        _request: Request<ListPolicyBindingsRequest>,
+    ) -> Result<Response<ListPolicyBindingsResponse>, Status> {
+        let res = policy_binding::list()
+            .and_then(|bindings| serde_json::to_string_pretty(&bindings).map_err(|e| e.to_string()))
+            .map(|content| ListPolicyBindingsResponse {
+                status: "OK".as_bytes().to_vec(),
+                content: content.into_bytes(),
+            })
+            .unwrap_or_else(|e| ListPolicyBindingsResponse {
+                status: e.into_bytes(),
+                content: Vec::new(),
+            });
+
+        Ok(Response::new(res))
+    }
+
+    async fn delete_policy_binding(
+        &self,
+        request: Request<DeletePolicyBindingRequest>,
+    ) -> Result<Response<DeletePolicyBindingResponse>, Status> {
+        let name = String::from_utf8(request.into_inner().name).unwrap_or_else(|_| {
+            error!("parse policy binding name failed");
+            "".to_string()
+        });
+        info!("delete policy binding: {}", name);
+
+        let res = policy_binding::delete(&name)
+            .map(|_| DeletePolicyBindingResponse {
+                status: "OK".as_bytes().to_vec(),
+            })
+            .unwrap_or_else(|e| DeletePolicyBindingResponse {
+                status: e.into_bytes(),
+            });
+
+        Ok(Response::new(res))
+    }
+
+    async fn import_sgx_reference(
+        &self,
+        request: Request<ImportSgxReferenceRequest>,
+    ) -> Result<Response<ImportSgxReferenceResponse>, Status> {
+        let request: ImportSgxReferenceRequest = request.into_inner();
+        let name = String::from_utf8(request.name).unwrap_or_else(|_| {
+            error!("parse reference name failed");
+            "".to_string()
+        });
+        let mode = match request.replace {
+            true => Merge::Replace,
+            false => Merge::Append,
+        };
+        info!("import SGX reference into {}, {:?}", name, mode);
+
+        let res = sigstruct::import(&name, &request.content, mode)
+            .map(|content| ImportSgxReferenceResponse {
+                status: "OK".as_bytes().to_vec(),
+                content: content.into_bytes(),
+            })
+            .unwrap_or_else(|e| ImportSgxReferenceResponse {
+                status: e.into_bytes(),
+                content: Vec::new(),
+            });
+
+        Ok(Response::new(res))
+    }
+
+    async fn get_policy_cache_metrics(
+        &self,
+        _request: Request<GetPolicyCacheMetricsRequest>,
+    ) -> Result<Response<GetPolicyCacheMetricsResponse>, Status> {
+        let res = serde_json::to_string(&policy_engine::cache::metrics())
+            .map(|metrics| GetPolicyCacheMetricsResponse {
+                status: "OK".as_bytes().to_vec(),
+                metrics: metrics.into_bytes(),
+            })
+            .unwrap_or_else(|e| GetPolicyCacheMetricsResponse {
+                status: e.to_string().into_bytes(),
+                metrics: Vec::new(),
+            });
+
+        Ok(Response::new(res))
+    }
+
+    async fn set_opa_bundle(
+        &self,
+        request: Request<SetOpaBundleRequest>,
+    ) -> Result<Response<SetOpaBundleResponse>, Status> {
+        let request: SetOpaBundleRequest = request.into_inner();
+        let name = String::from_utf8(request.name).unwrap_or_else(|_| {
+            error!("parse bundle name failed");
+            "".to_string()
+        });
+        info!("set OPA bundle {}, {} bytes", name, request.content.len());
+
+        let res = resources::bundle::set(&name, &request.content)
+            .map(|diagnostics| match diagnostics.is_empty() {
+                true => SetOpaBundleResponse {
+                    status: "OK".as_bytes().to_vec(),
+                    diagnostics: "[]".as_bytes().to_vec(),
+                },
+                false => {
+                    error!("bundle {} is rejected: {:?}", name, diagnostics);
+                    SetOpaBundleResponse {
+                        status: "Bundle syntax check failed".as_bytes().to_vec(),
+                        diagnostics: serde_json::to_vec(&diagnostics).unwrap_or_default(),
+                    }
+                }
+            })
+            .unwrap_or_else(|e| SetOpaBundleResponse {
+                status: e.into_bytes(),
+                diagnostics: "[]".as_bytes().to_vec(),
+            });
+
+        Ok(Response::new(res))
+    }
+
+    async fn export_opa_bundle(
+        &self,
+        request: Request<ExportOpaBundleRequest>,
+    ) -> Result<Response<ExportOpaBundleResponse>, Status> {
+        let name = String::from_utf8(request.into_inner().name).unwrap_or_else(|_| {
+            error!("parse bundle name failed");
+            "".to_string()
+        });
+
+        let res = resources::bundle::export(&name)
+            .map(|content| ExportOpaBundleResponse {
+                status: "OK".as_bytes().to_vec(),
+                content,
+            })
+            .unwrap_or_else(|e| ExportOpaBundleResponse {
+                status: e.into_bytes(),
+                content: Vec::new(),
+            });
+
+        Ok(Response::new(res))
+    }
+
+    async fn list_opa_bundles(
+        &self,
+        _request: Request<ListOpaBundlesRequest>,
+    ) -> Result<Response<ListOpaBundlesResponse>, Status> {
+        let res = resources::bundle::list()
+            .and_then(|bundles| serde_json::to_string_pretty(&bundles).map_err(|e| e.to_string()))
+            .map(|content| ListOpaBundlesResponse {
+                status: "OK".as_bytes().to_vec(),
+                content: content.into_bytes(),
+            })
+            .unwrap_or_else(|e| ListOpaBundlesResponse {
+                status: e.into_bytes(),
+                content: Vec::new(),
+            });
+
+        Ok(Response::new(res))
+    }
+
+    async fn delete_opa_bundle(
+        &self,
+        request: Request<DeleteOpaBundleRequest>,
+    ) -> Result<Response<DeleteOpaBundleResponse>, Status> {
+        let name = String::from_utf8(request.into_inner().name).unwrap_or_else(|_| {
+            error!("parse bundle name failed");
+            "".to_string()
+        });
+        info!("delete OPA bundle: {}", name);
+
+        let res = resources::bundle::delete(&name)
+            .map(|_| DeleteOpaBundleResponse {
+                status: "OK".as_bytes().to_vec(),
+            })
+            .unwrap_or_else(|e| DeleteOpaBundleResponse {
+                status: e.into_bytes(),
+            });
+
+        Ok(Response::new(res))
+    }
 }
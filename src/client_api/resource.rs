@@ -0,0 +1,83 @@
+use crate::client_api::api;
+use crate::resources::catalog;
+use tonic::{Request, Response, Status};
+
+use api::clientApi::resource_service_server::ResourceService;
+use api::clientApi::{DeleteResourceRequest, DeleteResourceResponse};
+use api::clientApi::{ListResourcesRequest, ListResourcesResponse};
+use api::clientApi::{SetResourceRequest, SetResourceResponse};
+
+#[derive(Debug, Default)]
+pub struct resourceService {}
+
+#[tonic::async_trait]
+impl ResourceService for resourceService {
+    async fn set_resource(
+        &self,
+        request: Request<SetResourceRequest>,
+    ) -> Result<Response<SetResourceResponse>, Status> {
+        let request: SetResourceRequest = request.into_inner();
+        let name = String::from_utf8(request.name).unwrap_or_else(|_| {
+            error!("parse resource name failed");
+            "".to_string()
+        });
+        info!("set resource: {}", name);
+
+        let res = catalog::set(&name, &request.content)
+            .map(|_| SetResourceResponse {
+                status: "OK".as_bytes().to_vec(),
+            })
+            .unwrap_or_else(|e| SetResourceResponse {
+                status: e.into_bytes(),
+            });
+
+        Ok(Response::new(res))
+    }
+
+    async fn list_resources(
+        &self,
+        request: Request<ListResourcesRequest>,
+    ) -> Result<Response<ListResourcesResponse>, Status> {
+        let repository = String::from_utf8(request.into_inner().repository).unwrap_or_else(|_| {
+            error!("parse repository failed");
+            "".to_string()
+        });
+        let repository = match repository.as_str() {
+            "" => None,
+            repository => Some(repository),
+        };
+
+        let res = catalog::list(repository)
+            .map(|names| ListResourcesResponse {
+                status: "OK".as_bytes().to_vec(),
+                names: names.into_iter().map(|name| name.into_bytes()).collect(),
+            })
+            .unwrap_or_else(|e| ListResourcesResponse {
+                status: e.into_bytes(),
+                names: Vec::new(),
+            });
+
+        Ok(Response::new(res))
+    }
+
+    async fn delete_resource(
+        &self,
+        request: Request<DeleteResourceRequest>,
+    ) -> Result<Response<DeleteResourceResponse>, Status> {
+        let name = String::from_utf8(request.into_inner().name).unwrap_or_else(|_| {
+            error!("parse resource name failed");
+            "".to_string()
+        });
+        info!("delete resource: {}", name);
+
+        let res = catalog::delete(&name)
+            .map(|_| DeleteResourceResponse {
+                status: "OK".as_bytes().to_vec(),
+            })
+            .unwrap_or_else(|e| DeleteResourceResponse {
+                status: e.into_bytes(),
+            });
+
+        Ok(Response::new(res))
+    }
+}
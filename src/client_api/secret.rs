@@ -0,0 +1,83 @@
+use crate::client_api::api;
+use crate::resources::secret;
+use tonic::{Request, Response, Status};
+
+use api::clientApi::secret_service_server::SecretService;
+use api::clientApi::{CreateSecretRequest, CreateSecretResponse};
+use api::clientApi::{DeleteSecretRequest, DeleteSecretResponse};
+use api::clientApi::{GetSecretStatusRequest, GetSecretStatusResponse};
+
+#[derive(Debug, Default)]
+pub struct secretService {}
+
+fn parse_id(id: Vec<u8>) -> String {
+    String::from_utf8(id).unwrap_or_else(|_| {
+        error!("parse secret id failed");
+        "".to_string()
+    })
+}
+
+#[tonic::async_trait]
+impl SecretService for secretService {
+    async fn create_secret(
+        &self,
+        request: Request<CreateSecretRequest>,
+    ) -> Result<Response<CreateSecretResponse>, Status> {
+        let request: CreateSecretRequest = request.into_inner();
+        let id = parse_id(request.id);
+        info!(
+            "create secret: {}, max uses: {}, ttl: {}",
+            id, request.max_uses, request.ttl
+        );
+
+        let res = secret::create(&id, &request.content, request.max_uses, request.ttl)
+            .map(|_| CreateSecretResponse {
+                status: "OK".as_bytes().to_vec(),
+            })
+            .unwrap_or_else(|e| CreateSecretResponse {
+                status: e.into_bytes(),
+            });
+
+        Ok(Response::new(res))
+    }
+
+    async fn get_secret_status(
+        &self,
+        request: Request<GetSecretStatusRequest>,
+    ) -> Result<Response<GetSecretStatusResponse>, Status> {
+        let id = parse_id(request.into_inner().id);
+
+        let res = secret::status(&id)
+            .map(|secret| GetSecretStatusResponse {
+                status: "OK".as_bytes().to_vec(),
+                uses: secret.uses,
+                max_uses: secret.max_uses,
+                created_at: secret.created_at,
+                expires_at: secret.expires_at,
+            })
+            .unwrap_or_else(|e| GetSecretStatusResponse {
+                status: e.into_bytes(),
+                ..Default::default()
+            });
+
+        Ok(Response::new(res))
+    }
+
+    async fn delete_secret(
+        &self,
+        request: Request<DeleteSecretRequest>,
+    ) -> Result<Response<DeleteSecretResponse>, Status> {
+        let id = parse_id(request.into_inner().id);
+        info!("delete secret: {}", id);
+
+        let res = secret::delete(&id)
+            .map(|_| DeleteSecretResponse {
+                status: "OK".as_bytes().to_vec(),
+            })
+            .unwrap_or_else(|e| DeleteSecretResponse {
+                status: e.into_bytes(),
+            });
+
+        Ok(Response::new(res))
+    }
+}
@@ -0,0 +1,52 @@
+use crate::client_api::api;
+use crate::resources::token;
+use tonic::{Request, Response, Status};
+
+use api::clientApi::token_service_server::TokenService;
+use api::clientApi::{GetJwksRequest, GetJwksResponse};
+use api::clientApi::{VerifyTokenRequest, VerifyTokenResponse};
+
+#[derive(Debug, Default)]
+pub struct tokenService {}
+
+#[tonic::async_trait]
+impl TokenService for tokenService {
+    async fn get_jwks(
+        &self,
+        _request: Request<GetJwksRequest>,
+    ) -> Result<Response<GetJwksResponse>, Status> {
+        let res = token::jwks()
+            .map(|jwks| GetJwksResponse {
+                status: "OK".as_bytes().to_vec(),
+                jwks: jwks.to_string().into_bytes(),
+            })
+            .unwrap_or_else(|e| GetJwksResponse {
+                status: e.into_bytes(),
+                jwks: Vec::new(),
+            });
+
+        Ok(Response::new(res))
+    }
+
+    async fn verify_token(
+        &self,
+        request: Request<VerifyTokenRequest>,
+    ) -> Result<Response<VerifyTokenResponse>, Status> {
+        let token = String::from_utf8(request.into_inner().token).unwrap_or_else(|_| {
+            error!("parse token failed");
+            "".to_string()
+        });
+
+        let res = token::verify(token.trim())
+            .map(|claims| VerifyTokenResponse {
+                status: "OK".as_bytes().to_vec(),
+                claims: claims.to_string().into_bytes(),
+            })
+            .unwrap_or_else(|e| VerifyTokenResponse {
+                status: e.into_bytes(),
+                claims: Vec::new(),
+            });
+
+        Ok(Response::new(res))
+    }
+}
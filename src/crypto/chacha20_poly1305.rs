@@ -0,0 +1,36 @@
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+
+pub fn encrypt(data: &[u8], key: &[u8], iv: &[u8]) -> Result<Vec<u8>, String> {
+    let encrypting_key = Key::from_slice(key);
+    let cipher = ChaCha20Poly1305::new(encrypting_key);
+    let nonce = Nonce::from_slice(iv);
+    cipher
+        .encrypt(nonce, data.as_ref())
+        .map_err(|e| format!("Encrypt data failed: {:?}", e))
+}
+
+pub fn decrypt(encrypted_data: &[u8], key: &[u8], iv: &[u8]) -> Result<Vec<u8>, String> {
+    let decrypting_key = Key::from_slice(key);
+    let cipher = ChaCha20Poly1305::new(decrypting_key);
+    let nonce = Nonce::from_slice(iv);
+    cipher
+        .decrypt(nonce, encrypted_data.as_ref())
+        .map_err(|e| format!("Decrypt data failed: {:?}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encrypt_and_decrypt() {
+        let key = b"01234567890123456789012345678901";
+        let iv = b"012345678901";
+        let data = b"test_data";
+
+        let encrypted_data = encrypt(data, key, iv).unwrap();
+        assert_eq!(decrypt(&encrypted_data, key, iv).unwrap(), data.to_vec());
+        assert!(decrypt(&encrypted_data, key, b"210987654321").is_err());
+    }
+}
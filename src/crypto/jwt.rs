@@ -0,0 +1,131 @@
+//! Compact JWS tokens signed with Ed25519 (JWS algorithm `EdDSA`)
+extern crate crypto;
+
+use crypto::digest::Digest;
+use crypto::ed25519;
+use crypto::sha2::Sha256;
+use serde_json::Value;
+
+pub const ALGORITHM: &str = "EdDSA";
+
+pub struct SigningKey {
+    pub kid: String,
+    secret: [u8; 64],
+    pub public: [u8; 32],
+}
+
+impl SigningKey {
+    /// Derive the key pair from a 32 bytes seed, the kid is the hex encoded
+    /// SHA-256 thumbprint prefix of the public key
+    pub fn from_seed(seed: &[u8]) -> Result<SigningKey, String> {
+        if seed.len() != 32 {
+            return Err(format!("invalid seed length {}", seed.len()));
+        }
+
+        let (secret, public) = ed25519::keypair(seed);
+        let mut hasher = Sha256::new();
+        hasher.input(&public);
+        let kid = hasher.result_str()[..16].to_string();
+
+        Ok(SigningKey {
+            kid,
+            secret,
+            public,
+        })
+    }
+
+    pub fn sign(&self, claims: &Value) -> String {
+        let header = serde_json::json!({
+            "alg": ALGORITHM,
+            "typ": "JWT",
+            "kid": self.kid,
+        });
+        let signing_input = format!(
+            "{}.{}",
+            encode(header.to_string().as_bytes()),
+            encode(claims.to_string().as_bytes())
+        );
+        let signature = ed25519::signature(signing_input.as_bytes(), &self.secret);
+
+        format!("{}.{}", signing_input, encode(&signature))
+    }
+
+    /// The public key in JWK format (RFC 8037)
+    pub fn jwk(&self) -> Value {
+        serde_json::json!({
+            "kty": "OKP",
+            "crv": "Ed25519",
+            "alg": ALGORITHM,
+            "use": "sig",
+            "kid": self.kid,
+            "x": encode(&self.public),
+        })
+    }
+}
+
+fn encode(data: &[u8]) -> String {
+    base64::encode_config(data, base64::URL_SAFE_NO_PAD)
+}
+
+fn decode(data: &str) -> Result<Vec<u8>, String> {
+    base64::decode_config(data, base64::URL_SAFE_NO_PAD).map_err(|e| e.to_string())
+}
+
+/// Decode the header of `token` without verifying it
+pub fn header(token: &str) -> Result<Value, String> {
+    let header = token.split('.').next().unwrap_or("");
+    decode(header)
+        .and_then(|header| serde_json::from_slice(&header).map_err(|e| e.to_string()))
+        .map_err(|e| format!("invalid token header: {}", e))
+}
+
+/// Verify the signature of `token` with `public` and return its claims
+pub fn verify(token: &str, public: &[u8]) -> Result<Value, String> {
+    let parts: Vec<&str> = token.split('.').collect();
+    if parts.len() != 3 {
+        return Err("token isn't in compact JWS format".to_string());
+    }
+
+    if header(token)?["alg"] != ALGORITHM {
+        return Err(format!("token isn't signed with {}", ALGORITHM));
+    }
+
+    let signature = decode(parts[2]).map_err(|e| format!("invalid token signature: {}", e))?;
+    let signing_input = format!("{}.{}", parts[0], parts[1]);
+    if signature.len() != 64 || !ed25519::verify(signing_input.as_bytes(), public, &signature) {
+        return Err("token signature mismatch".to_string());
+    }
+
+    decode(parts[1])
+        .and_then(|claims| serde_json::from_slice(&claims).map_err(|e| e.to_string()))
+        .map_err(|e| format!("invalid token claims: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let key = SigningKey::from_seed(&[7u8; 32]).unwrap();
+        let claims = serde_json::json!({"tee": "sgx", "exp": 1});
+
+        let token = key.sign(&claims);
+        assert_eq!(header(&token).unwrap()["kid"], key.kid.as_str());
+        assert_eq!(verify(&token, &key.public).unwrap(), claims);
+
+        let other = SigningKey::from_seed(&[8u8; 32]).unwrap();
+        assert!(verify(&token, &other.public).is_err());
+
+        // Tamper with the claims
+        let parts: Vec<&str> = token.split('.').collect();
+        let forged = format!(
+            "{}.{}.{}",
+            parts[0],
+            encode(br#"{"tee":"csv","exp":1}"#),
+            parts[2]
+        );
+        assert!(verify(&forged, &key.public).is_err());
+        assert!(verify("a.b", &key.public).is_err());
+    }
+}
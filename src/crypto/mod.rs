@@ -1,2 +1,5 @@
 pub mod aes256_cbc;
 pub mod aes256_gcm;
+pub mod chacha20_poly1305;
+pub mod jwt;
+pub mod registry;
@@ -0,0 +1,153 @@
+//! Registry of the symmetric ciphers verdictd can wrap and unwrap data with.
+//!
+//! A cipher is looked up by `(algorithm, mode, key length)`, e.g.
+//! `("AES", "GCM", 256)`. When a request has no mode, the first registered
+//! mode of the algorithm is used, so `"AES"` alone still means AES-256-GCM.
+use crate::crypto::{aes256_cbc, aes256_gcm, chacha20_poly1305};
+
+/// `(data, key, iv)` to the encrypted or decrypted data
+type CipherFn = fn(&[u8], &[u8], &[u8]) -> Result<Vec<u8>, String>;
+
+pub struct Cipher {
+    pub algorithm: &'static str,
+    pub mode: &'static str,
+    /// Key length in bits
+    pub key_length: u32,
+    /// IV length in bytes
+    pub iv_length: usize,
+    encrypt: CipherFn,
+    decrypt: CipherFn,
+}
+
+fn aes256_cbc_encrypt(data: &[u8], key: &[u8], iv: &[u8]) -> Result<Vec<u8>, String> {
+    aes256_cbc::encrypt(data, key, iv).map_err(|e| format!("Encrypt data failed: {:?}", e))
+}
+
+fn aes256_cbc_decrypt(data: &[u8], key: &[u8], iv: &[u8]) -> Result<Vec<u8>, String> {
+    aes256_cbc::decrypt(data, key, iv).map_err(|e| format!("Decrypt data failed: {:?}", e))
+}
+
+const CIPHERS: [Cipher; 3] = [
+    Cipher {
+        algorithm: "AES",
+        mode: "GCM",
+        key_length: 256,
+        iv_length: 12,
+        encrypt: aes256_gcm::encrypt,
+        decrypt: aes256_gcm::decrypt,
+    },
+    Cipher {
+        algorithm: "AES",
+        mode: "CBC",
+        key_length: 256,
+        iv_length: 16,
+        encrypt: aes256_cbc_encrypt,
+        decrypt: aes256_cbc_decrypt,
+    },
+    Cipher {
+        algorithm: "ChaCha20-Poly1305",
+        mode: "AEAD",
+        key_length: 256,
+        iv_length: 12,
+        encrypt: chacha20_poly1305::encrypt,
+        decrypt: chacha20_poly1305::decrypt,
+    },
+];
+
+pub fn lookup(
+    algorithm: &str,
+    mode: Option<&str>,
+    key_length: u32,
+) -> Result<&'static Cipher, String> {
+    CIPHERS
+        .iter()
+        .find(|cipher| {
+            cipher.algorithm == algorithm
+                && (mode.is_none() || mode == Some(cipher.mode))
+                && cipher.key_length == key_length
+        })
+        .ok_or_else(|| {
+            format!(
+                "unsupported cipher: algorithm {}, mode {}, key length {}, supported: {}",
+                algorithm,
+                mode.unwrap_or("default"),
+                key_length,
+                supported().join(", ")
+            )
+        })
+}
+
+/// Names of the registered ciphers, e.g. "AES-GCM-256"
+pub fn supported() -> Vec<String> {
+    CIPHERS
+        .iter()
+        .map(|cipher| format!("{}-{}-{}", cipher.algorithm, cipher.mode, cipher.key_length))
+        .collect()
+}
+
+impl Cipher {
+    fn check(&self, key: &[u8], iv: &[u8]) -> Result<(), String> {
+        if key.len() * 8 != self.key_length as usize {
+            return Err(format!(
+                "{}-{} needs a {} bits key, got {} bits",
+                self.algorithm,
+                self.mode,
+                self.key_length,
+                key.len() * 8
+            ));
+        }
+        if iv.len() != self.iv_length {
+            return Err(format!(
+                "{}-{} needs a {} bytes iv, got {} bytes",
+                self.algorithm,
+                self.mode,
+                self.iv_length,
+                iv.len()
+            ));
+        }
+        Ok(())
+    }
+
+    pub fn encrypt(&self, data: &[u8], key: &[u8], iv: &[u8]) -> Result<Vec<u8>, String> {
+        self.check(key, iv)?;
+        (self.encrypt)(data, key, iv)
+    }
+
+    pub fn decrypt(&self, data: &[u8], key: &[u8], iv: &[u8]) -> Result<Vec<u8>, String> {
+        self.check(key, iv)?;
+        (self.decrypt)(data, key, iv)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lookup() {
+        assert_eq!(lookup("AES", None, 256).unwrap().mode, "GCM");
+        assert_eq!(lookup("AES", Some("CBC"), 256).unwrap().iv_length, 16);
+        assert!(lookup("AES", Some("CTR"), 256).is_err());
+        assert!(lookup("AES", None, 128).is_err());
+        assert!(lookup("ChaCha20-Poly1305", None, 256).is_ok());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let key = [1u8; 32];
+        for cipher in CIPHERS.iter() {
+            let iv = vec![2u8; cipher.iv_length];
+            let encrypted_data = cipher.encrypt(b"test_data", &key, &iv).unwrap();
+            assert_eq!(
+                cipher.decrypt(&encrypted_data, &key, &iv).unwrap(),
+                b"test_data".to_vec()
+            );
+        }
+
+        let cipher = lookup("AES", Some("GCM"), 256).unwrap();
+        assert!(cipher
+            .encrypt(b"test_data", &key[..16], &[0u8; 12])
+            .is_err());
+        assert!(cipher.decrypt(b"test_data", &key, &[0u8; 16]).is_err());
+    }
+}
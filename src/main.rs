@@ -6,6 +6,9 @@
 use clap::{App, Arg};
 use resources::*;
 use shadow_rs::shadow;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
 
 mod attestation_agent;
 mod client_api;
@@ -32,6 +35,11 @@ async fn main() {
         build::BUILD_TIME
     );
     info!("Verdictd info: {}", version);
+    #[cfg(feature = "mock-rats-tls")]
+    warn!(
+        "INSECURE: built with mock-rats-tls, sessions are neither attested nor encrypted, \
+         for testing only"
+    );
 
     match resources::opa::default() {
         Ok(_) => {}
@@ -41,6 +49,14 @@ async fn main() {
         }
     }
 
+    match resources::bundle::default() {
+        Ok(_) => {}
+        Err(e) => {
+            error!("bundle: {}", e);
+            return;
+        }
+    }
+
     match gpg::default() {
         Ok(_) => {}
         Err(e) => {
@@ -57,7 +73,39 @@ async fn main() {
         }
     }
 
-    let matches = App::new("verdictd")
+    match token::default() {
+        Ok(_) => {}
+        Err(e) => {
+            error!("token: {}", e);
+            return;
+        }
+    }
+
+    match secret::default() {
+        Ok(_) => {}
+        Err(e) => {
+            error!("secret: {}", e);
+            return;
+        }
+    }
+
+    match catalog::default() {
+        Ok(_) => {}
+        Err(e) => {
+            error!("catalog: {}", e);
+            return;
+        }
+    }
+
+    match audit::default() {
+        Ok(_) => {}
+        Err(e) => {
+            error!("audit: {}", e);
+            return;
+        }
+    }
+
+    let app = App::new("verdictd")
         .version(version.as_str())
         .long_version(version.as_str())
         .author("Inclavare-Containers Team")
@@ -103,14 +151,86 @@ async fn main() {
                 .long("mutual")
                 .help("Work in mutual mode"),
         )
+        .arg(
+            Arg::with_name("max_sessions")
+                .long("max-sessions")
+                .value_name("number")
+                .help("Max number of concurrent attestation sessions")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("idle_timeout")
+                .long("idle-timeout")
+                .value_name("seconds")
+                .help("Close an attestation session idle for this long")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("session_timeout")
+                .long("session-timeout")
+                .value_name("seconds")
+                .help("Close an attestation session lasting for this long")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("shutdown_timeout")
+                .long("shutdown-timeout")
+                .value_name("seconds")
+                .help("Time given to in-flight attestation sessions on shutdown")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("upstream")
+                .long("upstream")
+                .value_name("sockaddr")
+                .help("Fetch the keys and resources not held locally from this upstream verdictd")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("upstream_cache")
+                .long("upstream-cache")
+                .value_name("none|keys|resources|all")
+                .help("Kinds of items fetched from the upstream verdictd kept in memory")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("upstream_cache_ttl")
+                .long("upstream-cache-ttl")
+                .value_name("seconds")
+                .help("How long an item fetched from the upstream verdictd is cached")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("policy_engine")
+                .long("policy-engine")
+                .value_name("opa|rego")
+                .help("Policy engine evaluating the OPA policies, opa (Go libopa) or rego (native)")
+                .takes_value(true),
+        )
         .arg(
             Arg::with_name("client_api")
                 .long("client-api")
                 .value_name("client_api")
                 .help("Specify the client API's listen addr")
                 .takes_value(true),
+        );
+    #[cfg(feature = "insecure-transport")]
+    let app = app
+        .arg(
+            Arg::with_name("insecure_unix_socket")
+                .long("insecure-unix-socket")
+                .value_name("path")
+                .help("INSECURE: also serve the attestation protocol on a Unix socket without attestation")
+                .takes_value(true),
         )
-        .get_matches();
+        .arg(
+            Arg::with_name("insecure_evidence")
+                .long("insecure-evidence")
+                .value_name("file")
+                .help("JSON file with the synthetic evidence of the insecure Unix socket's peers")
+                .takes_value(true),
+        );
+    let matches = app.get_matches();
 
     let sockaddr = match matches.is_present("listen") {
         true => matches.value_of("listen").unwrap().to_string(),
@@ -134,12 +254,142 @@ async fn main() {
     };
 
     let mutual = matches.is_present("mutual");
-    std::thread::spawn(move || {
-        info!("Listen addr: {}", sockaddr);
-        attestation_agent::rats_tls::server(
-            &sockaddr, tls_type, crypto, attester, verifier, mutual,
-        );
+
+    let mut config = attestation_agent::rats_tls::ServerConfig {
+        listen: sockaddr,
+        ..Default::default()
+    };
+    match matches.value_of("max_sessions").map(|v| v.parse::<usize>()) {
+        Some(Ok(0)) | Some(Err(_)) => {
+            error!("--max-sessions must be a positive number");
+            return;
+        }
+        Some(Ok(v)) => config.max_sessions = v,
+        None => {}
+    }
+    for (name, timeout) in [
+        ("idle_timeout", &mut config.idle_timeout),
+        ("session_timeout", &mut config.session_timeout),
+        ("shutdown_timeout", &mut config.shutdown_timeout),
+    ] {
+        match matches.value_of(name).map(|v| v.parse::<u64>()) {
+            Some(Ok(0)) | Some(Err(_)) => {
+                error!(
+                    "--{} must be a positive number of seconds",
+                    name.replace('_', "-")
+                );
+                return;
+            }
+            Some(Ok(v)) => *timeout = Duration::from_secs(v),
+            None => {}
+        }
+    }
+
+    if let Some(engine) = matches.value_of("policy_engine") {
+        if let Err(e) = policy_engine::set_engine(engine) {
+            error!("--policy-engine: {}", e);
+            return;
+        }
+    }
+
+    if let Some(addr) = matches.value_of("upstream") {
+        let mut upstream = attestation_agent::upstream::UpstreamConfig {
+            addr: addr.to_string(),
+            tls_type: Some(tls_type.clone()),
+            crypto: Some(crypto.clone()),
+            attester: Some(attester.clone()),
+            verifier: Some(verifier.clone()),
+            ..Default::default()
+        };
+        if let Some(cache) = matches.value_of("upstream_cache") {
+            match attestation_agent::upstream::CachePolicy::parse(cache) {
+                Ok(cache) => upstream.cache = cache,
+                Err(e) => {
+                    error!("--upstream-cache: {}", e);
+                    return;
+                }
+            }
+        }
+        match matches
+            .value_of("upstream_cache_ttl")
+            .map(|v| v.parse::<u64>())
+        {
+            Some(Ok(v)) => upstream.cache_ttl = Duration::from_secs(v),
+            Some(Err(_)) => {
+                error!("--upstream-cache-ttl must be a number of seconds");
+                return;
+            }
+            None => {}
+        }
+        attestation_agent::upstream::configure(Some(upstream));
+    }
+
+    // Stop both listeners on SIGTERM or Ctrl-C
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    let mut sigterm = match signal(SignalKind::terminate()) {
+        Ok(sigterm) => sigterm,
+        Err(e) => {
+            error!("register SIGTERM handler failed: {}", e);
+            return;
+        }
+    };
+    tokio::spawn(async move {
+        tokio::select! {
+            _ = sigterm.recv() => info!("Received SIGTERM, shutting down"),
+            _ = tokio::signal::ctrl_c() => info!("Received SIGINT, shutting down"),
+        }
+        let _ = shutdown_tx.send(true);
     });
+    let shutdown = |mut rx: watch::Receiver<bool>| async move {
+        while !*rx.borrow() {
+            if rx.changed().await.is_err() {
+                break;
+            }
+        }
+    };
+
+    #[cfg(feature = "insecure-transport")]
+    if let Some(path) = matches.value_of("insecure_unix_socket") {
+        use attestation_agent::transport::insecure;
+
+        let evidence = match matches.value_of("insecure_evidence") {
+            Some(file) => match insecure::SyntheticEvidence::load(file) {
+                Ok(evidence) => Some(evidence),
+                Err(e) => {
+                    error!("insecure evidence: {}", e);
+                    return;
+                }
+            },
+            None => None,
+        };
+        let insecure_server =
+            insecure::server(path.to_string(), evidence, shutdown(shutdown_rx.clone()));
+        tokio::spawn(async move {
+            if let Err(e) = insecure_server.await {
+                error!("Launch insecure attestation service failed with: {}", e);
+            }
+        });
+    }
+
+    info!("Listen addr: {}", config.listen);
+    let attestation_server = attestation_agent::rats_tls::server(
+        config,
+        tls_type,
+        crypto,
+        attester,
+        verifier,
+        mutual,
+        shutdown(shutdown_rx.clone()),
+    );
+    // Don't keep running with the client API only
+    let attestation_server = async {
+        let res = attestation_server.await;
+        if let Err(e) = &res {
+            error!("Launch attestation service failed with: {}", e);
+            std::process::exit(1);
+        }
+        res
+    };
 
     // Launch client API gRPC server
     let client_api = match matches.is_present("client_api") {
@@ -147,8 +397,13 @@ async fn main() {
         false => "[::1]:60000".to_string(),
     };
     info!("Listen client API server addr: {}", client_api);
-    let client_api_server = client_api::api::server(&client_api);
-    match client_api_server.await {
+    let client_api_server = client_api::api::server(&client_api, shutdown(shutdown_rx));
+
+    let (attestation_res, client_api_res) = tokio::join!(attestation_server, client_api_server);
+    if attestation_res.is_ok() {
+        info!("Attestation service stopped");
+    }
+    match client_api_res {
         Ok(_) => info!("Success"),
         Err(e) => info!("Launch client API service failed with: {}", e.to_string()),
     }
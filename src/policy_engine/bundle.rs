@@ -0,0 +1,334 @@
+//! OPA bundles: a tar.gz of Rego modules, `data.json` documents and an
+//! optional `.manifest`, evaluated together as one policy.
+//!
+//! A `data.json` is the document at its directory, `tenants/a/data.json` is
+//! `data.tenants.a`. The decision rules are queried from the package named by
+//! `metadata.entrypoint` of the manifest, `policy` if it's not set:
+//!
+//! ```json
+//! {
+//!     "revision": "2023-06-01",
+//!     "roots": ["tenants", "lib"],
+//!     "metadata": {"entrypoint": "tenants/a"}
+//! }
+//! ```
+use super::{PolicyModule, DEFAULT_ENTRYPOINT};
+use crate::resources::audit;
+use flate2::read::GzDecoder;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::io::Read;
+use tar::{Archive, EntryType};
+
+/// Largest uncompressed bundle accepted
+pub const MAX_SIZE: u64 = 64 << 20;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    #[serde(default)]
+    pub revision: String,
+    /// Paths under `data` the bundle's packages and documents must be in,
+    /// anywhere if it's empty
+    #[serde(default)]
+    pub roots: Vec<String>,
+    #[serde(default)]
+    pub metadata: Map<String, Value>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bundle {
+    pub manifest: Manifest,
+    /// Modules named by their path in the archive
+    pub modules: Vec<PolicyModule>,
+    /// All `data.json` documents merged at their paths
+    pub data: Value,
+    /// Package of the decision rules, e.g. `tenants.a`
+    pub entrypoint: String,
+    /// Hex encoded SHA-256 of the archive
+    pub hash: String,
+}
+
+/// Path of an archive entry without `./` and `/` prefixes, `..` is refused
+fn entry_path(path: &str) -> Result<Vec<String>, String> {
+    let parts: Vec<String> = path
+        .split('/')
+        .filter(|part| !part.is_empty() && *part != ".")
+        .map(String::from)
+        .collect();
+    match parts.iter().any(|part| part == "..") {
+        true => Err(format!("bundle path {} leaves the bundle", path)),
+        false => Ok(parts),
+    }
+}
+
+/// Merge `value` into `document`, objects are merged key by key
+fn merge(document: &mut Value, value: Value, path: &[String]) -> Result<(), String> {
+    match (document, value) {
+        (Value::Object(document), Value::Object(value)) => {
+            for (key, value) in value {
+                let mut child_path = path.to_vec();
+                child_path.push(key.clone());
+                match document.get_mut(&key) {
+                    Some(child) => merge(child, value, &child_path)?,
+                    None => {
+                        document.insert(key, value);
+                    }
+                }
+            }
+            Ok(())
+        }
+        _ => Err(format!("data.{} is set twice", path.join("."))),
+    }
+}
+
+/// Whether `path` is under one of `roots`, everything is without roots
+fn in_roots(roots: &[String], path: &[String]) -> bool {
+    roots.is_empty()
+        || roots.iter().any(|root| {
+            let root: Vec<&str> = root.split('/').filter(|part| !part.is_empty()).collect();
+            root.len() <= path.len() && root.iter().zip(path).all(|(a, b)| a == b)
+        })
+}
+
+impl Bundle {
+    pub fn from_archive(archive: &[u8]) -> Result<Self, String> {
+        let mut manifest = None;
+        let mut modules = Vec::new();
+        let mut documents: Vec<(Vec<String>, Value)> = Vec::new();
+        let mut size = 0;
+
+        let mut tar = Archive::new(GzDecoder::new(archive));
+        let entries = tar
+            .entries()
+            .map_err(|e| format!("read bundle failed: {}", e))?;
+        for entry in entries {
+            let mut entry = entry.map_err(|e| format!("read bundle failed: {}", e))?;
+            let name = entry
+                .path()
+                .map_err(|e| format!("read bundle failed: {}", e))?
+                .to_string_lossy()
+                .to_string();
+            match entry.header().entry_type() {
+                EntryType::Directory => continue,
+                EntryType::Regular => {}
+                _ => return Err(format!("bundle entry {} isn't a regular file", name)),
+            }
+
+            size += entry.size();
+            if size > MAX_SIZE {
+                return Err(format!("bundle is larger than {} bytes", MAX_SIZE));
+            }
+            let mut content = Vec::new();
+            entry
+                .read_to_end(&mut content)
+                .map_err(|e| format!("read {} failed: {}", name, e))?;
+
+            let mut path = entry_path(&name)?;
+            let file = path.pop().unwrap_or_default();
+            match file.as_str() {
+                ".manifest" if path.is_empty() => {
+                    manifest = Some(
+                        serde_json::from_slice::<Manifest>(&content)
+                            .map_err(|e| format!("parse .manifest failed: {}", e))?,
+                    )
+                }
+                ".signatures.json" if path.is_empty() => {
+                    warn!("bundle signatures aren't verified")
+                }
+                "data.json" => {
+                    let document = serde_json::from_slice(&content)
+                        .map_err(|e| format!("parse {} failed: {}", name, e))?;
+                    documents.push((path, document));
+                }
+                "data.yaml" | "data.yml" => {
+                    return Err(format!("{} isn't supported, use data.json", name))
+                }
+                _ if file.ends_with(".rego") => {
+                    let source =
+                        String::from_utf8(content).map_err(|_| format!("{} isn't UTF-8", name))?;
+                    path.push(file);
+                    modules.push(PolicyModule {
+                        file: path.join("/"),
+                        source,
+                    });
+                }
+                _ => {}
+            }
+        }
+
+        let manifest = manifest.unwrap_or_default();
+        if modules.is_empty() {
+            return Err("bundle has no Rego module".to_string());
+        }
+
+        let mut data = Value::Object(Map::new());
+        for (path, document) in documents {
+            if !in_roots(&manifest.roots, &path) {
+                return Err(format!(
+                    "data.{} is outside the bundle roots",
+                    path.join(".")
+                ));
+            }
+            let document = path.iter().rev().fold(document, |value, key| {
+                let mut object = Map::new();
+                object.insert(key.clone(), value);
+                Value::Object(object)
+            });
+            merge(&mut data, document, &[])?;
+        }
+
+        for module in modules.iter() {
+            let package = package_of(&module.source)
+                .ok_or_else(|| format!("{} has no package declaration", module.file))?;
+            if !in_roots(&manifest.roots, &package) {
+                return Err(format!(
+                    "package {} of {} is outside the bundle roots",
+                    package.join("."),
+                    module.file
+                ));
+            }
+        }
+
+        let entrypoint = match manifest.metadata.get("entrypoint") {
+            None => DEFAULT_ENTRYPOINT.to_string(),
+            Some(Value::String(entrypoint)) => entrypoint
+                .trim_start_matches("data/")
+                .trim_start_matches("data.")
+                .replace('/', "."),
+            Some(_) => return Err("manifest metadata.entrypoint isn't a string".to_string()),
+        };
+
+        Ok(Bundle {
+            manifest,
+            modules,
+            data,
+            entrypoint,
+            hash: audit::digest(archive),
+        })
+    }
+}
+
+/// Package path of a module, from its first `package` declaration
+fn package_of(source: &str) -> Option<Vec<String>> {
+    source
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or_default().trim())
+        .find_map(|line| line.strip_prefix("package "))
+        .map(|package| package.trim().split('.').map(String::from).collect())
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+
+    /// tar.gz of `files`, path and content
+    pub fn archive(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        for (path, content) in files {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, path, content.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    pub const LIB: &str = "package lib.sgx\n\nsvn_ok(svn) { svn >= data.lib.sgx.svn }\n";
+    pub const TENANT: &str = r#"package tenants.a
+
+import data.lib.sgx
+
+default allow = false
+
+allow { sgx.svn_ok(input.svn); input.mrSigner == data.tenants.a.mrSigner }
+
+deny_reasons[reason] {
+    not sgx.svn_ok(input.svn)
+    reason := sprintf("svn %v is lower than %v", [input.svn, data.lib.sgx.svn])
+}
+
+claims := {"tenant": "a"}
+"#;
+
+    /// Bundle of a tenant policy sharing the SGX library
+    pub fn tenant_bundle() -> Vec<u8> {
+        archive(&[
+            (
+                ".manifest",
+                r#"{"revision": "r1", "roots": ["lib", "tenants/a"], "metadata": {"entrypoint": "tenants/a"}}"#,
+            ),
+            ("lib/sgx.rego", LIB),
+            ("lib/sgx/data.json", r#"{"svn": 2}"#),
+            ("./tenants/a/policy.rego", TENANT),
+            ("tenants/a/data.json", r#"{"mrSigner": "55"}"#),
+            ("README.md", "ignored"),
+        ])
+    }
+
+    #[test]
+    fn test_from_archive() {
+        let archive = tenant_bundle();
+        let bundle = Bundle::from_archive(&archive).unwrap();
+        assert_eq!(bundle.manifest.revision, "r1");
+        assert_eq!(bundle.entrypoint, "tenants.a");
+        assert_eq!(
+            bundle
+                .modules
+                .iter()
+                .map(|m| m.file.as_str())
+                .collect::<Vec<&str>>(),
+            vec!["lib/sgx.rego", "tenants/a/policy.rego"]
+        );
+        assert_eq!(
+            bundle.data,
+            serde_json::json!({"lib": {"sgx": {"svn": 2}}, "tenants": {"a": {"mrSigner": "55"}}})
+        );
+        assert_eq!(bundle.hash, audit::digest(&archive));
+
+        // A single module without manifest is queried as data.policy
+        let bundle = Bundle::from_archive(&archive_of_policy()).unwrap();
+        assert_eq!(bundle.entrypoint, DEFAULT_ENTRYPOINT);
+        assert_eq!(bundle.data, serde_json::json!({"svn": 1}));
+    }
+
+    fn archive_of_policy() -> Vec<u8> {
+        archive(&[
+            (
+                "policy.rego",
+                "package policy\nallow { input.svn >= data.svn }",
+            ),
+            ("data.json", r#"{"svn": 1}"#),
+        ])
+    }
+
+    #[test]
+    fn test_from_archive_errors() {
+        let errors = [
+            archive(&[("data.json", "{}")]),
+            archive(&[("p.rego", "package policy"), ("data.json", "{")]),
+            archive(&[
+                ("p.rego", "package policy"),
+                ("data.json", r#"{"a": 1}"#),
+                ("a/data.json", "{}"),
+            ]),
+            archive(&[
+                (".manifest", r#"{"roots": ["tenants"]}"#),
+                ("p.rego", "package policy"),
+            ]),
+            b"not a bundle".to_vec(),
+        ];
+        for archive in errors.iter() {
+            assert!(Bundle::from_archive(archive).is_err());
+        }
+        assert!(entry_path("a/../../policy.rego").is_err());
+        assert_eq!(
+            entry_path("./a//b.rego"),
+            Ok(vec!["a".to_string(), "b.rego".to_string()])
+        );
+    }
+}
@@ -0,0 +1,361 @@
+//! Cache of policy files and prepared policies.
+//!
+//! Policy and reference files, and bundles, are read once and kept with
+//! their SHA-256 until the client API rewrites them or their size, inode,
+//! modification or change time differ on disk. An edit within the timestamp
+//! granularity keeps the size and times of the file. So a file modified less
+//! than a second before it was read is read and hashed again on the next
+//! load.
+//!
+//! Prepared policies are keyed by the engine and the hashes of the policy
+//! and reference contents, or of the bundle, so an attestation only
+//! evaluates the input against an already compiled policy.
+use super::bundle::Bundle;
+use super::{PolicyEngine, PreparedPolicy};
+use crate::resources::{audit, bundle, file, opa};
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs;
+use std::os::unix::fs::MetadataExt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// Most prepared policies kept, the least recently used one is evicted
+pub const CAPACITY: usize = 64;
+
+/// Content of a policy or reference with its hex encoded SHA-256
+#[derive(Debug, Clone)]
+pub struct Source {
+    pub content: Arc<String>,
+    pub hash: String,
+}
+
+impl Source {
+    pub fn new(content: String) -> Self {
+        Source {
+            hash: audit::digest(content.as_bytes()),
+            content: Arc::new(content),
+        }
+    }
+}
+
+/// Timestamps within this of the read may hide a later edit
+const GRANULARITY: Duration = Duration::from_secs(1);
+
+/// What identifies a version of a file on disk
+#[derive(Debug, Clone, PartialEq)]
+struct Stamp {
+    modified: Option<SystemTime>,
+    len: u64,
+    ino: u64,
+    ctime: (i64, i64),
+    read: SystemTime,
+}
+
+impl Stamp {
+    fn new(metadata: &fs::Metadata) -> Self {
+        Stamp {
+            modified: metadata.modified().ok(),
+            len: metadata.len(),
+            ino: metadata.ino(),
+            ctime: (metadata.ctime(), metadata.ctime_nsec()),
+            read: SystemTime::now(),
+        }
+    }
+
+    /// The file still has the content read with `self`
+    fn unchanged(&self, current: &Stamp) -> bool {
+        let settled = match self.modified {
+            Some(modified) => modified + GRANULARITY <= self.read,
+            None => false,
+        };
+        settled
+            && self.modified == current.modified
+            && self.len == current.len
+            && self.ino == current.ino
+            && self.ctime == current.ctime
+    }
+}
+
+struct File {
+    stamp: Stamp,
+    source: Source,
+}
+
+struct BundleFile {
+    stamp: Stamp,
+    bundle: Arc<Bundle>,
+}
+
+struct Entry {
+    prepared: Arc<dyn PreparedPolicy>,
+    last_used: u64,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Metrics {
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub entries: usize,
+    pub hit_rate: f64,
+}
+
+#[derive(Default)]
+struct Prepared {
+    entries: HashMap<(&'static str, String, String), Entry>,
+    clock: u64,
+    hits: u64,
+    misses: u64,
+    evictions: u64,
+}
+
+lazy_static! {
+    static ref FILES: Mutex<HashMap<String, File>> = Mutex::new(HashMap::new());
+    static ref BUNDLES: Mutex<HashMap<String, BundleFile>> = Mutex::new(HashMap::new());
+    static ref PREPARED: Mutex<Prepared> = Mutex::new(Prepared::default());
+}
+
+/// Content of the policy or reference file `name`, read again only if it
+/// changed on disk
+pub fn load(name: &str) -> Result<Source, String> {
+    let lock = opa::FILE_LOCK.read();
+    assert_eq!(*lock, 0);
+
+    let path = String::from(opa::OPA_PATH) + name;
+    let metadata = fs::metadata(&path).map_err(|e| format!("read {} failed: {}", name, e))?;
+    let stamp = Stamp::new(&metadata);
+
+    let mut files = FILES.lock();
+    if let Some(file) = files.get(name) {
+        if file.stamp.unchanged(&stamp) {
+            return Ok(file.source.clone());
+        }
+    }
+
+    let mut source = Source::new(file::export_string(&path)?);
+    if let Some(file) = files.get(name) {
+        // Same content, keep the prepared policies of the old one
+        if file.source.hash == source.hash {
+            source = file.source.clone();
+        }
+    }
+    files.insert(
+        name.to_string(),
+        File {
+            stamp,
+            source: source.clone(),
+        },
+    );
+    Ok(source)
+}
+
+/// The bundle `name`, parsed again only if it changed on disk
+pub fn load_bundle(name: &str) -> Result<Arc<Bundle>, String> {
+    let lock = bundle::FILE_LOCK.read();
+    assert_eq!(*lock, 0);
+
+    let path = bundle::path(name);
+    let metadata =
+        fs::metadata(&path).map_err(|e| format!("read bundle {} failed: {}", name, e))?;
+    let stamp = Stamp::new(&metadata);
+
+    let mut bundles = BUNDLES.lock();
+    if let Some(file) = bundles.get(name) {
+        if file.stamp.unchanged(&stamp) {
+            return Ok(file.bundle.clone());
+        }
+    }
+
+    let archive = file::export_raw(&path)?;
+    let parsed = Arc::new(Bundle::from_archive(&archive)?);
+    bundles.insert(
+        name.to_string(),
+        BundleFile {
+            stamp,
+            bundle: parsed.clone(),
+        },
+    );
+    Ok(parsed)
+}
+
+/// `policy` compiled by `engine` with the reference `data`
+pub fn prepare(
+    engine: &dyn PolicyEngine,
+    policy: &Source,
+    data: &Source,
+) -> Result<Arc<dyn PreparedPolicy>, String> {
+    let key = (engine.name(), policy.hash.clone(), data.hash.clone());
+    prepare_with(key, || engine.prepare(&policy.content, &data.content))
+}
+
+/// The modules of `bundle` compiled by `engine` with its data
+pub fn prepare_bundle(
+    engine: &dyn PolicyEngine,
+    bundle: &Bundle,
+) -> Result<Arc<dyn PreparedPolicy>, String> {
+    // No policy has the hash of a bundle, the reference hash is left empty
+    let key = (engine.name(), bundle.hash.clone(), String::new());
+    prepare_with(key, || {
+        engine.prepare_modules(
+            &bundle.modules,
+            &bundle.data.to_string(),
+            &bundle.entrypoint,
+        )
+    })
+}
+
+fn prepare_with(
+    key: (&'static str, String, String),
+    prepare: impl FnOnce() -> Result<Box<dyn PreparedPolicy>, String>,
+) -> Result<Arc<dyn PreparedPolicy>, String> {
+    {
+        let mut cache = PREPARED.lock();
+        cache.clock += 1;
+        let clock = cache.clock;
+        if let Some(entry) = cache.entries.get_mut(&key) {
+            entry.last_used = clock;
+            let prepared = entry.prepared.clone();
+            cache.hits += 1;
+            return Ok(prepared);
+        }
+        cache.misses += 1;
+    }
+
+    // Compile without holding the lock, a concurrent miss of the same key
+    // only compiles twice
+    let prepared: Arc<dyn PreparedPolicy> = Arc::from(prepare()?);
+
+    let mut cache = PREPARED.lock();
+    if cache.entries.len() >= CAPACITY && !cache.entries.contains_key(&key) {
+        let oldest = cache
+            .entries
+            .iter()
+            .min_by_key(|(_, entry)| entry.last_used)
+            .map(|(key, _)| key.clone());
+        if let Some(oldest) = oldest {
+            cache.entries.remove(&oldest);
+            cache.evictions += 1;
+        }
+    }
+    let last_used = cache.clock;
+    cache.entries.insert(
+        key,
+        Entry {
+            prepared: prepared.clone(),
+            last_used,
+        },
+    );
+    Ok(prepared)
+}
+
+/// Forget the file `name` and the policies prepared from its last content,
+/// called when it's rewritten
+pub fn invalidate(name: &str) {
+    let file = FILES.lock().remove(name);
+    if let Some(file) = file {
+        forget(&file.source.hash);
+    }
+}
+
+/// Forget the bundle `name` and the policy prepared from it
+pub fn invalidate_bundle(name: &str) {
+    let file = BUNDLES.lock().remove(name);
+    if let Some(file) = file {
+        forget(&file.bundle.hash);
+    }
+}
+
+/// Drop the prepared policies compiled from the content of `hash`
+fn forget(hash: &str) {
+    PREPARED
+        .lock()
+        .entries
+        .retain(|(_, policy, data), _| policy != hash && data != hash);
+}
+
+pub fn metrics() -> Metrics {
+    let cache = PREPARED.lock();
+    let lookups = cache.hits + cache.misses;
+    Metrics {
+        hits: cache.hits,
+        misses: cache.misses,
+        evictions: cache.evictions,
+        entries: cache.entries.len(),
+        hit_rate: match lookups {
+            0 => 0.0,
+            _ => cache.hits as f64 / lookups as f64,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy_engine::rego::RegoEngine;
+
+    #[test]
+    fn test_prepare() {
+        let engine = RegoEngine;
+        let policy = Source::new(format!("{}\n# {}", opa::SGX_POLICY, "cache test"));
+        let data = Source::new(opa::SGX_DATA.to_string());
+
+        let before = metrics();
+        let prepared = prepare(&engine, &policy, &data).unwrap();
+        let again = prepare(&engine, &policy, &data).unwrap();
+        assert!(Arc::ptr_eq(&prepared, &again));
+        let after = metrics();
+        assert!(after.hits > before.hits);
+        assert!(after.misses > before.misses);
+
+        let decision = again.make_decision(r#"{"svn": 1}"#).unwrap();
+        assert!(!decision.allow);
+
+        let broken = Source::new("package policy\nallow {".to_string());
+        assert!(prepare(&engine, &broken, &data).is_err());
+    }
+
+    #[test]
+    fn test_load() {
+        opa::default().unwrap();
+        let name = "verdictd-cache-test";
+        opa::set_reference(name, r#"{"svn": 1}"#).unwrap();
+        let first = load(name).unwrap();
+        assert_eq!(*first.content, r#"{"svn": 1}"#);
+
+        // Rewritten through set_reference, the old content is forgotten
+        opa::set_reference(name, r#"{"svn": 2}"#).unwrap();
+        assert_eq!(*load(name).unwrap().content, r#"{"svn": 2}"#);
+
+        // Changed on disk behind verdictd's back
+        fs::write(String::from(opa::OPA_PATH) + name, r#"{"svn": 30}"#).unwrap();
+        let changed = load(name).unwrap();
+        assert_eq!(*changed.content, r#"{"svn": 30}"#);
+        assert_ne!(changed.hash, first.hash);
+
+        // Same size and modification time as the content already read
+        let path = String::from(opa::OPA_PATH) + name;
+        let modified = fs::metadata(&path).unwrap().modified().unwrap();
+        let file = fs::OpenOptions::new().write(true).open(&path).unwrap();
+        fs::write(&path, r#"{"svn": 31}"#).unwrap();
+        file.set_modified(modified).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().modified().unwrap(), modified);
+        assert_eq!(*load(name).unwrap().content, r#"{"svn": 31}"#);
+
+        // A settled file is served from the cache. Moving the read time
+        // stands in for waiting GRANULARITY after the last edit.
+        let mut files = FILES.lock();
+        let file = files.get_mut(name).unwrap();
+        file.stamp.read = file.stamp.modified.unwrap() + GRANULARITY;
+        let hash = file.source.hash.clone();
+        drop(files);
+        assert_eq!(load(name).unwrap().hash, hash);
+
+        let _ = fs::remove_file(String::from(opa::OPA_PATH) + name);
+        invalidate(name);
+        assert!(load(name).is_err());
+    }
+}
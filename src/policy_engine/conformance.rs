@@ -0,0 +1,385 @@
+//! Decisions every policy engine must agree on, for the shipped policies
+use super::bundle::tests::tenant_bundle;
+use super::bundle::Bundle;
+use super::PolicyEngine;
+use crate::resources::opa::*;
+use serde_json::{json, Map, Value};
+
+struct Case {
+    name: &'static str,
+    policy: &'static str,
+    data: Value,
+    input: Value,
+    allow: bool,
+}
+
+fn sgx_data() -> Value {
+    serde_json::from_str(SGX_DATA).unwrap()
+}
+
+fn sgx_input() -> Value {
+    json!({
+        "mrEnclave": "ee",
+        "mrSigner": "55",
+        "productId": 1,
+        "svn": 2,
+        "debug": false,
+        "tcbStatus": "UpToDate",
+    })
+}
+
+fn tdx_input() -> Value {
+    json!({
+        "mrTd": "aa",
+        "mrConfigId": "00",
+        "mrOwner": "00",
+        "rtmr0": "r0",
+        "rtmr1": "r1",
+        "teeTcbSvn": [3, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
+        "debug": false,
+    })
+}
+
+fn csv_input() -> Value {
+    json!({
+        "measure": "mm",
+        "vmId": "v1",
+        "policy": {"apiMajor": 1, "apiMinor": 0},
+        "debug": false,
+        "migratable": true,
+    })
+}
+
+fn merge(mut base: Value, patch: Value) -> Value {
+    for (k, v) in patch.as_object().unwrap() {
+        base[k] = v.clone();
+    }
+    base
+}
+
+fn cases() -> Vec<Case> {
+    let tdx_data: Value = serde_json::from_str(TDX_DATA).unwrap();
+    let csv_data: Value = serde_json::from_str(CSV_DATA).unwrap();
+    let auth_data = json!({
+        "kids": {"k1": [{"tee": "sgx", "mrEnclave": "ee"}, {"tee": "tdx"}]},
+        "resources": {},
+        "secrets": {"s1": [{"svn": 2}]},
+    });
+    let svn16 = json!([2, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+
+    let case = |name, policy, data, input, allow| Case {
+        name,
+        policy,
+        data,
+        input,
+        allow,
+    };
+    vec![
+        case("sgx default", SGX_POLICY, sgx_data(), sgx_input(), true),
+        case(
+            "sgx mrEnclave listed",
+            SGX_POLICY,
+            merge(
+                sgx_data(),
+                json!({"mrEnclave": ["dd", "ee"], "mrSigner": ["55"]}),
+            ),
+            sgx_input(),
+            true,
+        ),
+        case(
+            "sgx mrEnclave not listed",
+            SGX_POLICY,
+            merge(sgx_data(), json!({"mrEnclave": ["dd"]})),
+            sgx_input(),
+            false,
+        ),
+        case(
+            "sgx svn too low",
+            SGX_POLICY,
+            merge(sgx_data(), json!({"svn": 3})),
+            sgx_input(),
+            false,
+        ),
+        case(
+            "sgx debug denied",
+            SGX_POLICY,
+            sgx_data(),
+            merge(sgx_input(), json!({"debug": true})),
+            false,
+        ),
+        case(
+            "sgx debug allowed",
+            SGX_POLICY,
+            merge(sgx_data(), json!({"allowDebug": true})),
+            merge(sgx_input(), json!({"debug": true})),
+            true,
+        ),
+        case(
+            "sgx tcbStatus not listed",
+            SGX_POLICY,
+            merge(sgx_data(), json!({"tcbStatus": ["SWHardeningNeeded"]})),
+            sgx_input(),
+            false,
+        ),
+        case(
+            "sgx tcbStatus unknown",
+            SGX_POLICY,
+            merge(
+                sgx_data(),
+                json!({"tcbStatus": ["UpToDate", "SWHardeningNeeded"]}),
+            ),
+            merge(sgx_input(), json!({"tcbStatus": null})),
+            false,
+        ),
+        case(
+            "sgx tcbStatus accepted",
+            SGX_POLICY,
+            sgx_data(),
+            merge(sgx_input(), json!({"tcbStatus": null})),
+            true,
+        ),
+        case(
+            "sgx missing input",
+            SGX_POLICY,
+            sgx_data(),
+            json!({"mrEnclave": "ee"}),
+            false,
+        ),
+        case(
+            "tdx default",
+            TDX_POLICY,
+            tdx_data.clone(),
+            tdx_input(),
+            true,
+        ),
+        case(
+            "tdx rtmr and svn match",
+            TDX_POLICY,
+            merge(
+                tdx_data.clone(),
+                json!({"mrTd": ["aa"], "rtmr": {"rtmr0": "r0"}, "teeTcbSvn": svn16}),
+            ),
+            tdx_input(),
+            true,
+        ),
+        case(
+            "tdx rtmr mismatch",
+            TDX_POLICY,
+            merge(
+                tdx_data.clone(),
+                json!({"rtmr": {"rtmr0": "r0", "rtmr1": "xx"}}),
+            ),
+            tdx_input(),
+            false,
+        ),
+        case(
+            "tdx svn component too low",
+            TDX_POLICY,
+            merge(
+                tdx_data,
+                json!({"teeTcbSvn": [2, 1, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]}),
+            ),
+            tdx_input(),
+            false,
+        ),
+        case(
+            "csv default",
+            CSV_POLICY,
+            csv_data.clone(),
+            csv_input(),
+            true,
+        ),
+        case(
+            "csv measure not listed",
+            CSV_POLICY,
+            merge(csv_data.clone(), json!({"measure": ["m0"]})),
+            csv_input(),
+            false,
+        ),
+        case(
+            "csv migration denied",
+            CSV_POLICY,
+            merge(csv_data.clone(), json!({"allowMigration": false})),
+            csv_input(),
+            false,
+        ),
+        case(
+            "csv api too old",
+            CSV_POLICY,
+            merge(csv_data, json!({"apiMajor": 2, "vmId": ["v1"]})),
+            csv_input(),
+            false,
+        ),
+        case(
+            "auth unbound kid",
+            AUTH_POLICY,
+            auth_data.clone(),
+            json!({"kid": "k2", "tee": "csv", "claims": {}}),
+            true,
+        ),
+        case(
+            "auth unattested peer",
+            AUTH_POLICY,
+            auth_data.clone(),
+            json!({"kid": "k2", "tee": "none", "claims": {}}),
+            false,
+        ),
+        case(
+            "auth binding matches",
+            AUTH_POLICY,
+            auth_data.clone(),
+            json!({"kid": "k1", "tee": "sgx", "claims": {"mrEnclave": "ee"}}),
+            true,
+        ),
+        case(
+            "auth tee binding",
+            AUTH_POLICY,
+            auth_data.clone(),
+            json!({"kid": "k1", "tee": "tdx", "claims": {}}),
+            true,
+        ),
+        case(
+            "auth no binding matches",
+            AUTH_POLICY,
+            auth_data.clone(),
+            json!({"kid": "k1", "tee": "sgx", "claims": {"mrEnclave": "dd"}}),
+            false,
+        ),
+        case(
+            "auth secret binding",
+            AUTH_POLICY,
+            auth_data,
+            json!({"secret": "s1", "tee": "sgx", "claims": {"svn": 1}}),
+            false,
+        ),
+    ]
+}
+
+/// Deny reasons the shipped policies give, by case
+fn deny_reasons(name: &str) -> Option<Vec<&'static str>> {
+    let reasons = match name {
+        "sgx mrEnclave not listed" => vec!["mrEnclave ee isn't a reference value"],
+        "sgx svn too low" => vec!["svn 2 is lower than 3"],
+        "sgx debug denied" => vec!["debug enclaves aren't allowed"],
+        "sgx tcbStatus not listed" => vec!["tcbStatus UpToDate isn't allowed"],
+        "sgx missing input" => vec!["debug enclaves aren't allowed"],
+        "tdx rtmr mismatch" => vec!["rtmr1 r1 isn't the reference value"],
+        "tdx svn component too low" => vec!["teeTcbSvn is lower than the reference"],
+        "csv measure not listed" => vec!["measure mm isn't a reference value"],
+        "csv migration denied" => vec!["migratable guests aren't allowed"],
+        "csv api too old" => vec!["apiMajor 1 is lower than 2"],
+        "auth unattested peer" | "auth no binding matches" | "auth secret binding" => {
+            vec!["allow is false"]
+        }
+        _ => return None,
+    };
+    Some(reasons)
+}
+
+/// Check `engine` against all cases, including the deny reasons
+pub fn run(engine: &dyn PolicyEngine) {
+    for case in cases() {
+        let decision = engine
+            .make_decision(case.policy, &case.data.to_string(), &case.input.to_string())
+            .unwrap_or_else(|e| panic!("{}: {}", case.name, e));
+
+        assert_eq!(decision.allow, case.allow, "{}", case.name);
+        assert_eq!(
+            decision.deny_reasons.is_empty(),
+            case.allow,
+            "{}: {:?}",
+            case.name,
+            decision.deny_reasons
+        );
+        if let Some(reasons) = deny_reasons(case.name) {
+            assert_eq!(decision.deny_reasons, reasons, "{}", case.name);
+        }
+        assert_eq!(decision.claims, Map::new(), "{}", case.name);
+    }
+}
+
+/// Check `engine` against the tenant bundle, its modules import a library
+/// and read documents of several `data.json`
+pub fn run_bundle(engine: &dyn PolicyEngine) {
+    let bundle = Bundle::from_archive(&tenant_bundle()).unwrap();
+    assert_eq!(
+        engine.check_modules(&bundle.modules, &bundle.entrypoint),
+        vec![]
+    );
+    let prepared = engine
+        .prepare_modules(
+            &bundle.modules,
+            &bundle.data.to_string(),
+            &bundle.entrypoint,
+        )
+        .unwrap();
+
+    let decision = prepared
+        .make_decision(r#"{"svn": 1, "mrSigner": "55"}"#)
+        .unwrap();
+    assert!(!decision.allow);
+    assert_eq!(decision.deny_reasons, vec!["svn 1 is lower than 2"]);
+    assert_eq!(Value::Object(decision.claims), json!({"tenant": "a"}));
+
+    let decision = prepared
+        .make_decision(r#"{"svn": 2, "mrSigner": "55"}"#)
+        .unwrap();
+    assert!(decision.allow);
+
+    let decision = prepared
+        .make_decision(r#"{"svn": 2, "mrSigner": "66"}"#)
+        .unwrap();
+    assert_eq!(decision.deny_reasons, vec!["allow is false"]);
+}
+
+/// Claims and deny reasons of a policy defining them
+#[test]
+fn test_rego_decision() {
+    let policy = r#"
+package policy
+
+default allow = false
+
+allow {
+    input.svn >= data.svn
+}
+
+deny_reasons[reason] {
+    input.svn < data.svn
+    reason := sprintf("svn %d is lower than %d", [input.svn, data.svn])
+}
+
+claims := {"tenant": data.tenants[input.mrSigner], "svn": input.svn}
+"#;
+    let data = r#"{"svn": 2, "tenants": {"55": "a"}}"#;
+    let engine = super::rego::RegoEngine;
+
+    let decision = engine
+        .make_decision(policy, data, r#"{"svn": 1, "mrSigner": "55"}"#)
+        .unwrap();
+    assert!(!decision.allow);
+    assert_eq!(decision.deny_reasons, vec!["svn 1 is lower than 2"]);
+    assert_eq!(
+        Value::Object(decision.claims),
+        json!({"tenant": "a", "svn": 1})
+    );
+
+    let decision = engine
+        .make_decision(policy, data, r#"{"svn": 2, "mrSigner": "66"}"#)
+        .unwrap();
+    assert!(decision.allow);
+    assert_eq!(decision.claims, Map::new());
+}
+
+#[test]
+fn test_rego_conformance() {
+    run(&super::rego::RegoEngine);
+    run_bundle(&super::rego::RegoEngine);
+}
+
+#[cfg(feature = "opa-go")]
+#[test]
+fn test_opa_conformance() {
+    run(&super::opa::opa_engine::OpaEngine);
+    run_bundle(&super::opa::opa_engine::OpaEngine);
+}
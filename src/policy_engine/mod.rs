@@ -1 +1,236 @@
+pub mod bundle;
+pub mod cache;
 pub mod opa;
+pub mod rego;
+
+#[cfg(test)]
+mod conformance;
+
+use lazy_static::lazy_static;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+/// Problem found in a policy by `PolicyEngine::check`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Diagnostic {
+    pub file: String,
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}:{}: {}",
+            self.file, self.line, self.column, self.message
+        )
+    }
+}
+
+/// The rats-tls callback and the authorization take decisions from `allow`
+pub const MISSING_ALLOW: &str = "policy doesn't define the allow rule";
+
+/// Package the decision rules are queried from, `data.policy`, unless a
+/// bundle names another one
+pub const DEFAULT_ENTRYPOINT: &str = "policy";
+
+/// Rego source file of a policy, `file` is the name diagnostics refer to
+#[derive(Debug, Clone, PartialEq)]
+pub struct PolicyModule {
+    pub file: String,
+    pub source: String,
+}
+
+impl PolicyModule {
+    pub fn new(file: &str, source: &str) -> Self {
+        PolicyModule {
+            file: file.to_string(),
+            source: source.to_string(),
+        }
+    }
+}
+
+/// Outcome of a policy for one input, from the `allow`, `deny_reasons` and
+/// `claims` rules of the policy package:
+/// `{"allow": true, "deny_reasons": [], "claims": {}}`
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Decision {
+    pub allow: bool,
+    #[serde(default)]
+    pub deny_reasons: Vec<String>,
+    /// Annotations the policy attaches to the decision, e.g. the tenant
+    #[serde(default)]
+    pub claims: Map<String, Value>,
+}
+
+impl Decision {
+    /// Decision from the values of the policy rules, undefined rules are
+    /// missing or null. Any deny reason denies, a denial always has one.
+    pub fn from_rules(rules: &Map<String, Value>) -> Result<Self, String> {
+        let rule = |name| rules.get(name).filter(|value| !value.is_null());
+
+        let mut deny_reasons: Vec<String> = match rule("deny_reasons") {
+            None => Vec::new(),
+            Some(Value::String(reason)) => vec![reason.clone()],
+            Some(Value::Array(reasons)) => reasons
+                .iter()
+                .map(|reason| match reason {
+                    Value::String(reason) => reason.clone(),
+                    reason => reason.to_string(),
+                })
+                .collect(),
+            Some(_) => return Err("deny_reasons must be a set or an array".to_string()),
+        };
+        // Sets have no order, both engines report them sorted
+        deny_reasons.sort();
+        deny_reasons.dedup();
+
+        let claims = match rule("claims") {
+            None => Map::new(),
+            Some(Value::Object(claims)) => claims.clone(),
+            Some(_) => return Err("claims must be an object".to_string()),
+        };
+
+        let allow = rule("allow");
+        if deny_reasons.is_empty() && allow != Some(&Value::Bool(true)) {
+            deny_reasons.push(match allow {
+                Some(allow) => format!("allow is {}", allow),
+                None => "allow is undefined".to_string(),
+            });
+        }
+
+        Ok(Decision {
+            allow: deny_reasons.is_empty(),
+            deny_reasons,
+            claims,
+        })
+    }
+}
+
+/// Backend evaluating Rego policies.
+///
+/// `make_decision` takes the content of a policy, its reference data and the
+/// input, all as text, and returns the `Decision` of the policy. A policy is
+/// one module or, from a bundle, several modules whose decision rules are in
+/// the `entrypoint` package, e.g. `policy` or `tenants.sgx`.
+pub trait PolicyEngine: Sync {
+    fn name(&self) -> &'static str;
+
+    /// Compile `modules` together without data, nothing is returned if
+    /// they're valid
+    fn check_modules(&self, modules: &[PolicyModule], entrypoint: &str) -> Vec<Diagnostic>;
+
+    /// Compile `modules` with the reference `data` for repeated decisions
+    fn prepare_modules(
+        &self,
+        modules: &[PolicyModule],
+        data: &str,
+        entrypoint: &str,
+    ) -> Result<Box<dyn PreparedPolicy>, String>;
+
+    /// Compile the single module `policy` without data. `file` is the name
+    /// the diagnostics refer to.
+    fn check(&self, file: &str, policy: &str) -> Vec<Diagnostic> {
+        self.check_modules(&[PolicyModule::new(file, policy)], DEFAULT_ENTRYPOINT)
+    }
+
+    /// Compile the single module `policy` with its reference `data`
+    fn prepare(&self, policy: &str, data: &str) -> Result<Box<dyn PreparedPolicy>, String> {
+        self.prepare_modules(
+            &[PolicyModule::new("policy.rego", policy)],
+            data,
+            DEFAULT_ENTRYPOINT,
+        )
+    }
+
+    fn make_decision(&self, policy: &str, data: &str, input: &str) -> Result<Decision, String> {
+        self.prepare(policy, data)?.make_decision(input)
+    }
+}
+
+/// A policy compiled by a `PolicyEngine`, shared by concurrent attestations
+pub trait PreparedPolicy: Send + Sync {
+    fn make_decision(&self, input: &str) -> Result<Decision, String>;
+}
+
+#[cfg(feature = "opa-go")]
+static OPA: opa::opa_engine::OpaEngine = opa::opa_engine::OpaEngine;
+static REGO: rego::RegoEngine = rego::RegoEngine;
+
+/// Engine used unless another one is configured, libopa if it's built in
+#[cfg(feature = "opa-go")]
+pub const DEFAULT_ENGINE: &str = "opa";
+#[cfg(not(feature = "opa-go"))]
+pub const DEFAULT_ENGINE: &str = "rego";
+
+lazy_static! {
+    static ref ENGINE: RwLock<&'static dyn PolicyEngine> =
+        RwLock::new(by_name(DEFAULT_ENGINE).unwrap());
+}
+
+fn by_name(name: &str) -> Option<&'static dyn PolicyEngine> {
+    match name {
+        #[cfg(feature = "opa-go")]
+        "opa" => Some(&OPA),
+        "rego" => Some(&REGO),
+        _ => None,
+    }
+}
+
+/// Select the engine making all decisions: "opa" for the Go libopa backend
+/// (needs the `opa-go` feature) or "rego" for the native interpreter
+pub fn set_engine(name: &str) -> Result<(), String> {
+    let engine = by_name(name).ok_or_else(|| match name {
+        "opa" => "policy engine opa isn't built in, rebuild with the opa-go feature".to_string(),
+        _ => format!("policy engine {} isn't one of opa, rego", name),
+    })?;
+    info!("policy engine: {}", engine.name());
+    *ENGINE.write() = engine;
+    Ok(())
+}
+
+pub fn engine() -> &'static dyn PolicyEngine {
+    *ENGINE.read()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn decision(rules: Value) -> Result<Decision, String> {
+        Decision::from_rules(rules.as_object().unwrap())
+    }
+
+    #[test]
+    fn test_decision() {
+        assert_eq!(
+            decision(json!({"allow": true, "claims": {"tenant": "a"}})),
+            Ok(Decision {
+                allow: true,
+                deny_reasons: Vec::new(),
+                claims: json!({"tenant": "a"}).as_object().unwrap().clone(),
+            })
+        );
+
+        let denied = decision(json!({"allow": true, "deny_reasons": ["b", "a", 1, "a"]})).unwrap();
+        assert!(!denied.allow);
+        assert_eq!(denied.deny_reasons, vec!["1", "a", "b"]);
+
+        assert_eq!(
+            decision(json!({"allow": false})).unwrap().deny_reasons,
+            vec!["allow is false"]
+        );
+        assert_eq!(
+            decision(json!({"allow": null, "deny_reasons": []}))
+                .unwrap()
+                .deny_reasons,
+            vec!["allow is undefined"]
+        );
+        assert!(decision(json!({"allow": true, "claims": [1]})).is_err());
+        assert!(decision(json!({"allow": true, "deny_reasons": {}})).is_err());
+    }
+}
@@ -76,6 +76,16 @@ extern "C" {
 
 extern char* makeDecisionGo(GoString policy, GoString data, GoString input);
 
+// Compile the policy and store it under *handle, returns the error or NULL.
+//
+extern char* prepareGo(GoString modules, GoString data, GoString entrypoint, unsigned long long* handle);
+extern char* evalGo(unsigned long long handle, GoString input);
+extern void releaseGo(unsigned long long handle);
+
+// Parse and compile the policy, returns the JSON array of its diagnostics.
+//
+extern char* checkGo(GoString modules, GoString entrypoint);
+
 #ifdef __cplusplus
 }
 #endif
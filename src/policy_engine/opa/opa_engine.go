@@ -5,74 +5,246 @@ import "C"
 import (
 	"context"
 	"encoding/json"
+	"errors"
 	"strings"
+	"sync"
 
+	"github.com/open-policy-agent/opa/ast"
 	"github.com/open-policy-agent/opa/rego"
 	"github.com/open-policy-agent/opa/storage/inmem"
 )
 
-//export makeDecisionGo
-func makeDecisionGo(policy string, data string, input string) *C.char {
-	// Deserialize the message in json format
-	input_map := make(map[string]interface{})
-	err := json.Unmarshal([]byte(input), &input_map)
-	if err != nil {
-		return C.CString("Unmarshal input error.")
+// A policy compiled against its reference data, shared by concurrent evaluations
+type preparedPolicy struct {
+	query rego.PreparedEvalQuery
+}
+
+var (
+	preparedLock sync.RWMutex
+	prepared     = make(map[uint64]*preparedPolicy)
+	nextHandle   uint64
+)
+
+// A Rego source file, the file name is what diagnostics refer to
+type module struct {
+	File   string `json:"file"`
+	Source string `json:"source"`
+}
+
+// Parse the JSON array of modules passed by rust
+func parseModules(modules string) ([]module, error) {
+	mods := []module{}
+	if err := json.Unmarshal([]byte(modules), &mods); err != nil {
+		return nil, errors.New("Unmarshal modules error.")
 	}
+	return mods, nil
+}
 
+// Compile the modules with the data, decisions are queried from
+// data.<entrypoint>. The modules are copied by Unmarshal, they're kept by the
+// prepared query beyond this call.
+func prepare(mods []module, data string, entrypoint string) (*preparedPolicy, error) {
 	data_map := make(map[string]interface{})
-	err2 := json.Unmarshal([]byte(data), &data_map)
-	if err2 != nil {
+	err := json.Unmarshal([]byte(data), &data_map)
+	if err != nil {
 		// Handle error.
-		return C.CString("Unmarshal data error.")
+		return nil, errors.New("Unmarshal data error.")
 	}
 	// Manually create the storage layer. inmem.NewFromObject returns an
 	// in-memory store containing the supplied data.
 	store := inmem.NewFromObject(data_map)
 
 	// Construct a Rego object that can be prepared or evaluated.
-	r := rego.New(
-		rego.Query("input;data.policy"),
-		rego.Module("demo.rego", policy),
+	options := []func(*rego.Rego){
+		rego.Query("data." + entrypoint),
 		rego.Store(store),
-	)
+	}
+	for _, m := range mods {
+		options = append(options, rego.Module(m.File, m.Source))
+	}
+	r := rego.New(options...)
 
 	// Create a prepared query that can be evaluated.
-	ctx := context.Background()
-	query, err := r.PrepareForEval(ctx)
+	query, err := r.PrepareForEval(context.Background())
 	if err != nil {
-		return C.CString(err.Error())
+		return nil, err
 	}
 
-	// Make opa query
-	rs, err := query.Eval(ctx, rego.EvalInput(input_map))
+	return &preparedPolicy{query}, nil
+}
+
+func (p *preparedPolicy) eval(input string) string {
+	// Deserialize the message in json format
+	input_map := make(map[string]interface{})
+	err := json.Unmarshal([]byte(input), &input_map)
 	if err != nil {
-		return C.CString(err.Error())
+		return "Unmarshal input error."
 	}
 
-	// Transform the processed decision into the format rust hopes for
-	inputOPA := rs[0].Expressions[0].Value.(map[string]interface{})
-	dataOPA := rs[0].Expressions[1].Value.(map[string]interface{})
-	parseInfo := make(map[string]interface{})
+	// Make opa query
+	rs, err := p.query.Eval(context.Background(), rego.EvalInput(input_map))
+	if err != nil {
+		return err.Error()
+	}
 
-	for k, v := range inputOPA {
-		value := [2]interface{}{v, data_map[k]}
-		parseInfo[k] = value
+	// An undefined package or a policy without rules yields no result
+	if len(rs) == 0 || len(rs[0].Expressions) == 0 {
+		return "Policy evaluation has no result."
 	}
 
+	// Only the rules the decision is made of, rust interprets them
+	dataOPA, ok := rs[0].Expressions[0].Value.(map[string]interface{})
+	if !ok {
+		return "Policy evaluation result isn't an object."
+	}
 	decisionMap := make(map[string]interface{})
-	decisionMap["parseInfo"] = parseInfo
-	decisionMap["allow"] = dataOPA["allow"]
+	for _, rule := range []string{"allow", "deny_reasons", "claims"} {
+		if value, ok := dataOPA[rule]; ok {
+			decisionMap[rule] = value
+		}
+	}
 
 	decision, err := json.Marshal(decisionMap)
 	if err != nil {
-		return C.CString("Unmarshal decision error.")
+		return "Marshal decision error."
 	}
-	decision_str := string(decision)
-	res := strings.Replace(decision_str, "\\u003e", ">", -1)
-	res = strings.Replace(res, "\\u003c", "<", -1)
-	
-	return C.CString(res)
+
+	return string(decision)
+}
+
+//export makeDecisionGo
+func makeDecisionGo(policy string, data string, input string) *C.char {
+	p, err := prepare([]module{{"demo.rego", string([]byte(policy))}}, data, "policy")
+	if err != nil {
+		return C.CString(err.Error())
+	}
+	return C.CString(p.eval(input))
+}
+
+// Compile the JSON array of modules and store it under *handle, returns the
+// error or NULL.
+//export prepareGo
+func prepareGo(modules string, data string, entrypoint string, handle *C.ulonglong) *C.char {
+	mods, err := parseModules(modules)
+	if err != nil {
+		return C.CString(err.Error())
+	}
+	p, err := prepare(mods, data, string([]byte(entrypoint)))
+	if err != nil {
+		return C.CString(err.Error())
+	}
+
+	preparedLock.Lock()
+	defer preparedLock.Unlock()
+	nextHandle++
+	prepared[nextHandle] = p
+	*handle = C.ulonglong(nextHandle)
+	return nil
+}
+
+//export evalGo
+func evalGo(handle C.ulonglong, input string) *C.char {
+	preparedLock.RLock()
+	p, ok := prepared[uint64(handle)]
+	preparedLock.RUnlock()
+	if !ok {
+		return C.CString("Prepared policy not found.")
+	}
+	return C.CString(p.eval(input))
+}
+
+//export releaseGo
+func releaseGo(handle C.ulonglong) {
+	preparedLock.Lock()
+	defer preparedLock.Unlock()
+	delete(prepared, uint64(handle))
+}
+
+type diagnostic struct {
+	File    string `json:"file"`
+	Line    int    `json:"line"`
+	Column  int    `json:"column"`
+	Message string `json:"message"`
+}
+
+// Parse and compile the JSON array of modules together, returns the JSON
+// array of their diagnostics.
+//export checkGo
+func checkGo(modules string, entrypoint string) *C.char {
+	diagnostics := []diagnostic{}
+
+	mods, err := parseModules(modules)
+	parsed := make(map[string]*ast.Module)
+	for _, m := range mods {
+		if err != nil {
+			break
+		}
+		module, e := ast.ParseModule(m.File, m.Source)
+		if e == nil && module == nil {
+			e = errors.New(m.File + ": empty policy")
+		}
+		err = e
+		parsed[m.File] = module
+	}
+	if err == nil {
+		compiler := ast.NewCompiler()
+		compiler.Compile(parsed)
+		if compiler.Failed() {
+			err = compiler.Errors
+		}
+	}
+
+	if errs, ok := err.(ast.Errors); ok {
+		for _, e := range errs {
+			d := diagnostic{Message: e.Message}
+			if e.Location != nil {
+				d.File = e.Location.File
+				d.Line = e.Location.Row
+				d.Column = e.Location.Col
+			}
+			diagnostics = append(diagnostics, d)
+		}
+	} else if err != nil {
+		diagnostics = append(diagnostics, diagnostic{Message: err.Error()})
+	} else {
+		// Decisions are queried as data.<entrypoint>.allow
+		first := ""
+		if len(mods) > 0 {
+			first = mods[0].File
+		}
+		path := "data." + entrypoint
+		found, allow := "", false
+		for _, m := range mods {
+			module := parsed[m.File]
+			if module.Package.Path.String() != path {
+				continue
+			}
+			if found == "" {
+				found = m.File
+			}
+			for _, rule := range module.Rules {
+				if rule.Head.Name.String() == "allow" {
+					allow = true
+				}
+			}
+		}
+		if found == "" {
+			message := "no module is package " + entrypoint
+			if len(mods) == 1 {
+				message = "package is " + strings.TrimPrefix(parsed[first].Package.Path.String(), "data.") +
+					", attestation policies must be package " + entrypoint
+			}
+			diagnostics = append(diagnostics, diagnostic{File: first, Line: 1, Column: 1, Message: message})
+			found = first
+		}
+		if !allow {
+			diagnostics = append(diagnostics, diagnostic{File: found, Line: 1, Column: 1,
+				Message: "policy doesn't define the allow rule"})
+		}
+	}
+
+	res, _ := json.Marshal(diagnostics)
+	return C.CString(string(res))
 }
 
 func main() {}
@@ -1,14 +1,37 @@
-use crate::resources::opa;
+use crate::policy_engine;
+use crate::policy_engine::cache::{self, Source};
+use crate::policy_engine::Decision;
+#[cfg(feature = "opa-go")]
+use crate::policy_engine::{Diagnostic, PolicyModule, PreparedPolicy};
+#[cfg(feature = "opa-go")]
 use std::ffi::CStr;
-use std::os::raw::c_char;
+#[cfg(feature = "opa-go")]
+use std::os::raw::{c_char, c_void};
 
 // Link import cgo function
+#[cfg(feature = "opa-go")]
 #[link(name = "opa")]
 extern "C" {
     pub fn makeDecisionGo(policy: GoString, data: GoString, input: GoString) -> *mut c_char;
+    pub fn prepareGo(
+        modules: GoString,
+        data: GoString,
+        entrypoint: GoString,
+        handle: *mut u64,
+    ) -> *mut c_char;
+    pub fn evalGo(handle: u64, input: GoString) -> *mut c_char;
+    pub fn releaseGo(handle: u64);
+    pub fn checkGo(modules: GoString, entrypoint: GoString) -> *mut c_char;
+}
+
+// The decision is allocated by C.CString
+#[cfg(feature = "opa-go")]
+extern "C" {
+    fn free(p: *mut c_void);
 }
 
 /// String structure passed into cgo
+#[cfg(feature = "opa-go")]
 #[derive(Debug)]
 #[repr(C)]
 pub struct GoString {
@@ -16,34 +39,146 @@ pub struct GoString {
     pub n: isize,
 }
 
-// According to message and policy, the decision is made by opa
-pub fn make_decision(policy_name: &str, data_name: &str, input: &str) -> Result<String, String> {
-    // Get the content of policy from policy_name
-    let policy = opa::export(policy_name)?;
+#[cfg(feature = "opa-go")]
+impl GoString {
+    fn new(s: &str) -> Self {
+        GoString {
+            p: s.as_ptr() as *const c_char,
+            n: s.len() as isize,
+        }
+    }
+}
 
-    let policy_go = GoString {
-        p: policy.as_ptr() as *const i8,
-        n: policy.len() as isize,
-    };
+/// Take the string returned by cgo
+#[cfg(feature = "opa-go")]
+unsafe fn take_go_string(buf: *mut c_char) -> Result<String, String> {
+    let str = CStr::from_ptr(buf)
+        .to_str()
+        .map(|str| str.to_string())
+        .map_err(|e| e.to_string());
+    free(buf as *mut c_void);
+    str
+}
 
-    let data = opa::export(data_name)?;
-    let data_go = GoString {
-        p: data.as_ptr() as *const i8,
-        n: data.len() as isize,
-    };
+/// Decision from the rules returned by cgo, anything else is an error
+#[cfg(feature = "opa-go")]
+fn decision(res: String) -> Result<Decision, String> {
+    match serde_json::from_str(&res) {
+        Ok(rules) => Decision::from_rules(&rules),
+        Err(_) => Err(res),
+    }
+}
 
-    let input_go = GoString {
-        p: input.as_ptr() as *const i8,
-        n: input.len() as isize,
-    };
+/// JSON array of `modules` passed to cgo
+#[cfg(feature = "opa-go")]
+fn modules_json(modules: &[PolicyModule]) -> String {
+    serde_json::Value::Array(
+        modules
+            .iter()
+            .map(|module| serde_json::json!({"file": module.file, "source": module.source}))
+            .collect(),
+    )
+    .to_string()
+}
 
-    // Call the function exported by cgo and process the returned decision
-    let decision_buf: *mut c_char = unsafe { makeDecisionGo(policy_go, data_go, input_go) };
-    let decision_str: &CStr = unsafe { CStr::from_ptr(decision_buf) };
-    decision_str
-        .to_str()
-        .map_err(|e| e.to_string())
-        .and_then(|str| Ok(str.to_string()))
+/// Go OPA backend linked from libopa
+#[cfg(feature = "opa-go")]
+pub struct OpaEngine;
+
+#[cfg(feature = "opa-go")]
+impl policy_engine::PolicyEngine for OpaEngine {
+    fn name(&self) -> &'static str {
+        "opa"
+    }
+
+    fn check_modules(&self, modules: &[PolicyModule], entrypoint: &str) -> Vec<Diagnostic> {
+        let modules = modules_json(modules);
+        let diagnostics =
+            unsafe { take_go_string(checkGo(GoString::new(&modules), GoString::new(entrypoint))) };
+        diagnostics
+            .and_then(|diagnostics| {
+                serde_json::from_str(&diagnostics)
+                    .map_err(|_| format!("parse diagnostics failed: {}", diagnostics))
+            })
+            .unwrap_or_else(|e| {
+                vec![Diagnostic {
+                    file: String::new(),
+                    line: 0,
+                    column: 0,
+                    message: e,
+                }]
+            })
+    }
+
+    fn prepare_modules(
+        &self,
+        modules: &[PolicyModule],
+        data: &str,
+        entrypoint: &str,
+    ) -> Result<Box<dyn PreparedPolicy>, String> {
+        let modules = modules_json(modules);
+        let mut handle = 0;
+        let error = unsafe {
+            prepareGo(
+                GoString::new(&modules),
+                GoString::new(data),
+                GoString::new(entrypoint),
+                &mut handle,
+            )
+        };
+        if !error.is_null() {
+            return Err(unsafe { take_go_string(error) }?);
+        }
+        Ok(Box::new(PreparedGo { handle }))
+    }
+
+    fn make_decision(&self, policy: &str, data: &str, input: &str) -> Result<Decision, String> {
+        // Call the function exported by cgo and process the returned decision
+        unsafe {
+            take_go_string(makeDecisionGo(
+                GoString::new(policy),
+                GoString::new(data),
+                GoString::new(input),
+            ))
+        }
+        .and_then(decision)
+    }
+}
+
+/// Query prepared by libopa, released when dropped
+#[cfg(feature = "opa-go")]
+struct PreparedGo {
+    handle: u64,
+}
+
+#[cfg(feature = "opa-go")]
+impl PreparedPolicy for PreparedGo {
+    fn make_decision(&self, input: &str) -> Result<Decision, String> {
+        unsafe { take_go_string(evalGo(self.handle, GoString::new(input))) }.and_then(decision)
+    }
+}
+
+#[cfg(feature = "opa-go")]
+impl Drop for PreparedGo {
+    fn drop(&mut self) {
+        unsafe { releaseGo(self.handle) };
+    }
+}
+
+// According to message and policy, the decision is made by the policy engine
+pub fn make_decision(policy_name: &str, data_name: &str, input: &str) -> Result<Decision, String> {
+    // Get the content of policy from policy_name
+    let policy = cache::load(policy_name)?;
+    let data = cache::load(data_name)?;
+
+    cache::prepare(policy_engine::engine(), &policy, &data)?.make_decision(input)
+}
+
+/// Decision of the stored bundle `bundle_name` on `input`
+pub fn make_decision_bundle(bundle_name: &str, input: &str) -> Result<Decision, String> {
+    let bundle = cache::load_bundle(bundle_name)?;
+
+    cache::prepare_bundle(policy_engine::engine(), &bundle)?.make_decision(input)
 }
 
 pub fn make_decision_ext(
@@ -54,39 +189,18 @@ pub fn make_decision_ext(
     reference_content: &str,
     reference_remote: bool,
     input: &str,
-) -> Result<String, String> {
+) -> Result<Decision, String> {
     let policy = if policy_remote == true {
-        policy_content.to_owned()
+        Source::new(policy_content.to_owned())
     } else {
-        opa::export(policy_name).unwrap()
+        cache::load(policy_name)?
     };
 
     let reference = if reference_remote == true {
-        reference_content.to_owned()
+        Source::new(reference_content.to_owned())
     } else {
-        opa::export(reference_name).unwrap()
+        cache::load(reference_name)?
     };
 
-    let policy_go = GoString {
-        p: policy.as_str().as_ptr() as *const i8,
-        n: policy.as_str().len() as isize,
-    };
-
-    let reference_go = GoString {
-        p: reference.as_str().as_ptr() as *const i8,
-        n: reference.as_str().len() as isize,
-    };
-
-    let input_go = GoString {
-        p: input.as_ptr() as *const i8,
-        n: input.len() as isize,
-    };
-
-    // Call the function exported by cgo and process the returned decision
-    let decision_buf: *mut c_char = unsafe { makeDecisionGo(policy_go, reference_go, input_go) };
-    let decision_str: &CStr = unsafe { CStr::from_ptr(decision_buf) };
-    decision_str
-        .to_str()
-        .map_err(|e| e.to_string())
-        .and_then(|str| Ok(str.to_string()))
+    cache::prepare(policy_engine::engine(), &policy, &reference)?.make_decision(input)
 }
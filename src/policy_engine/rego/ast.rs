@@ -0,0 +1,84 @@
+use serde_json::Value;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Module {
+    /// Path of the package, e.g. `["policy"]`
+    pub package: Vec<String>,
+    pub imports: Vec<Import>,
+    pub rules: Vec<Rule>,
+}
+
+/// `import data.lib.x as y`, the alias is the last key if omitted
+#[derive(Debug, Clone, PartialEq)]
+pub struct Import {
+    /// Path starting with `data` or `input`
+    pub path: Vec<String>,
+    pub alias: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub name: String,
+    pub kind: RuleKind,
+    pub body: Vec<Literal>,
+    pub line: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuleKind {
+    /// `default name = value`
+    Default(Term),
+    /// `name = value { body }`, the value is `true` if omitted
+    Complete(Term),
+    /// `name[key] { body }`, a set of keys
+    Set(Term),
+    /// `name[key] = value { body }`
+    Object(Term, Term),
+    /// `name(args) = value { body }`
+    Function(Vec<Term>, Term),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Expr(Term),
+    Not(Term),
+    /// `some x, y` declares local variables
+    Some(Vec<String>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Term {
+    /// null, boolean, number or string
+    Scalar(Value),
+    Var(String),
+    /// `head.a[b]`, the path has a `Scalar` for every `.a`
+    Ref(Box<Term>, Vec<Term>),
+    Array(Vec<Term>),
+    Object(Vec<(Term, Term)>),
+    Call(String, Vec<Term>),
+    /// `[term | body]`
+    Comprehension(Box<Term>, Vec<Literal>),
+    Binary(Op, Box<Term>, Box<Term>),
+    /// `var := term`
+    Assign(Box<Term>, Box<Term>),
+    /// `term = term`
+    Unify(Box<Term>, Box<Term>),
+}
+
+/// Wildcards `_` are renamed to unique variables starting with this prefix
+pub const WILDCARD: &str = "_$";
@@ -0,0 +1,228 @@
+use super::eval::{compare, number};
+use serde_json::Value;
+use std::cmp::Ordering;
+
+/// Builtin functions supported by the native engine
+pub const BUILTINS: [&str; 21] = [
+    "count",
+    "sum",
+    "max",
+    "min",
+    "abs",
+    "startswith",
+    "endswith",
+    "contains",
+    "lower",
+    "upper",
+    "concat",
+    "split",
+    "to_number",
+    "is_string",
+    "is_number",
+    "is_boolean",
+    "is_array",
+    "is_object",
+    "is_null",
+    "object.get",
+    "sprintf",
+];
+
+pub fn arity(name: &str) -> usize {
+    match name {
+        "object.get" => 3,
+        "startswith" | "endswith" | "contains" | "concat" | "split" | "sprintf" => 2,
+        _ => 1,
+    }
+}
+
+fn string<'a>(name: &str, args: &'a [Value], i: usize) -> Result<&'a str, String> {
+    args[i]
+        .as_str()
+        .ok_or_else(|| format!("{}: operand {} must be a string", name, i + 1))
+}
+
+fn array<'a>(name: &str, args: &'a [Value], i: usize) -> Result<&'a Vec<Value>, String> {
+    args[i]
+        .as_array()
+        .ok_or_else(|| format!("{}: operand {} must be an array", name, i + 1))
+}
+
+/// `format` with its `%v`, `%s` and `%d` verbs replaced by `values`
+fn sprintf(format: &str, values: &[Value]) -> Result<String, String> {
+    let mut res = String::new();
+    let mut values = values.iter();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            res.push(c);
+            continue;
+        }
+        let verb = chars.next();
+        if verb == Some('%') {
+            res.push('%');
+            continue;
+        }
+        let value = values
+            .next()
+            .ok_or_else(|| format!("sprintf: missing value for %{}", verb.unwrap_or(' ')))?;
+        match (verb, value) {
+            (Some('v') | Some('s'), Value::String(s)) => res.push_str(s),
+            (Some('v') | Some('s'), value) => res.push_str(&value.to_string()),
+            (Some('d'), Value::Number(n)) if n.is_i64() || n.is_u64() => {
+                res.push_str(&n.to_string())
+            }
+            (Some('d'), _) => return Err("sprintf: %d needs an integer".to_string()),
+            (verb, _) => {
+                return Err(format!(
+                    "sprintf: unsupported verb %{}",
+                    verb.map(String::from).unwrap_or_default()
+                ))
+            }
+        }
+    }
+    Ok(res)
+}
+
+/// Call the builtin `name`, `None` if the result is undefined
+pub fn call(name: &str, args: &[Value]) -> Result<Option<Value>, String> {
+    if !BUILTINS.contains(&name) {
+        return Err(format!("function {} is undefined", name));
+    }
+    if args.len() != arity(name) {
+        return Err(format!(
+            "{}: expects {} arguments, got {}",
+            name,
+            arity(name),
+            args.len()
+        ));
+    }
+
+    let value = match name {
+        "count" => match &args[0] {
+            Value::Array(items) => Value::from(items.len()),
+            Value::Object(object) => Value::from(object.len()),
+            Value::String(s) => Value::from(s.chars().count()),
+            _ => return Err("count: operand 1 must be an array, object or string".to_string()),
+        },
+        "sum" => {
+            let mut sum = 0.0;
+            for item in array(name, args, 0)? {
+                sum += item
+                    .as_f64()
+                    .ok_or_else(|| "sum: operand 1 must be an array of numbers".to_string())?;
+            }
+            number(sum)?
+        }
+        "max" | "min" => {
+            let wanted = match name {
+                "max" => Ordering::Greater,
+                _ => Ordering::Less,
+            };
+            let items = array(name, args, 0)?;
+            match items.iter().reduce(|a, b| match compare(b, a) == wanted {
+                true => b,
+                false => a,
+            }) {
+                Some(value) => value.clone(),
+                None => return Ok(None),
+            }
+        }
+        "abs" => match &args[0] {
+            Value::Number(n) => match n.as_i64() {
+                Some(i) => match i.checked_abs() {
+                    Some(abs) => Value::from(abs),
+                    None => return Err(format!("abs: {} overflows", i)),
+                },
+                None => number(n.as_f64().unwrap_or(0.0).abs())?,
+            },
+            _ => return Err("abs: operand 1 must be a number".to_string()),
+        },
+        "startswith" => Value::Bool(string(name, args, 0)?.starts_with(string(name, args, 1)?)),
+        "endswith" => Value::Bool(string(name, args, 0)?.ends_with(string(name, args, 1)?)),
+        "contains" => Value::Bool(string(name, args, 0)?.contains(string(name, args, 1)?)),
+        "lower" => Value::from(string(name, args, 0)?.to_lowercase()),
+        "upper" => Value::from(string(name, args, 0)?.to_uppercase()),
+        "concat" => {
+            let separator = string(name, args, 0)?;
+            let parts = array(name, args, 1)?
+                .iter()
+                .map(|item| {
+                    item.as_str()
+                        .ok_or_else(|| "concat: operand 2 must be an array of strings".to_string())
+                })
+                .collect::<Result<Vec<&str>, String>>()?;
+            Value::from(parts.join(separator))
+        }
+        "split" => Value::from(
+            string(name, args, 0)?
+                .split(string(name, args, 1)?)
+                .collect::<Vec<&str>>(),
+        ),
+        "to_number" => match &args[0] {
+            Value::Null => Value::from(0),
+            Value::Bool(b) => Value::from(*b as i64),
+            Value::Number(n) => Value::Number(n.clone()),
+            Value::String(s) => serde_json::from_str::<serde_json::Number>(s)
+                .map(Value::Number)
+                .map_err(|_| format!("to_number: invalid number {:?}", s))?,
+            _ => {
+                return Err(
+                    "to_number: operand 1 must be a string, number, boolean or null".to_string(),
+                )
+            }
+        },
+        "is_string" => Value::Bool(args[0].is_string()),
+        "is_number" => Value::Bool(args[0].is_number()),
+        "is_boolean" => Value::Bool(args[0].is_boolean()),
+        "is_array" => Value::Bool(args[0].is_array()),
+        "is_object" => Value::Bool(args[0].is_object()),
+        "is_null" => Value::Bool(args[0].is_null()),
+        "object.get" => {
+            let object = args[0]
+                .as_object()
+                .ok_or_else(|| "object.get: operand 1 must be an object".to_string())?;
+            let key = string(name, args, 1)?;
+            object.get(key).unwrap_or(&args[2]).clone()
+        }
+        "sprintf" => Value::from(sprintf(string(name, args, 0)?, array(name, args, 1)?)?),
+        _ => unreachable!(),
+    };
+
+    Ok(Some(value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_builtins() {
+        assert_eq!(call("count", &[json!([1, 2])]), Ok(Some(json!(2))));
+        assert_eq!(call("sum", &[json!([1, 2.5])]), Ok(Some(json!(3.5))));
+        assert_eq!(call("max", &[json!([1, 3, 2])]), Ok(Some(json!(3))));
+        assert_eq!(call("min", &[json!([])]), Ok(None));
+        assert_eq!(
+            call("concat", &[json!("-"), json!(["a", "b"])]),
+            Ok(Some(json!("a-b")))
+        );
+        assert_eq!(call("to_number", &[json!("12")]), Ok(Some(json!(12))));
+        assert_eq!(
+            call("object.get", &[json!({"a": 1}), json!("b"), json!(0)]),
+            Ok(Some(json!(0)))
+        );
+        assert!(call("count", &[json!(1)]).is_err());
+        assert_eq!(
+            call(
+                "sprintf",
+                &[json!("%s is %d%%, %v"), json!(["svn", 2, [1]])]
+            ),
+            Ok(Some(json!("svn is 2%, [1]")))
+        );
+        assert!(call("sprintf", &[json!("%d"), json!(["a"])]).is_err());
+        assert!(call("trim", &[json!("a"), json!(" ")]).is_err());
+        assert_eq!(call("abs", &[json!(-3)]), Ok(Some(json!(3))));
+        assert_eq!(call("abs", &[json!(-1.5)]), Ok(Some(json!(1.5))));
+        assert!(call("abs", &[json!(i64::MIN)]).is_err());
+    }
+}
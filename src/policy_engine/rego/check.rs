@@ -0,0 +1,306 @@
+use super::ast::*;
+use super::builtins;
+use super::eval::qualify;
+use super::parser;
+use crate::policy_engine::{Diagnostic, PolicyModule, MISSING_ALLOW};
+use std::collections::HashMap;
+
+/// Parse `modules` and check the calls and rule definitions across them,
+/// without data. The decision rules must be in the `entrypoint` package.
+pub fn check(modules: &[PolicyModule], entrypoint: &str) -> Vec<Diagnostic> {
+    let diagnostic = |file: &str, line, column, message| Diagnostic {
+        file: file.to_string(),
+        line,
+        column,
+        message,
+    };
+
+    let mut diagnostics = Vec::new();
+    let mut parsed: Vec<(&str, Module)> = Vec::new();
+    for module in modules.iter() {
+        match parser::parse(&module.source) {
+            Ok(ast) => parsed.push((&module.file, ast)),
+            Err(e) => diagnostics.push(diagnostic(&module.file, e.line, e.column, e.message)),
+        }
+    }
+    if !diagnostics.is_empty() {
+        return diagnostics;
+    }
+
+    let entrypoint: Vec<String> = entrypoint.split('.').map(String::from).collect();
+    if !parsed
+        .iter()
+        .any(|(_, module)| module.package == entrypoint)
+    {
+        let message = match parsed.as_slice() {
+            [(_, module)] => format!(
+                "package is {}, attestation policies must be package {}",
+                module.package.join("."),
+                entrypoint.join(".")
+            ),
+            _ => format!("no module is package {}", entrypoint.join(".")),
+        };
+        let file = parsed.first().map(|(file, _)| *file).unwrap_or_default();
+        diagnostics.push(diagnostic(file, 1, 1, message));
+    }
+
+    // Arity of every rule by its qualified name, None for rules that aren't
+    // functions
+    let mut rules: HashMap<String, (&str, &Rule, Option<usize>)> = HashMap::new();
+    for (file, module) in parsed.iter() {
+        for rule in module.rules.iter() {
+            let arity = match &rule.kind {
+                RuleKind::Default(_) => continue,
+                RuleKind::Function(args, _) => Some(args.len()),
+                _ => None,
+            };
+            let name = qualify(&module.package, &rule.name);
+            match rules.get(&name) {
+                Some((first_file, first, first_arity)) if *first_arity != arity => {
+                    diagnostics.push(diagnostic(
+                        file,
+                        rule.line,
+                        1,
+                        match first_file == file {
+                            true => format!(
+                                "rule {} conflicts with its definition at line {}",
+                                rule.name, first.line
+                            ),
+                            false => format!(
+                                "rule {} conflicts with its definition at {}:{}",
+                                rule.name, first_file, first.line
+                            ),
+                        },
+                    ));
+                }
+                Some(_) => {}
+                None => {
+                    rules.insert(name, (file, rule, arity));
+                }
+            }
+        }
+    }
+    for (file, module) in parsed.iter() {
+        for rule in module.rules.iter().filter(|rule| is_default(rule)) {
+            rules
+                .entry(qualify(&module.package, &rule.name))
+                .or_insert((file, rule, None));
+        }
+    }
+
+    for (file, module) in parsed.iter() {
+        for rule in module.rules.iter() {
+            let mut calls = Vec::new();
+            match &rule.kind {
+                RuleKind::Default(term) | RuleKind::Complete(term) | RuleKind::Set(term) => {
+                    collect_calls(term, &mut calls)
+                }
+                RuleKind::Object(key, term) => {
+                    collect_calls(key, &mut calls);
+                    collect_calls(term, &mut calls);
+                }
+                RuleKind::Function(args, term) => {
+                    args.iter().for_each(|arg| collect_calls(arg, &mut calls));
+                    collect_calls(term, &mut calls);
+                }
+            }
+            rule.body
+                .iter()
+                .for_each(|literal| collect_literal_calls(literal, &mut calls));
+
+            for (name, args) in calls {
+                let arity = match rules.get(&function_name(module, name)) {
+                    Some((_, _, Some(arity))) => Some(*arity),
+                    Some((_, _, None)) => {
+                        diagnostics.push(diagnostic(
+                            file,
+                            rule.line,
+                            1,
+                            format!("{} is a rule, not a function", name),
+                        ));
+                        continue;
+                    }
+                    None if builtins::BUILTINS.contains(&name) => Some(builtins::arity(name)),
+                    None => None,
+                };
+                match arity {
+                    Some(arity) if arity != args => diagnostics.push(diagnostic(
+                        file,
+                        rule.line,
+                        1,
+                        format!("{} takes {} arguments, got {}", name, arity, args),
+                    )),
+                    Some(_) => {}
+                    None => diagnostics.push(diagnostic(
+                        file,
+                        rule.line,
+                        1,
+                        format!("function {} is undefined", name),
+                    )),
+                }
+            }
+        }
+    }
+
+    if !rules.contains_key(&qualify(&entrypoint, "allow")) {
+        let file = parsed
+            .iter()
+            .find(|(_, module)| module.package == entrypoint)
+            .or_else(|| parsed.first())
+            .map(|(file, _)| *file)
+            .unwrap_or_default();
+        diagnostics.push(diagnostic(file, 1, 1, MISSING_ALLOW.to_string()));
+    }
+
+    diagnostics
+}
+
+/// Qualified name of the function `module` calls as `name`, like
+/// `policy.f` for `f` or `lib.f` for `data.lib.f` and imported `lib.f`
+fn function_name(module: &Module, name: &str) -> String {
+    let mut parts: Vec<String> = name.split('.').map(String::from).collect();
+    if parts.len() == 1 {
+        return qualify(&module.package, name);
+    }
+    if let Some(import) = module.imports.iter().find(|i| i.alias == parts[0]) {
+        parts.splice(0..1, import.path.iter().cloned());
+    }
+    match parts[0] == "data" {
+        true => parts[1..].join("."),
+        false => name.to_string(),
+    }
+}
+
+fn is_default(rule: &Rule) -> bool {
+    matches!(rule.kind, RuleKind::Default(_))
+}
+
+fn collect_literal_calls<'a>(literal: &'a Literal, calls: &mut Vec<(&'a str, usize)>) {
+    match literal {
+        Literal::Expr(term) | Literal::Not(term) => collect_calls(term, calls),
+        Literal::Some(_) => {}
+    }
+}
+
+/// Name and number of arguments of every call in `term`
+fn collect_calls<'a>(term: &'a Term, calls: &mut Vec<(&'a str, usize)>) {
+    match term {
+        Term::Scalar(_) | Term::Var(_) => {}
+        Term::Ref(head, path) => {
+            collect_calls(head, calls);
+            path.iter().for_each(|key| collect_calls(key, calls));
+        }
+        Term::Array(items) => items.iter().for_each(|item| collect_calls(item, calls)),
+        Term::Object(pairs) => pairs.iter().for_each(|(key, value)| {
+            collect_calls(key, calls);
+            collect_calls(value, calls);
+        }),
+        Term::Call(name, args) => {
+            calls.push((name, args.len()));
+            args.iter().for_each(|arg| collect_calls(arg, calls));
+        }
+        Term::Comprehension(term, body) => {
+            collect_calls(term, calls);
+            body.iter()
+                .for_each(|literal| collect_literal_calls(literal, calls));
+        }
+        Term::Binary(_, lhs, rhs) | Term::Assign(lhs, rhs) | Term::Unify(lhs, rhs) => {
+            collect_calls(lhs, calls);
+            collect_calls(rhs, calls);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resources::opa;
+
+    fn check_one(file: &str, policy: &str) -> Vec<Diagnostic> {
+        check(&[PolicyModule::new(file, policy)], "policy")
+    }
+
+    #[test]
+    fn test_check() {
+        for policy in [
+            opa::SGX_POLICY,
+            opa::TDX_POLICY,
+            opa::CSV_POLICY,
+            opa::AUTH_POLICY,
+        ] {
+            assert_eq!(check_one("policy.rego", policy), Vec::new());
+        }
+
+        let diagnostics = check_one(
+            "broken.rego",
+            "package policy\n\nallow {\n    input.a ==\n}",
+        );
+        assert_eq!(
+            diagnostics,
+            vec![Diagnostic {
+                file: "broken.rego".to_string(),
+                line: 5,
+                column: 1,
+                message: "unexpected \"}\"".to_string(),
+            }]
+        );
+
+        let diagnostics = check_one(
+            "p.rego",
+            "package policy\ndefault deny = false\nf(x) { x }\ndeny { f(1, 2); g(1); count(input.x) }",
+        );
+        let messages: Vec<&str> = diagnostics.iter().map(|d| d.message.as_str()).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "f takes 1 arguments, got 2",
+                "function g is undefined",
+                MISSING_ALLOW
+            ]
+        );
+        assert!(diagnostics.iter().take(2).all(|d| d.line == 4));
+    }
+
+    #[test]
+    fn test_check_modules() {
+        let lib = PolicyModule::new(
+            "lib/sgx.rego",
+            "package lib.sgx\n\nsvn_ok(svn) { svn >= data.lib.sgx.svn }\ntrusted = true",
+        );
+        let policy = PolicyModule::new(
+            "tenants/a.rego",
+            "package tenants.a\n\nimport data.lib.sgx\n\nallow { sgx.svn_ok(input.svn); data.lib.sgx.trusted }",
+        );
+        assert_eq!(
+            check(&[lib.clone(), policy.clone()], "tenants.a"),
+            Vec::new()
+        );
+
+        // Without the library its function is undefined, the entrypoint
+        // must be a package of the bundle
+        let messages = |diagnostics: Vec<Diagnostic>| -> Vec<String> {
+            diagnostics.into_iter().map(|d| d.to_string()).collect()
+        };
+        assert_eq!(
+            messages(check(std::slice::from_ref(&policy), "tenants.a")),
+            vec!["tenants/a.rego:5:1: function sgx.svn_ok is undefined"]
+        );
+        assert_eq!(
+            messages(check(&[lib.clone(), policy], "policy")),
+            vec![
+                "lib/sgx.rego:1:1: no module is package policy".to_string(),
+                format!("lib/sgx.rego:1:1: {}", MISSING_ALLOW),
+            ]
+        );
+
+        let conflict = PolicyModule::new("lib/other.rego", "package lib.sgx\nsvn_ok = 1");
+        assert_eq!(
+            messages(check(&[lib, conflict], "lib.sgx")),
+            vec![
+                "lib/other.rego:2:1: rule svn_ok conflicts with its definition at lib/sgx.rego:3"
+                    .to_string(),
+                format!("lib/sgx.rego:1:1: {}", MISSING_ALLOW),
+            ]
+        );
+    }
+}
@@ -0,0 +1,987 @@
+use super::ast::*;
+use super::builtins;
+use serde_json::{Map, Number, Value};
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+
+/// Variables bound while evaluating a rule body
+pub type Env = HashMap<String, Value>;
+
+/// Evaluates the rules of a set of modules against an input and a data
+/// document.
+///
+/// Expressions are evaluated to all of their solutions, every solution
+/// carrying the variables it binds, so iteration like `data.a[_]` and
+/// backtracking fall out of the evaluation order. Rule values are computed
+/// once and memoized. Rules are named by their package, e.g.
+/// `policy.allow`; inside a rule the names of its package and the imports of
+/// its module are in scope.
+pub struct Evaluator<'a> {
+    packages: Vec<&'a [String]>,
+    rules: HashMap<String, Vec<(&'a Rule, &'a Module)>>,
+    input: &'a Value,
+    data: &'a Value,
+    cache: RefCell<HashMap<String, Option<Value>>>,
+    evaluating: RefCell<HashSet<String>>,
+    /// Modules of the rules being evaluated, innermost last
+    scope: RefCell<Vec<&'a Module>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Complete,
+    Set,
+    Object,
+    Function,
+}
+
+fn kind(rule: &Rule) -> Option<Kind> {
+    match rule.kind {
+        RuleKind::Default(_) => None,
+        RuleKind::Complete(_) => Some(Kind::Complete),
+        RuleKind::Set(_) => Some(Kind::Set),
+        RuleKind::Object(_, _) => Some(Kind::Object),
+        RuleKind::Function(_, _) => Some(Kind::Function),
+    }
+}
+
+/// Name of the rule `name` of `package`, e.g. `policy.allow`
+pub fn qualify(package: &[String], name: &str) -> String {
+    format!("{}.{}", package.join("."), name)
+}
+
+impl<'a> Evaluator<'a> {
+    pub fn new(modules: &'a [Module], input: &'a Value, data: &'a Value) -> Result<Self, String> {
+        let mut packages: Vec<&[String]> = Vec::new();
+        let mut rules: HashMap<String, Vec<(&Rule, &Module)>> = HashMap::new();
+        for module in modules.iter() {
+            if !packages.contains(&module.package.as_slice()) {
+                packages.push(&module.package);
+            }
+            for rule in module.rules.iter() {
+                rules
+                    .entry(qualify(&module.package, &rule.name))
+                    .or_default()
+                    .push((rule, module));
+            }
+        }
+
+        for (name, rules) in rules.iter() {
+            let kinds: HashSet<String> = rules
+                .iter()
+                .filter_map(|(rule, _)| kind(rule))
+                .map(|kind| format!("{:?}", kind))
+                .collect();
+            if kinds.len() > 1 {
+                return Err(format!(
+                    "rule {} at line {} conflicts with another definition of {}",
+                    name,
+                    rules[rules.len() - 1].0.line,
+                    name
+                ));
+            }
+            if rules
+                .iter()
+                .filter(|(rule, _)| matches!(rule.kind, RuleKind::Default(_)))
+                .count()
+                > 1
+            {
+                return Err(format!("rule {} has multiple default values", name));
+            }
+        }
+
+        Ok(Evaluator {
+            packages,
+            rules,
+            input,
+            data,
+            cache: RefCell::new(HashMap::new()),
+            evaluating: RefCell::new(HashSet::new()),
+            scope: RefCell::new(Vec::new()),
+        })
+    }
+
+    fn kind_of(&self, name: &str) -> Option<Kind> {
+        self.rules
+            .get(name)
+            .and_then(|rules| rules.iter().find_map(|(rule, _)| kind(rule)))
+    }
+
+    /// Evaluate `f` with the names of `module` in scope
+    fn in_scope<T>(&self, module: &'a Module, f: impl FnOnce() -> T) -> T {
+        self.scope.borrow_mut().push(module);
+        let res = f();
+        self.scope.borrow_mut().pop();
+        res
+    }
+
+    /// Qualified name of the rule `name` of the package in scope
+    fn local(&self, name: &str) -> Option<String> {
+        let module = *self.scope.borrow().last()?;
+        let name = qualify(&module.package, name);
+        match self.rules.contains_key(&name) {
+            true => Some(name),
+            false => None,
+        }
+    }
+
+    /// Import of the module in scope aliased `name`
+    fn import(&self, name: &str) -> Option<&'a Import> {
+        let module = *self.scope.borrow().last()?;
+        module.imports.iter().find(|import| import.alias == name)
+    }
+
+    /// Qualified name of the function called as `name`, `None` for builtins
+    fn function(&self, name: &str) -> Option<String> {
+        let mut parts: Vec<String> = name.split('.').map(String::from).collect();
+        let qualified = if parts.len() == 1 {
+            self.local(name)?
+        } else {
+            if let Some(import) = self.import(&parts[0]) {
+                parts.splice(0..1, import.path.iter().cloned());
+            }
+            if parts[0] != "data" {
+                return None;
+            }
+            parts[1..].join(".")
+        };
+        match self.kind_of(&qualified) {
+            Some(Kind::Function) => Some(qualified),
+            _ => None,
+        }
+    }
+
+    /// Value of the rule `name`, e.g. `policy.allow`, `None` if it's undefined
+    pub fn rule(&self, name: &str) -> Result<Option<Value>, String> {
+        if let Some(value) = self.cache.borrow().get(name) {
+            return Ok(value.clone());
+        }
+        if !self.evaluating.borrow_mut().insert(name.to_string()) {
+            return Err(format!("rule {} is recursive", name));
+        }
+        let value = self.compute_rule(name);
+        self.evaluating.borrow_mut().remove(name);
+
+        let value = value?;
+        self.cache
+            .borrow_mut()
+            .insert(name.to_string(), value.clone());
+        Ok(value)
+    }
+
+    fn compute_rule(&self, name: &str) -> Result<Option<Value>, String> {
+        let rules = match self.rules.get(name) {
+            Some(rules) => rules,
+            None => return Ok(None),
+        };
+
+        let mut default = None;
+        let mut values: Vec<Value> = Vec::new();
+        let mut object = Map::new();
+        for (rule, module) in rules.iter() {
+            self.in_scope(module, || {
+                self.compute_rule_values(name, rule, &mut default, &mut values, &mut object)
+            })?;
+        }
+
+        match self.kind_of(name) {
+            Some(Kind::Set) => Ok(Some(Value::Array(values))),
+            Some(Kind::Object) => Ok(Some(Value::Object(object))),
+            _ if values.len() > 1 => Err(format!(
+                "complete rule {} produced conflicting values {}",
+                name,
+                Value::Array(values)
+            )),
+            _ => Ok(values.pop().or(default)),
+        }
+    }
+
+    /// Add the values `rule` of the rule `name` produces
+    fn compute_rule_values(
+        &self,
+        name: &str,
+        rule: &Rule,
+        default: &mut Option<Value>,
+        values: &mut Vec<Value>,
+        object: &mut Map<String, Value>,
+    ) -> Result<(), String> {
+        match &rule.kind {
+            RuleKind::Default(term) => *default = Some(self.ground(term)?),
+            RuleKind::Complete(term) | RuleKind::Set(term) => {
+                for env in self.eval_body(&rule.body, &Env::new())? {
+                    for (value, _) in self.eval_term(term, &env)? {
+                        push_unique(values, value);
+                    }
+                }
+            }
+            RuleKind::Object(key, term) => {
+                for env in self.eval_body(&rule.body, &Env::new())? {
+                    for (key, env) in self.eval_term(key, &env)? {
+                        for (value, _) in self.eval_term(term, &env)? {
+                            let key = key_string(&key);
+                            match object.get(&key) {
+                                Some(old) if !equal(old, &value) => {
+                                    return Err(format!(
+                                        "object rule {} produced conflicting values for key {}",
+                                        name, key
+                                    ))
+                                }
+                                _ => object.insert(key, value),
+                            };
+                        }
+                    }
+                }
+            }
+            RuleKind::Function(_, _) => {
+                return Err(format!("function {} is referenced without arguments", name))
+            }
+        }
+        Ok(())
+    }
+
+    fn call_function(&self, name: &str, args: &[Value]) -> Result<Option<Value>, String> {
+        if !self.evaluating.borrow_mut().insert(name.to_string()) {
+            return Err(format!("function {} is recursive", name));
+        }
+        let outputs = self.function_outputs(name, args);
+        self.evaluating.borrow_mut().remove(name);
+
+        let mut outputs = outputs?;
+        if outputs.len() > 1 {
+            return Err(format!(
+                "function {} produced conflicting values {}",
+                name,
+                Value::Array(outputs)
+            ));
+        }
+        Ok(outputs.pop())
+    }
+
+    fn function_outputs(&self, name: &str, args: &[Value]) -> Result<Vec<Value>, String> {
+        let mut outputs = Vec::new();
+        for (rule, module) in self.rules[name].iter() {
+            self.in_scope(module, || {
+                self.function_rule_outputs(name, rule, args, &mut outputs)
+            })?;
+        }
+        Ok(outputs)
+    }
+
+    /// Add the outputs of `rule` of the function `name` for `args`
+    fn function_rule_outputs(
+        &self,
+        name: &str,
+        rule: &Rule,
+        args: &[Value],
+        outputs: &mut Vec<Value>,
+    ) -> Result<(), String> {
+        if let RuleKind::Function(params, term) = &rule.kind {
+            if params.len() != args.len() {
+                return Err(format!(
+                    "function {} takes {} arguments, got {}",
+                    name,
+                    params.len(),
+                    args.len()
+                ));
+            }
+
+            let mut envs = vec![Env::new()];
+            for (param, arg) in params.iter().zip(args) {
+                let mut next = Vec::new();
+                for env in envs.iter() {
+                    next.extend(self.unify(param, arg, env)?);
+                }
+                envs = next;
+            }
+
+            for env in envs.iter() {
+                for env in self.eval_body(&rule.body, env)? {
+                    for (value, _) in self.eval_term(term, &env)? {
+                        push_unique(outputs, value);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Value of a term without variables, e.g. a default value
+    fn ground(&self, term: &Term) -> Result<Value, String> {
+        self.eval_term(term, &Env::new())?
+            .into_iter()
+            .next()
+            .map(|(value, _)| value)
+            .ok_or_else(|| "default value is undefined".to_string())
+    }
+
+    /// Environments satisfying all literals of a body
+    fn eval_body(&self, body: &[Literal], env: &Env) -> Result<Vec<Env>, String> {
+        let mut envs = vec![env.clone()];
+        for literal in body {
+            let mut next = Vec::new();
+            for env in envs.iter() {
+                next.extend(self.eval_literal(literal, env)?);
+            }
+            if next.is_empty() {
+                return Ok(next);
+            }
+            envs = next;
+        }
+        Ok(envs)
+    }
+
+    fn eval_literal(&self, literal: &Literal, env: &Env) -> Result<Vec<Env>, String> {
+        match literal {
+            Literal::Expr(term) => Ok(self
+                .eval_term(term, env)?
+                .into_iter()
+                .filter(|(value, _)| *value != Value::Bool(false))
+                .map(|(_, env)| env)
+                .collect()),
+            Literal::Not(term) => {
+                let holds = self
+                    .eval_term(term, env)?
+                    .iter()
+                    .any(|(value, _)| *value != Value::Bool(false));
+                Ok(match holds {
+                    true => Vec::new(),
+                    false => vec![env.clone()],
+                })
+            }
+            Literal::Some(vars) => {
+                let mut env = env.clone();
+                for var in vars {
+                    env.remove(var);
+                }
+                Ok(vec![env])
+            }
+        }
+    }
+
+    /// Name of `term` if it's a variable without a value yet
+    fn unbound<'t>(&self, term: &'t Term, env: &Env) -> Option<&'t str> {
+        match term {
+            Term::Var(name)
+                if !env.contains_key(name)
+                    && name != "input"
+                    && name != "data"
+                    && self.local(name).is_none()
+                    && self.import(name).is_none() =>
+            {
+                Some(name)
+            }
+            _ => None,
+        }
+    }
+
+    fn is_pattern(&self, term: &Term, env: &Env) -> bool {
+        match term {
+            Term::Var(_) => self.unbound(term, env).is_some(),
+            Term::Array(items) => items.iter().any(|item| self.is_pattern(item, env)),
+            Term::Object(pairs) => pairs.iter().any(|(_, value)| self.is_pattern(value, env)),
+            _ => false,
+        }
+    }
+
+    /// Environments binding the variables of `term` so that it equals `value`
+    fn unify(&self, term: &Term, value: &Value, env: &Env) -> Result<Vec<Env>, String> {
+        if let Some(name) = self.unbound(term, env) {
+            let mut env = env.clone();
+            env.insert(name.to_string(), value.clone());
+            return Ok(vec![env]);
+        }
+
+        match (term, value) {
+            (Term::Array(items), Value::Array(values)) if self.is_pattern(term, env) => {
+                if items.len() != values.len() {
+                    return Ok(Vec::new());
+                }
+                let mut envs = vec![env.clone()];
+                for (item, value) in items.iter().zip(values) {
+                    let mut next = Vec::new();
+                    for env in envs.iter() {
+                        next.extend(self.unify(item, value, env)?);
+                    }
+                    envs = next;
+                }
+                Ok(envs)
+            }
+            (Term::Object(pairs), Value::Object(object)) if self.is_pattern(term, env) => {
+                if pairs.len() != object.len() {
+                    return Ok(Vec::new());
+                }
+                let mut envs = vec![env.clone()];
+                for (key, item) in pairs.iter() {
+                    let mut next = Vec::new();
+                    for env in envs.iter() {
+                        for (key, env) in self.eval_term(key, env)? {
+                            if let Some(value) = object.get(&key_string(&key)) {
+                                next.extend(self.unify(item, value, &env)?);
+                            }
+                        }
+                    }
+                    envs = next;
+                }
+                Ok(envs)
+            }
+            (Term::Array(_), _) | (Term::Object(_), _) if self.is_pattern(term, env) => {
+                Ok(Vec::new())
+            }
+            _ => Ok(self
+                .eval_term(term, env)?
+                .into_iter()
+                .filter(|(term_value, _)| equal(term_value, value))
+                .map(|(_, env)| env)
+                .collect()),
+        }
+    }
+
+    /// All solutions of `term`, each with the environment it's valid in
+    pub fn eval_term(&self, term: &Term, env: &Env) -> Result<Vec<(Value, Env)>, String> {
+        match term {
+            Term::Scalar(value) => Ok(vec![(value.clone(), env.clone())]),
+            Term::Var(name) => {
+                if let Some(value) = env.get(name) {
+                    return Ok(vec![(value.clone(), env.clone())]);
+                }
+                match name.as_str() {
+                    "input" => Ok(vec![(self.input.clone(), env.clone())]),
+                    "data" => Ok(vec![(self.data.clone(), env.clone())]),
+                    _ => match (self.local(name), self.import(name)) {
+                        (Some(rule), _) => Ok(self
+                            .rule(&rule)?
+                            .map(|value| vec![(value, env.clone())])
+                            .unwrap_or_default()),
+                        (None, Some(_)) => self.eval_ref(term, &[], env),
+                        (None, None) => Err(format!("var {} is unsafe", display_var(name))),
+                    },
+                }
+            }
+            Term::Ref(head, path) => self.eval_ref(head, path, env),
+            Term::Array(items) => Ok(self
+                .eval_terms(items, env)?
+                .into_iter()
+                .map(|(values, env)| (Value::Array(values), env))
+                .collect()),
+            Term::Object(pairs) => {
+                let mut solutions = vec![(Map::new(), env.clone())];
+                for (key, value) in pairs.iter() {
+                    let mut next = Vec::new();
+                    for (object, env) in solutions.iter() {
+                        for (key, env) in self.eval_term(key, env)? {
+                            for (value, env) in self.eval_term(value, &env)? {
+                                let mut object = object.clone();
+                                object.insert(key_string(&key), value);
+                                next.push((object, env));
+                            }
+                        }
+                    }
+                    solutions = next;
+                }
+                Ok(solutions
+                    .into_iter()
+                    .map(|(object, env)| (Value::Object(object), env))
+                    .collect())
+            }
+            Term::Call(name, args) => {
+                let mut solutions = Vec::new();
+                for (args, env) in self.eval_terms(args, env)? {
+                    let value = match self.function(name) {
+                        Some(function) => self.call_function(&function, &args)?,
+                        None => builtins::call(name, &args)?,
+                    };
+                    if let Some(value) = value {
+                        solutions.push((value, env));
+                    }
+                }
+                Ok(solutions)
+            }
+            Term::Comprehension(term, body) => {
+                let mut values = Vec::new();
+                for env in self.eval_body(body, env)? {
+                    for (value, _) in self.eval_term(term, &env)? {
+                        values.push(value);
+                    }
+                }
+                Ok(vec![(Value::Array(values), env.clone())])
+            }
+            Term::Binary(op, lhs, rhs) => {
+                let mut solutions = Vec::new();
+                for (lhs, env) in self.eval_term(lhs, env)? {
+                    for (rhs, env) in self.eval_term(rhs, &env)? {
+                        solutions.push((binary(*op, &lhs, &rhs)?, env));
+                    }
+                }
+                Ok(solutions)
+            }
+            Term::Assign(lhs, rhs) | Term::Unify(lhs, rhs) => {
+                let (pattern, other) = match (self.is_pattern(lhs, env), self.is_pattern(rhs, env))
+                {
+                    (false, true) => (rhs, lhs),
+                    _ => (lhs, rhs),
+                };
+                let mut solutions = Vec::new();
+                for (value, env) in self.eval_term(other, env)? {
+                    for env in self.unify(pattern, &value, &env)? {
+                        solutions.push((Value::Bool(true), env));
+                    }
+                }
+                Ok(solutions)
+            }
+        }
+    }
+
+    fn eval_terms(&self, terms: &[Term], env: &Env) -> Result<Vec<(Vec<Value>, Env)>, String> {
+        let mut solutions = vec![(Vec::new(), env.clone())];
+        for term in terms {
+            let mut next = Vec::new();
+            for (values, env) in solutions.iter() {
+                for (value, env) in self.eval_term(term, env)? {
+                    let mut values = values.clone();
+                    values.push(value);
+                    next.push((values, env));
+                }
+            }
+            solutions = next;
+        }
+        Ok(solutions)
+    }
+
+    fn eval_ref(&self, head: &Term, path: &[Term], env: &Env) -> Result<Vec<(Value, Env)>, String> {
+        if let Term::Var(name) = head {
+            if !env.contains_key(name) {
+                if let Some(import) = self.import(name) {
+                    let mut full: Vec<Term> = import.path[1..]
+                        .iter()
+                        .map(|key| Term::Scalar(Value::String(key.clone())))
+                        .collect();
+                    full.extend(path.iter().cloned());
+                    return self.eval_ref(&Term::Var(import.path[0].clone()), &full, env);
+                }
+                if name == "data" {
+                    if let Some(solutions) = self.eval_data_ref(path, env)? {
+                        return Ok(solutions);
+                    }
+                }
+                if let Some(rule) = self.local(name) {
+                    return self.eval_rule_ref(&rule, path, env);
+                }
+            }
+        }
+
+        let mut solutions = Vec::new();
+        for (value, env) in self.eval_term(head, env)? {
+            solutions.extend(self.walk(value, false, path, &env)?);
+        }
+        Ok(solutions)
+    }
+
+    /// `data.<package>.<rule>` refers to a rule, `data.<package>` to the
+    /// values of all rules of the packages under it. `None` for base data.
+    fn eval_data_ref(&self, path: &[Term], env: &Env) -> Result<Option<Vec<(Value, Env)>>, String> {
+        let keys: Vec<String> = path
+            .iter()
+            .map_while(|key| match key {
+                Term::Scalar(Value::String(key)) => Some(key.clone()),
+                _ => None,
+            })
+            .collect();
+
+        let package = self
+            .packages
+            .iter()
+            .filter(|package| keys.len() > package.len() && keys.starts_with(package))
+            .filter(|package| {
+                self.rules
+                    .contains_key(&qualify(package, &keys[package.len()]))
+            })
+            .max_by_key(|package| package.len());
+        if let Some(package) = package {
+            let rule = qualify(package, &keys[package.len()]);
+            return self
+                .eval_rule_ref(&rule, &path[package.len() + 1..], env)
+                .map(Some);
+        }
+
+        let prefix = (1..=keys.len()).rev().find(|len| {
+            self.packages
+                .iter()
+                .any(|package| package.starts_with(&keys[..*len]))
+        });
+        match prefix {
+            // No rule is under the next key, it's only base data
+            Some(len) if len < keys.len() => {
+                match keys[..=len]
+                    .iter()
+                    .try_fold(self.data, |value, key| value.get(key))
+                {
+                    Some(value) => self
+                        .walk(value.clone(), false, &path[len + 1..], env)
+                        .map(Some),
+                    None => Ok(Some(Vec::new())),
+                }
+            }
+            Some(len) => {
+                let document = self.document(&keys[..len])?;
+                self.walk(document, false, &path[len..], env).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Base data under `prefix` with the values of the rules of the packages
+    /// under it
+    fn document(&self, prefix: &[String]) -> Result<Value, String> {
+        let mut document = prefix
+            .iter()
+            .try_fold(self.data, |value, key| value.get(key))
+            .cloned()
+            .unwrap_or_else(|| Value::Object(Map::new()));
+
+        for package in self.packages.iter().filter(|p| p.starts_with(prefix)) {
+            let mut names: Vec<&str> = self
+                .rules
+                .iter()
+                .filter(|(_, rules)| rules[0].1.package.as_slice() == *package)
+                .filter(|(name, _)| self.kind_of(name) != Some(Kind::Function))
+                .map(|(_, rules)| rules[0].0.name.as_str())
+                .collect();
+            names.sort_unstable();
+            names.dedup();
+
+            for name in names {
+                if let Some(value) = self.rule(&qualify(package, name))? {
+                    let mut keys: Vec<&str> =
+                        package[prefix.len()..].iter().map(String::as_str).collect();
+                    keys.push(name);
+                    insert_path(&mut document, &keys, value);
+                }
+            }
+        }
+        Ok(document)
+    }
+
+    fn eval_rule_ref(
+        &self,
+        name: &str,
+        path: &[Term],
+        env: &Env,
+    ) -> Result<Vec<(Value, Env)>, String> {
+        match self.rule(name)? {
+            Some(value) => self.walk(value, self.kind_of(name) == Some(Kind::Set), path, env),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Follow `path` into `value`, unbound variables in the path iterate
+    /// over the keys. A set's first key refers to its elements.
+    fn walk(
+        &self,
+        value: Value,
+        set: bool,
+        path: &[Term],
+        env: &Env,
+    ) -> Result<Vec<(Value, Env)>, String> {
+        let mut solutions = vec![(value, env.clone())];
+        for (i, key) in path.iter().enumerate() {
+            let set = set && i == 0;
+            let mut next = Vec::new();
+            for (value, env) in solutions.iter() {
+                match self.unbound(key, env) {
+                    Some(name) => {
+                        for (key, child) in children(value, set) {
+                            let mut env = env.clone();
+                            env.insert(name.to_string(), key);
+                            next.push((child, env));
+                        }
+                    }
+                    None => {
+                        for (key, env) in self.eval_term(key, env)? {
+                            if let Some(child) = child(value, &key, set) {
+                                next.push((child, env));
+                            }
+                        }
+                    }
+                }
+            }
+            solutions = next;
+        }
+        Ok(solutions)
+    }
+}
+
+fn display_var(name: &str) -> &str {
+    match name.starts_with(WILDCARD) {
+        true => "_",
+        false => name,
+    }
+}
+
+fn children(value: &Value, set: bool) -> Vec<(Value, Value)> {
+    match value {
+        Value::Array(items) if set => items.iter().map(|v| (v.clone(), v.clone())).collect(),
+        Value::Array(items) => items
+            .iter()
+            .enumerate()
+            .map(|(i, v)| (Value::from(i), v.clone()))
+            .collect(),
+        Value::Object(object) => object
+            .iter()
+            .map(|(k, v)| (Value::String(k.clone()), v.clone()))
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+fn child(value: &Value, key: &Value, set: bool) -> Option<Value> {
+    match (value, key) {
+        (Value::Array(items), key) if set => items.iter().find(|v| equal(v, key)).cloned(),
+        (Value::Array(items), Value::Number(n)) => {
+            let index = n.as_f64().filter(|f| *f >= 0.0 && f.fract() == 0.0)?;
+            items.get(index as usize).cloned()
+        }
+        (Value::Object(object), Value::String(key)) => object.get(key).cloned(),
+        _ => None,
+    }
+}
+
+/// Set `value` at `keys` in `document`, creating the objects on the way
+fn insert_path(document: &mut Value, keys: &[&str], value: Value) {
+    if !document.is_object() {
+        *document = Value::Object(Map::new());
+    }
+    let object = document.as_object_mut().unwrap();
+    match keys {
+        [] => {}
+        [key] => {
+            object.insert(key.to_string(), value);
+        }
+        [key, rest @ ..] => insert_path(
+            object
+                .entry(key.to_string())
+                .or_insert_with(|| Value::Object(Map::new())),
+            rest,
+            value,
+        ),
+    }
+}
+
+fn push_unique(values: &mut Vec<Value>, value: Value) {
+    if !values.iter().any(|v| equal(v, &value)) {
+        values.push(value);
+    }
+}
+
+/// Object keys are strings, other values are keyed by their JSON text
+fn key_string(key: &Value) -> String {
+    match key {
+        Value::String(key) => key.clone(),
+        key => key.to_string(),
+    }
+}
+
+fn type_order(value: &Value) -> u8 {
+    match value {
+        Value::Null => 0,
+        Value::Bool(_) => 1,
+        Value::Number(_) => 2,
+        Value::String(_) => 3,
+        Value::Array(_) => 4,
+        Value::Object(_) => 5,
+    }
+}
+
+/// Total order of values, values of different types are ordered by type
+pub fn compare(a: &Value, b: &Value) -> Ordering {
+    match (a, b) {
+        (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+        (Value::Number(a), Value::Number(b)) => match (a.as_i64(), b.as_i64()) {
+            (Some(a), Some(b)) => a.cmp(&b),
+            _ => a
+                .as_f64()
+                .partial_cmp(&b.as_f64())
+                .unwrap_or(Ordering::Equal),
+        },
+        (Value::String(a), Value::String(b)) => a.cmp(b),
+        (Value::Array(a), Value::Array(b)) => a
+            .iter()
+            .zip(b)
+            .map(|(a, b)| compare(a, b))
+            .find(|o| *o != Ordering::Equal)
+            .unwrap_or_else(|| a.len().cmp(&b.len())),
+        (Value::Object(a), Value::Object(b)) => {
+            let mut a: Vec<_> = a.iter().collect();
+            let mut b: Vec<_> = b.iter().collect();
+            a.sort_by(|x, y| x.0.cmp(y.0));
+            b.sort_by(|x, y| x.0.cmp(y.0));
+            a.iter()
+                .zip(b.iter())
+                .map(|(x, y)| x.0.cmp(y.0).then_with(|| compare(x.1, y.1)))
+                .find(|o| *o != Ordering::Equal)
+                .unwrap_or_else(|| a.len().cmp(&b.len()))
+        }
+        _ => type_order(a).cmp(&type_order(b)),
+    }
+}
+
+pub fn equal(a: &Value, b: &Value) -> bool {
+    compare(a, b) == Ordering::Equal
+}
+
+/// Integral results stay integers, like `1 + 2` being `3` and not `3.0`
+pub fn number(f: f64) -> Result<Value, String> {
+    if f.fract() == 0.0 && f.abs() < i64::MAX as f64 {
+        return Ok(Value::from(f as i64));
+    }
+    Number::from_f64(f)
+        .map(Value::Number)
+        .ok_or_else(|| format!("{} isn't a valid number", f))
+}
+
+fn binary(op: Op, lhs: &Value, rhs: &Value) -> Result<Value, String> {
+    let ordering = compare(lhs, rhs);
+    let result = match op {
+        Op::Eq => ordering == Ordering::Equal,
+        Op::Ne => ordering != Ordering::Equal,
+        Op::Lt => ordering == Ordering::Less,
+        Op::Le => ordering != Ordering::Greater,
+        Op::Gt => ordering == Ordering::Greater,
+        Op::Ge => ordering != Ordering::Less,
+        _ => return arithmetic(op, lhs, rhs),
+    };
+    Ok(Value::Bool(result))
+}
+
+fn arithmetic(op: Op, lhs: &Value, rhs: &Value) -> Result<Value, String> {
+    let (a, b) = match (lhs, rhs) {
+        (Value::Number(a), Value::Number(b)) => (a, b),
+        _ => return Err(format!("{:?} expects numbers, got {} and {}", op, lhs, rhs)),
+    };
+
+    if let (Some(a), Some(b)) = (a.as_i64(), b.as_i64()) {
+        let result = match op {
+            Op::Add => a.checked_add(b),
+            Op::Sub => a.checked_sub(b),
+            Op::Mul => a.checked_mul(b),
+            Op::Div | Op::Rem if b == 0 => return Err("divide by zero".to_string()),
+            Op::Div if a % b == 0 => Some(a / b),
+            Op::Rem => Some(a % b),
+            _ => None,
+        };
+        if let Some(result) = result {
+            return Ok(Value::from(result));
+        }
+    }
+
+    let (a, b) = (a.as_f64().unwrap_or(0.0), b.as_f64().unwrap_or(0.0));
+    match op {
+        Op::Add => number(a + b),
+        Op::Sub => number(a - b),
+        Op::Mul => number(a * b),
+        Op::Div if b == 0.0 => Err("divide by zero".to_string()),
+        Op::Div => number(a / b),
+        _ => Err("modulo on a floating-point number".to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::parser::parse;
+    use super::*;
+    use serde_json::json;
+
+    fn eval(policy: &str, rule: &str, input: Value, data: Value) -> Result<Option<Value>, String> {
+        let module = parse(policy).map_err(|e| e.to_string())?;
+        let rule = qualify(&module.package, rule);
+        Evaluator::new(std::slice::from_ref(&module), &input, &data)?.rule(&rule)
+    }
+
+    #[test]
+    fn test_eval_rules() {
+        let policy = r#"
+package policy
+
+default allow = false
+
+allow {
+    input.svn >= data.svn
+    input.mrEnclave == data.mrEnclave[_]
+    not denied
+}
+
+denied { input.debug == true }
+
+names[name] { data.mrEnclave[i] = name; i > 0 }
+sizes[name] = n { name := data.mrEnclave[_]; n := count(name) }
+total := sum([n | n := sizes[_]]) * 2 - 1
+small := [x | x := data.svns[_]; x < 3]
+first := data.policy.names[_]
+"#;
+        let data = json!({"svn": 2, "mrEnclave": ["a", "bb", "ccc"], "svns": [1, 2, 3, 2.5]});
+
+        let input = json!({"svn": 2, "mrEnclave": "bb", "debug": false});
+        assert_eq!(
+            eval(policy, "allow", input.clone(), data.clone()),
+            Ok(Some(json!(true)))
+        );
+        assert_eq!(
+            eval(policy, "names", input.clone(), data.clone()),
+            Ok(Some(json!(["bb", "ccc"])))
+        );
+        assert_eq!(
+            eval(policy, "sizes", input.clone(), data.clone()),
+            Ok(Some(json!({"a": 1, "bb": 2, "ccc": 3})))
+        );
+        assert_eq!(
+            eval(policy, "total", input.clone(), data.clone()),
+            Ok(Some(json!(11)))
+        );
+        assert_eq!(
+            eval(policy, "small", input.clone(), data.clone()),
+            Ok(Some(json!([1, 2, 2.5])))
+        );
+        assert!(eval(policy, "first", input, data.clone()).is_err());
+
+        let input = json!({"svn": 2, "mrEnclave": "bb", "debug": true});
+        assert_eq!(
+            eval(policy, "allow", input, data.clone()),
+            Ok(Some(json!(false)))
+        );
+        let input = json!({"svn": 1.5, "mrEnclave": "bb"});
+        assert_eq!(eval(policy, "allow", input, data), Ok(Some(json!(false))));
+    }
+
+    #[test]
+    fn test_eval_functions() {
+        let policy = r#"
+package policy
+
+matches("tee", value) { input.tee == value }
+matches(name, value) { input.claims[name] == value }
+
+allow {
+    [a, {"b": b}] := [1, {"b": 2}]
+    a + b == 3
+    matches("tee", "sgx")
+    matches("svn", 3)
+    not matches("svn", 4)
+    object.get(input.claims, "debug", false) == false
+}
+
+conflict = 1 { true }
+conflict = 2 { true }
+"#;
+        let input = json!({"tee": "sgx", "claims": {"svn": 3}});
+        assert_eq!(
+            eval(policy, "allow", input.clone(), json!({})),
+            Ok(Some(json!(true)))
+        );
+        assert!(eval(policy, "conflict", input.clone(), json!({})).is_err());
+        assert_eq!(eval(policy, "missing", input, json!({})), Ok(None));
+
+        assert!(eval("package p\na { x > 1 }", "a", json!({}), json!({})).is_err());
+        assert!(eval("package p\na { b }\nb { a }", "a", json!({}), json!({})).is_err());
+        assert_eq!(compare(&json!(1), &json!(1.0)), Ordering::Equal);
+    }
+}
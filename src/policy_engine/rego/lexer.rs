@@ -0,0 +1,196 @@
+use serde_json::Number;
+
+/// Error at a position of the policy source
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Token {
+    Ident(String),
+    String(String),
+    Number(Number),
+    Punct(&'static str),
+    Newline,
+    Eof,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spanned {
+    pub token: Token,
+    pub line: usize,
+    pub column: usize,
+}
+
+// Longest first, so ":=" isn't read as ":" and "="
+const PUNCTS: [&str; 25] = [
+    ":=", "==", "!=", "<=", ">=", "{", "}", "[", "]", "(", ")", ".", ",", ";", ":", "|", "&", "=",
+    "<", ">", "+", "-", "*", "/", "%",
+];
+
+pub fn tokenize(source: &str) -> Result<Vec<Spanned>, ParseError> {
+    let chars: Vec<char> = source.chars().collect();
+    let mut tokens = Vec::new();
+    let (mut i, mut line, mut line_start) = (0, 1, 0);
+
+    while i < chars.len() {
+        let c = chars[i];
+        let (token_line, column) = (line, i - line_start + 1);
+        let error = |message: String| ParseError {
+            line: token_line,
+            column,
+            message,
+        };
+        let start = i;
+
+        let token = match c {
+            '\n' => {
+                i += 1;
+                line += 1;
+                line_start = i;
+                Token::Newline
+            }
+            c if c.is_whitespace() => {
+                i += 1;
+                continue;
+            }
+            '#' => {
+                while i < chars.len() && chars[i] != '\n' {
+                    i += 1;
+                }
+                continue;
+            }
+            '"' => {
+                i += 1;
+                while i < chars.len() && chars[i] != '"' {
+                    if chars[i] == '\n' {
+                        return Err(error("string isn't terminated".to_string()));
+                    }
+                    if chars[i] == '\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+                if i >= chars.len() {
+                    return Err(error("string isn't terminated".to_string()));
+                }
+                i += 1;
+                let literal: String = chars[start..i].iter().collect();
+                let string = serde_json::from_str::<String>(&literal)
+                    .map_err(|e| error(format!("invalid string {}: {}", literal, e)))?;
+                Token::String(string)
+            }
+            '`' => {
+                i += 1;
+                while i < chars.len() && chars[i] != '`' {
+                    if chars[i] == '\n' {
+                        line += 1;
+                        line_start = i + 1;
+                    }
+                    i += 1;
+                }
+                if i >= chars.len() {
+                    return Err(error("raw string isn't terminated".to_string()));
+                }
+                i += 1;
+                Token::String(chars[start + 1..i - 1].iter().collect())
+            }
+            c if c.is_ascii_digit() => {
+                while i < chars.len()
+                    && (chars[i].is_ascii_alphanumeric()
+                        || chars[i] == '.'
+                        || ((chars[i] == '+' || chars[i] == '-')
+                            && matches!(chars[i - 1], 'e' | 'E')))
+                {
+                    // A dot not followed by a digit ends the number, e.g. `x[0].y`
+                    if chars[i] == '.' && !matches!(chars.get(i + 1), Some(c) if c.is_ascii_digit())
+                    {
+                        break;
+                    }
+                    i += 1;
+                }
+                let literal: String = chars[start..i].iter().collect();
+                let number = serde_json::from_str::<Number>(&literal)
+                    .map_err(|_| error(format!("invalid number {}", literal)))?;
+                Token::Number(number)
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                    i += 1;
+                }
+                Token::Ident(chars[start..i].iter().collect())
+            }
+            _ => {
+                let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+                let punct = PUNCTS
+                    .iter()
+                    .find(|p| rest.starts_with(**p))
+                    .ok_or_else(|| error(format!("unexpected character {:?}", c)))?;
+                i += punct.chars().count();
+                Token::Punct(punct)
+            }
+        };
+
+        tokens.push(Spanned {
+            token,
+            line: token_line,
+            column,
+        });
+    }
+
+    tokens.push(Spanned {
+        token: Token::Eof,
+        line,
+        column: chars.len() - line_start + 1,
+    });
+    Ok(tokens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(source: &str) -> Vec<Token> {
+        tokenize(source)
+            .unwrap()
+            .into_iter()
+            .map(|t| t.token)
+            .collect()
+    }
+
+    #[test]
+    fn test_tokenize() {
+        assert_eq!(
+            tokens("x := data.a[0] # comment\n\"s\\n\" >= -1.5"),
+            vec![
+                Token::Ident("x".to_string()),
+                Token::Punct(":="),
+                Token::Ident("data".to_string()),
+                Token::Punct("."),
+                Token::Ident("a".to_string()),
+                Token::Punct("["),
+                Token::Number(0.into()),
+                Token::Punct("]"),
+                Token::Newline,
+                Token::String("s\n".to_string()),
+                Token::Punct(">="),
+                Token::Punct("-"),
+                Token::Number(Number::from_f64(1.5).unwrap()),
+                Token::Eof,
+            ]
+        );
+
+        let e = tokenize("a {\n  b == \"c\n}").unwrap_err();
+        assert_eq!((e.line, e.column), (2, 8));
+        assert!(tokenize("a ? b").is_err());
+    }
+}
@@ -0,0 +1,83 @@
+//! Native Rego interpreter.
+//!
+//! Evaluates the subset of Rego used by attestation policies without the Go
+//! runtime: complete, default, partial set and object rules, functions,
+//! `not`, array comprehensions, iteration over references, imports of other
+//! packages and the common builtins. Unsupported syntax (`with`, `every`,
+//! `some .. in`, sets, `else`) is rejected when the policy is parsed.
+pub mod ast;
+pub mod builtins;
+pub mod check;
+pub mod eval;
+pub mod lexer;
+pub mod parser;
+
+use super::{Decision, Diagnostic, PolicyEngine, PolicyModule, PreparedPolicy};
+use serde_json::{Map, Value};
+
+pub struct RegoEngine;
+
+impl PolicyEngine for RegoEngine {
+    fn name(&self) -> &'static str {
+        "rego"
+    }
+
+    fn check_modules(&self, modules: &[PolicyModule], entrypoint: &str) -> Vec<Diagnostic> {
+        check::check(modules, entrypoint)
+    }
+
+    fn prepare_modules(
+        &self,
+        modules: &[PolicyModule],
+        data: &str,
+        entrypoint: &str,
+    ) -> Result<Box<dyn PreparedPolicy>, String> {
+        let modules = modules
+            .iter()
+            .map(|module| {
+                parser::parse(&module.source)
+                    .map_err(|e| format!("policy error: {}: {}", module.file, e))
+            })
+            .collect::<Result<Vec<ast::Module>, String>>()?;
+        let entrypoint: Vec<String> = entrypoint.split('.').map(String::from).collect();
+        if !modules.iter().any(|module| module.package == entrypoint) {
+            return Err(format!(
+                "policy package {} isn't defined",
+                entrypoint.join(".")
+            ));
+        }
+        let data: Map<String, Value> =
+            serde_json::from_str(data).map_err(|e| format!("Unmarshal data error: {}", e))?;
+
+        Ok(Box::new(PreparedRego {
+            modules,
+            data: Value::Object(data),
+            entrypoint,
+        }))
+    }
+}
+
+struct PreparedRego {
+    modules: Vec<ast::Module>,
+    data: Value,
+    /// Package of the decision rules
+    entrypoint: Vec<String>,
+}
+
+impl PreparedPolicy for PreparedRego {
+    fn make_decision(&self, input: &str) -> Result<Decision, String> {
+        let input: Map<String, Value> =
+            serde_json::from_str(input).map_err(|e| format!("Unmarshal input error: {}", e))?;
+        let input = Value::Object(input);
+
+        let evaluator = eval::Evaluator::new(&self.modules, &input, &self.data)?;
+        let mut rules = Map::new();
+        for name in ["allow", "deny_reasons", "claims"] {
+            if let Some(value) = evaluator.rule(&eval::qualify(&self.entrypoint, name))? {
+                rules.insert(name.to_string(), value);
+            }
+        }
+
+        Decision::from_rules(&rules)
+    }
+}
@@ -0,0 +1,647 @@
+use super::ast::*;
+use super::lexer::{tokenize, ParseError, Spanned, Token};
+use serde_json::Value;
+
+struct Parser {
+    tokens: Vec<Spanned>,
+    pos: usize,
+    wildcards: usize,
+}
+
+pub fn parse(source: &str) -> Result<Module, ParseError> {
+    let mut parser = Parser {
+        tokens: tokenize(source)?,
+        pos: 0,
+        wildcards: 0,
+    };
+    parser.module()
+}
+
+impl Parser {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.pos].token
+    }
+
+    fn next(&mut self) -> Token {
+        let token = self.tokens[self.pos].token.clone();
+        if self.pos + 1 < self.tokens.len() {
+            self.pos += 1;
+        }
+        token
+    }
+
+    fn error<T>(&self, message: String) -> Result<T, ParseError> {
+        let token = &self.tokens[self.pos];
+        Err(ParseError {
+            line: token.line,
+            column: token.column,
+            message,
+        })
+    }
+
+    fn is_punct(&self, punct: &str) -> bool {
+        matches!(self.peek(), Token::Punct(p) if *p == punct)
+    }
+
+    fn is_ident(&self, ident: &str) -> bool {
+        matches!(self.peek(), Token::Ident(i) if i == ident)
+    }
+
+    fn expect(&mut self, punct: &str) -> Result<(), ParseError> {
+        if self.is_punct(punct) {
+            self.next();
+            Ok(())
+        } else {
+            self.error(format!(
+                "expected {:?}, found {}",
+                punct,
+                describe(self.peek())
+            ))
+        }
+    }
+
+    fn ident(&mut self) -> Result<String, ParseError> {
+        match self.peek().clone() {
+            Token::Ident(ident) => {
+                self.next();
+                Ok(ident)
+            }
+            token => self.error(format!("expected a name, found {}", describe(&token))),
+        }
+    }
+
+    fn skip_newlines(&mut self) {
+        while *self.peek() == Token::Newline {
+            self.next();
+        }
+    }
+
+    // Newlines can't end a statement inside brackets or after operators
+    fn punct_skipping(&mut self, punct: &str) -> bool {
+        let pos = self.pos;
+        self.skip_newlines();
+        if self.is_punct(punct) {
+            true
+        } else {
+            self.pos = pos;
+            false
+        }
+    }
+
+    fn end_of_statement(&mut self) -> Result<(), ParseError> {
+        match self.peek() {
+            Token::Newline | Token::Eof => Ok(()),
+            Token::Punct(";") => Ok(()),
+            token => self.error(format!("unexpected {}", describe(token))),
+        }
+    }
+
+    fn module(&mut self) -> Result<Module, ParseError> {
+        self.skip_newlines();
+        if !self.is_ident("package") {
+            return self.error("policy must start with a package declaration".to_string());
+        }
+        self.next();
+        let mut package = vec![self.ident()?];
+        while self.is_punct(".") {
+            self.next();
+            package.push(self.ident()?);
+        }
+        self.end_of_statement()?;
+
+        let mut imports = Vec::new();
+        let mut rules = Vec::new();
+        loop {
+            while matches!(self.peek(), Token::Newline | Token::Punct(";")) {
+                self.next();
+            }
+            match self.peek() {
+                Token::Eof => break,
+                Token::Ident(ident) if ident == "import" => {
+                    if let Some(import) = self.import()? {
+                        imports.push(import);
+                    }
+                }
+                _ => rules.push(self.rule()?),
+            }
+        }
+
+        Ok(Module {
+            package,
+            imports,
+            rules,
+        })
+    }
+
+    // Imports of data and input documents, the future keywords change
+    // nothing here
+    fn import(&mut self) -> Result<Option<Import>, ParseError> {
+        self.next();
+        let mut path = vec![self.ident()?];
+        while self.is_punct(".") {
+            self.next();
+            path.push(self.ident()?);
+        }
+        let alias = match self.is_ident("as") {
+            true => {
+                self.next();
+                Some(self.ident()?)
+            }
+            false => None,
+        };
+
+        let import = match path[0].as_str() {
+            "future" if path.get(1).map(String::as_str) == Some("keywords") => None,
+            "rego" if path == ["rego", "v1"] => None,
+            // Importing a whole document only repeats its name
+            "data" | "input" if path.len() == 1 && alias.is_none() => None,
+            "data" | "input" if path.len() > 1 => Some(Import {
+                alias: alias.unwrap_or_else(|| path[path.len() - 1].clone()),
+                path,
+            }),
+            _ => return self.error(format!("import {} isn't supported", path.join("."))),
+        };
+        self.end_of_statement()?;
+        Ok(import)
+    }
+
+    fn rule(&mut self) -> Result<Rule, ParseError> {
+        let line = self.tokens[self.pos].line;
+
+        if self.is_ident("default") {
+            self.next();
+            let name = self.ident()?;
+            if !self.is_punct("=") && !self.is_punct(":=") {
+                return self.error("expected \"=\" after the default rule name".to_string());
+            }
+            self.next();
+            let value = self.term()?;
+            self.end_of_statement()?;
+            return Ok(Rule {
+                name,
+                kind: RuleKind::Default(value),
+                body: Vec::new(),
+                line,
+            });
+        }
+
+        let name = self.ident()?;
+        let mut args = None;
+        let mut key = None;
+        if self.is_punct("(") {
+            self.next();
+            args = Some(self.terms(")")?);
+        } else if self.is_punct("[") {
+            self.next();
+            key = Some(self.expr()?);
+            self.expect("]")?;
+        } else if self.is_ident("contains") {
+            self.next();
+            key = Some(self.expr()?);
+        }
+
+        let value = if self.is_punct("=") || self.is_punct(":=") {
+            self.next();
+            Some(self.expr()?)
+        } else {
+            None
+        };
+
+        if self.is_ident("if") {
+            self.next();
+        }
+        let body = if self.is_punct("{") {
+            self.next();
+            self.body("}")?
+        } else if value.is_some() || (key.is_some() && args.is_none()) {
+            Vec::new()
+        } else {
+            return self.error(format!("rule {} has no body", name));
+        };
+        if self.is_ident("else") {
+            return self.error("else isn't supported".to_string());
+        }
+        self.end_of_statement()?;
+
+        // A keyed rule without a value is a set, `p[k] = v` is an object
+        let kind = match (args, key, value) {
+            (None, Some(key), None) => RuleKind::Set(key),
+            (None, Some(key), Some(value)) => RuleKind::Object(key, value),
+            (args, _, value) => {
+                let value = value.unwrap_or(Term::Scalar(Value::Bool(true)));
+                match args {
+                    Some(args) => RuleKind::Function(args, value),
+                    None => RuleKind::Complete(value),
+                }
+            }
+        };
+
+        Ok(Rule {
+            name,
+            kind,
+            body,
+            line,
+        })
+    }
+
+    /// Literals up to `close`, separated by newlines or ";"
+    fn body(&mut self, close: &str) -> Result<Vec<Literal>, ParseError> {
+        let mut literals = Vec::new();
+        loop {
+            while matches!(self.peek(), Token::Newline | Token::Punct(";")) {
+                self.next();
+            }
+            if self.is_punct(close) {
+                self.next();
+                return Ok(literals);
+            }
+            if *self.peek() == Token::Eof {
+                return self.error(format!("expected {:?}, found end of policy", close));
+            }
+
+            literals.push(self.literal()?);
+
+            if !matches!(self.peek(), Token::Newline | Token::Punct(";")) && !self.is_punct(close) {
+                return self.error(format!("unexpected {}", describe(self.peek())));
+            }
+        }
+    }
+
+    fn literal(&mut self) -> Result<Literal, ParseError> {
+        if self.is_ident("not") {
+            self.next();
+            return Ok(Literal::Not(self.expr()?));
+        }
+        if self.is_ident("some") {
+            self.next();
+            let mut vars = vec![self.ident()?];
+            while self.is_punct(",") {
+                self.next();
+                vars.push(self.ident()?);
+            }
+            if self.is_ident("in") {
+                return self.error("some ... in isn't supported".to_string());
+            }
+            return Ok(Literal::Some(vars));
+        }
+        if self.is_ident("every") || self.is_ident("with") {
+            return self.error(format!("{} isn't supported", describe(self.peek())));
+        }
+
+        let literal = Literal::Expr(self.expr()?);
+        if self.is_ident("with") {
+            return self.error("with isn't supported".to_string());
+        }
+        Ok(literal)
+    }
+
+    /// Assignment, unification or a comparison
+    fn expr(&mut self) -> Result<Term, ParseError> {
+        let lhs = self.comparison()?;
+        if self.is_punct(":=") {
+            self.next();
+            self.skip_newlines();
+            if !matches!(lhs, Term::Var(_) | Term::Array(_)) {
+                return self.error("only variables can be assigned".to_string());
+            }
+            return Ok(Term::Assign(Box::new(lhs), Box::new(self.comparison()?)));
+        }
+        if self.is_punct("=") {
+            self.next();
+            self.skip_newlines();
+            return Ok(Term::Unify(Box::new(lhs), Box::new(self.comparison()?)));
+        }
+        Ok(lhs)
+    }
+
+    fn comparison(&mut self) -> Result<Term, ParseError> {
+        let lhs = self.additive()?;
+        let op = match self.peek() {
+            Token::Punct("==") => Op::Eq,
+            Token::Punct("!=") => Op::Ne,
+            Token::Punct("<") => Op::Lt,
+            Token::Punct("<=") => Op::Le,
+            Token::Punct(">") => Op::Gt,
+            Token::Punct(">=") => Op::Ge,
+            _ => return Ok(lhs),
+        };
+        self.next();
+        self.skip_newlines();
+        Ok(Term::Binary(op, Box::new(lhs), Box::new(self.additive()?)))
+    }
+
+    fn additive(&mut self) -> Result<Term, ParseError> {
+        let mut lhs = self.multiplicative()?;
+        loop {
+            let op = match self.peek() {
+                Token::Punct("+") => Op::Add,
+                Token::Punct("-") => Op::Sub,
+                _ => return Ok(lhs),
+            };
+            self.next();
+            self.skip_newlines();
+            lhs = Term::Binary(op, Box::new(lhs), Box::new(self.multiplicative()?));
+        }
+    }
+
+    fn multiplicative(&mut self) -> Result<Term, ParseError> {
+        let mut lhs = self.unary()?;
+        loop {
+            let op = match self.peek() {
+                Token::Punct("*") => Op::Mul,
+                Token::Punct("/") => Op::Div,
+                Token::Punct("%") => Op::Rem,
+                _ => return Ok(lhs),
+            };
+            self.next();
+            self.skip_newlines();
+            lhs = Term::Binary(op, Box::new(lhs), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Term, ParseError> {
+        if !self.is_punct("-") {
+            return self.term();
+        }
+        self.next();
+        Ok(match self.unary()? {
+            Term::Scalar(Value::Number(n)) => {
+                let negated = match (n.as_i64(), n.as_f64()) {
+                    (Some(i), _) => Value::from(-i),
+                    (None, Some(f)) => Value::from(-f),
+                    _ => return self.error(format!("can't negate {}", n)),
+                };
+                Term::Scalar(negated)
+            }
+            term => Term::Binary(
+                Op::Sub,
+                Box::new(Term::Scalar(Value::from(0))),
+                Box::new(term),
+            ),
+        })
+    }
+
+    /// A primary term followed by references and calls
+    fn term(&mut self) -> Result<Term, ParseError> {
+        let mut term = self.primary()?;
+        loop {
+            if self.is_punct(".") {
+                self.next();
+                let key = Term::Scalar(Value::String(self.ident()?));
+                term = push_path(term, key);
+            } else if self.is_punct("[") {
+                self.next();
+                self.skip_newlines();
+                let key = self.expr()?;
+                if !self.punct_skipping("]") {
+                    return self.error(format!("expected \"]\", found {}", describe(self.peek())));
+                }
+                self.next();
+                term = push_path(term, key);
+            } else if self.is_punct("(") {
+                let name = match function_name(&term) {
+                    Some(name) => name,
+                    None => return self.error("only named functions can be called".to_string()),
+                };
+                self.next();
+                term = Term::Call(name, self.terms(")")?);
+            } else {
+                return Ok(term);
+            }
+        }
+    }
+
+    fn primary(&mut self) -> Result<Term, ParseError> {
+        match self.next() {
+            Token::Number(n) => Ok(Term::Scalar(Value::Number(n))),
+            Token::String(s) => Ok(Term::Scalar(Value::String(s))),
+            Token::Ident(ident) => Ok(match ident.as_str() {
+                "true" => Term::Scalar(Value::Bool(true)),
+                "false" => Term::Scalar(Value::Bool(false)),
+                "null" => Term::Scalar(Value::Null),
+                "_" => {
+                    self.wildcards += 1;
+                    Term::Var(format!("{}{}", WILDCARD, self.wildcards))
+                }
+                _ => Term::Var(ident),
+            }),
+            Token::Punct("(") => {
+                self.skip_newlines();
+                let term = self.expr()?;
+                if !self.punct_skipping(")") {
+                    return self.error(format!("expected \")\", found {}", describe(self.peek())));
+                }
+                self.next();
+                Ok(term)
+            }
+            Token::Punct("[") => {
+                self.skip_newlines();
+                if self.is_punct("]") {
+                    self.next();
+                    return Ok(Term::Array(Vec::new()));
+                }
+                let first = self.expr()?;
+                if self.punct_skipping("|") {
+                    self.next();
+                    let body = self.body("]")?;
+                    return Ok(Term::Comprehension(Box::new(first), body));
+                }
+                let mut items = vec![first];
+                while self.punct_skipping(",") {
+                    self.next();
+                    self.skip_newlines();
+                    if self.is_punct("]") {
+                        break;
+                    }
+                    items.push(self.expr()?);
+                }
+                if !self.punct_skipping("]") {
+                    return self.error(format!("expected \"]\", found {}", describe(self.peek())));
+                }
+                self.next();
+                Ok(Term::Array(items))
+            }
+            Token::Punct("{") => {
+                let mut pairs = Vec::new();
+                loop {
+                    self.skip_newlines();
+                    if self.is_punct("}") {
+                        self.next();
+                        return Ok(Term::Object(pairs));
+                    }
+                    let key = self.term()?;
+                    if !self.is_punct(":") {
+                        return self.error("sets aren't supported".to_string());
+                    }
+                    self.next();
+                    self.skip_newlines();
+                    pairs.push((key, self.expr()?));
+                    if self.punct_skipping(",") {
+                        self.next();
+                    } else if !self.punct_skipping("}") {
+                        return self
+                            .error(format!("expected \"}}\", found {}", describe(self.peek())));
+                    }
+                }
+            }
+            token => {
+                self.pos -= 1;
+                self.error(format!("unexpected {}", describe(&token)))
+            }
+        }
+    }
+
+    /// Comma separated terms up to `close`
+    fn terms(&mut self, close: &str) -> Result<Vec<Term>, ParseError> {
+        let mut terms = Vec::new();
+        loop {
+            self.skip_newlines();
+            if self.is_punct(close) {
+                self.next();
+                return Ok(terms);
+            }
+            terms.push(self.expr()?);
+            if self.punct_skipping(",") {
+                self.next();
+            } else if !self.punct_skipping(close) {
+                return self.error(format!(
+                    "expected {:?}, found {}",
+                    close,
+                    describe(self.peek())
+                ));
+            }
+        }
+    }
+}
+
+fn push_path(term: Term, key: Term) -> Term {
+    match term {
+        Term::Ref(head, mut path) => {
+            path.push(key);
+            Term::Ref(head, path)
+        }
+        term => Term::Ref(Box::new(term), vec![key]),
+    }
+}
+
+/// `f` or the dotted name of a builtin like `object.get`
+fn function_name(term: &Term) -> Option<String> {
+    match term {
+        Term::Var(name) if !name.starts_with(WILDCARD) => Some(name.clone()),
+        Term::Ref(head, path) => {
+            let mut name = function_name(head)?;
+            for key in path {
+                match key {
+                    Term::Scalar(Value::String(key)) => {
+                        name.push('.');
+                        name.push_str(key);
+                    }
+                    _ => return None,
+                }
+            }
+            Some(name)
+        }
+        _ => None,
+    }
+}
+
+fn describe(token: &Token) -> String {
+    match token {
+        Token::Ident(ident) => ident.clone(),
+        Token::String(s) => format!("{:?}", s),
+        Token::Number(n) => n.to_string(),
+        Token::Punct(p) => format!("{:?}", p),
+        Token::Newline => "end of line".to_string(),
+        Token::Eof => "end of policy".to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_rules() {
+        let module = parse(
+            r#"
+package policy.test
+
+import future.keywords.if
+import data.lib.sgx
+
+default allow = false
+
+allow if {
+    input.svn >= data.svn; count(data.mrEnclave) > 0
+    not input.debug
+}
+
+ids[id] { id := data.ids[_] }
+
+f("tee", x) = x + 1 {
+    x > 0
+}
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(module.package, vec!["policy", "test"]);
+        assert_eq!(
+            module.imports,
+            vec![Import {
+                path: vec!["data".to_string(), "lib".to_string(), "sgx".to_string()],
+                alias: "sgx".to_string(),
+            }]
+        );
+        assert_eq!(module.rules.len(), 4);
+        assert_eq!(
+            module.rules[0].kind,
+            RuleKind::Default(Term::Scalar(Value::Bool(false)))
+        );
+        assert_eq!(module.rules[1].body.len(), 3);
+        assert_eq!(module.rules[1].line, 9);
+        assert!(matches!(module.rules[1].body[2], Literal::Not(_)));
+        assert_eq!(
+            module.rules[2].kind,
+            RuleKind::Set(Term::Var("id".to_string()))
+        );
+        assert!(matches!(&module.rules[3].kind, RuleKind::Function(args, _) if args.len() == 2));
+    }
+
+    #[test]
+    fn test_parse_terms() {
+        let module = parse(
+            "package p\nx := [i | data.a[i] > input.b[i]]\ny = {\"a\": [1,\n 2], \"b\": object.get(input, \"c\", -1)}",
+        )
+        .unwrap();
+        match &module.rules[0].kind {
+            RuleKind::Complete(Term::Comprehension(term, body)) => {
+                assert_eq!(**term, Term::Var("i".to_string()));
+                assert_eq!(body.len(), 1);
+            }
+            kind => panic!("unexpected {:?}", kind),
+        }
+        match &module.rules[1].kind {
+            RuleKind::Complete(Term::Object(pairs)) => {
+                assert!(
+                    matches!(&pairs[1].1, Term::Call(name, args) if name == "object.get" && args.len() == 3)
+                );
+            }
+            kind => panic!("unexpected {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let e = parse("allow { true }").unwrap_err();
+        assert_eq!(e.line, 1);
+
+        let e = parse("package p\n\nallow {\n    input.a ==\n}").unwrap_err();
+        assert_eq!((e.line, e.column), (5, 1));
+
+        assert!(parse("package p\nimport lib.x\n").is_err());
+        assert!(parse("package p\nimport data as d\n").is_err());
+        assert!(parse("package p\nallow { x := {1, 2} }").is_err());
+        assert!(parse("package p\nallow").is_err());
+    }
+}
@@ -0,0 +1,115 @@
+//! Claims of a Hygon CSV guest.
+//!
+//! The guest policy has the SEV layout:
+//!
+//! ```text
+//! bit 0      NODBG      debugging of the guest is disallowed
+//! bit 1      NOKS       sharing keys with other guests is disallowed
+//! bit 2      ES         CSV-ES is required
+//! bit 3      NOSEND     sending the guest to another platform is disallowed
+//! bit 4      DOMAIN     the guest must not be sent out of the domain
+//! bit 5      SEV        the guest must not be sent to a non CSV platform
+//! bit 16-23  API_MAJOR  min firmware API major version
+//! bit 24-31  API_MINOR  min firmware API minor version
+//! ```
+use super::ffi::*;
+use super::verifier::{raw_slice, EvidenceVerifier, PolicySelector};
+use crate::resources::opa;
+use serde_json::Value;
+
+const POLICY_NODBG: u32 = 1 << 0;
+const POLICY_NOKS: u32 = 1 << 1;
+const POLICY_ES: u32 = 1 << 2;
+const POLICY_NOSEND: u32 = 1 << 3;
+const POLICY_DOMAIN: u32 = 1 << 4;
+const POLICY_SEV: u32 = 1 << 5;
+
+pub fn decode_policy(policy: u32) -> Value {
+    serde_json::json!({
+        "raw": policy,
+        "nodbg": policy & POLICY_NODBG != 0,
+        "noks": policy & POLICY_NOKS != 0,
+        "es": policy & POLICY_ES != 0,
+        "nosend": policy & POLICY_NOSEND != 0,
+        "domain": policy & POLICY_DOMAIN != 0,
+        "sev": policy & POLICY_SEV != 0,
+        "apiMajor": (policy >> 16) & 0xff,
+        "apiMinor": (policy >> 24) & 0xff,
+    })
+}
+
+pub fn claims(
+    vm_id: &[u8],
+    vm_version: &[u8],
+    measure: &[u8],
+    policy: &[u8],
+) -> Result<Value, String> {
+    if measure.is_empty() {
+        return Err("CSV evidence has no measure".to_string());
+    }
+    if policy.len() < 4 {
+        return Err(format!("invalid CSV guest policy size {}", policy.len()));
+    }
+    let policy = u32::from_le_bytes([policy[0], policy[1], policy[2], policy[3]]);
+
+    Ok(serde_json::json!({
+        "vmId": base64::encode(vm_id),
+        "vmVersion": base64::encode(vm_version),
+        "measure": base64::encode(measure),
+        "policy": decode_policy(policy),
+        "debug": policy & POLICY_NODBG == 0,
+        "migratable": policy & POLICY_NOSEND == 0,
+    }))
+}
+
+pub struct CsvVerifier;
+
+impl EvidenceVerifier for CsvVerifier {
+    fn tee(&self) -> &'static str {
+        "csv"
+    }
+
+    fn evidence_type(&self) -> enclave_evidence_type_t {
+        enclave_evidence_type_t_CSV
+    }
+
+    unsafe fn claims(&self, evidence: &rtls_evidence) -> Result<Value, String> {
+        let ev = &evidence.__bindgen_anon_1.csv;
+        claims(
+            raw_slice(ev.vm_id, ev.vm_id_sz as usize),
+            raw_slice(ev.vm_version, ev.vm_version_sz as usize),
+            raw_slice(ev.measure, ev.measure_sz as usize),
+            raw_slice(ev.policy, ev.policy_sz as usize),
+        )
+    }
+
+    fn policy(&self, _claims: &Value) -> PolicySelector {
+        PolicySelector::new(opa::OPA_POLICY_CSV, opa::OPA_DATA_CSV)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_claims() {
+        let policy = (POLICY_NODBG | POLICY_NOKS | POLICY_ES) | (2 << 16) | (1 << 24);
+        let claims = claims(&[1u8; 16], &[2u8; 16], &[3u8; 32], &policy.to_le_bytes()).unwrap();
+
+        assert_eq!(claims["vmId"], base64::encode([1u8; 16]));
+        assert_eq!(claims["vmVersion"], base64::encode([2u8; 16]));
+        assert_eq!(claims["measure"], base64::encode([3u8; 32]));
+        assert_eq!(claims["policy"]["raw"], policy);
+        assert_eq!(claims["policy"]["nodbg"], true);
+        assert_eq!(claims["policy"]["es"], true);
+        assert_eq!(claims["policy"]["nosend"], false);
+        assert_eq!(claims["policy"]["apiMajor"], 2);
+        assert_eq!(claims["policy"]["apiMinor"], 1);
+        assert_eq!(claims["debug"], false);
+        assert_eq!(claims["migratable"], true);
+
+        assert!(super::claims(&[], &[], &[3u8; 32], &[0u8; 2]).is_err());
+        assert!(super::claims(&[], &[], &[], &[0u8; 4]).is_err());
+    }
+}
@@ -590,19 +590,130 @@ pub type rtls_csv_evidence_t = rtls_csv_evidence;
 pub type rtls_sgx_evidence_t = rtls_sgx_evidence;
 #[repr(C)]
 #[derive(Debug, Copy, Clone)]
-pub struct rtls_tdx_evidence {}
+pub struct rtls_tdx_evidence {
+    pub mrseam: *mut u8,
+    pub mrseamsigner: *mut u8,
+    pub tcb_svns: *mut u8,
+    pub cpu_svn: *mut u8,
+    pub mrtd: *mut u8,
+    pub rtmr: *mut u8,
+    pub tdel_info: *mut u8,
+    pub tdel_info_sz: u32,
+    pub tdel_data: *mut u8,
+    pub tdel_data_sz: u32,
+}
 #[test]
 fn bindgen_test_layout_rtls_tdx_evidence() {
     assert_eq!(
         ::std::mem::size_of::<rtls_tdx_evidence>(),
-        0usize,
+        80usize,
         concat!("Size of: ", stringify!(rtls_tdx_evidence))
     );
     assert_eq!(
         ::std::mem::align_of::<rtls_tdx_evidence>(),
-        1usize,
+        8usize,
         concat!("Alignment of ", stringify!(rtls_tdx_evidence))
     );
+    assert_eq!(
+        unsafe { &(*(::std::ptr::null::<rtls_tdx_evidence>())).mrseam as *const _ as usize },
+        0usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(rtls_tdx_evidence),
+            "::",
+            stringify!(mrseam)
+        )
+    );
+    assert_eq!(
+        unsafe { &(*(::std::ptr::null::<rtls_tdx_evidence>())).mrseamsigner as *const _ as usize },
+        8usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(rtls_tdx_evidence),
+            "::",
+            stringify!(mrseamsigner)
+        )
+    );
+    assert_eq!(
+        unsafe { &(*(::std::ptr::null::<rtls_tdx_evidence>())).tcb_svns as *const _ as usize },
+        16usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(rtls_tdx_evidence),
+            "::",
+            stringify!(tcb_svns)
+        )
+    );
+    assert_eq!(
+        unsafe { &(*(::std::ptr::null::<rtls_tdx_evidence>())).cpu_svn as *const _ as usize },
+        24usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(rtls_tdx_evidence),
+            "::",
+            stringify!(cpu_svn)
+        )
+    );
+    assert_eq!(
+        unsafe { &(*(::std::ptr::null::<rtls_tdx_evidence>())).mrtd as *const _ as usize },
+        32usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(rtls_tdx_evidence),
+            "::",
+            stringify!(mrtd)
+        )
+    );
+    assert_eq!(
+        unsafe { &(*(::std::ptr::null::<rtls_tdx_evidence>())).rtmr as *const _ as usize },
+        40usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(rtls_tdx_evidence),
+            "::",
+            stringify!(rtmr)
+        )
+    );
+    assert_eq!(
+        unsafe { &(*(::std::ptr::null::<rtls_tdx_evidence>())).tdel_info as *const _ as usize },
+        48usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(rtls_tdx_evidence),
+            "::",
+            stringify!(tdel_info)
+        )
+    );
+    assert_eq!(
+        unsafe { &(*(::std::ptr::null::<rtls_tdx_evidence>())).tdel_info_sz as *const _ as usize },
+        56usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(rtls_tdx_evidence),
+            "::",
+            stringify!(tdel_info_sz)
+        )
+    );
+    assert_eq!(
+        unsafe { &(*(::std::ptr::null::<rtls_tdx_evidence>())).tdel_data as *const _ as usize },
+        64usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(rtls_tdx_evidence),
+            "::",
+            stringify!(tdel_data)
+        )
+    );
+    assert_eq!(
+        unsafe { &(*(::std::ptr::null::<rtls_tdx_evidence>())).tdel_data_sz as *const _ as usize },
+        72usize,
+        concat!(
+            "Offset of field: ",
+            stringify!(rtls_tdx_evidence),
+            "::",
+            stringify!(tdel_data_sz)
+        )
+    );
 }
 pub type rtls_tdx_evidence_t = rtls_tdx_evidence;
 #[repr(C)]
@@ -809,7 +920,7 @@ pub union rtls_evidence__bindgen_ty_1 {
 fn bindgen_test_layout_rtls_evidence__bindgen_ty_1() {
     assert_eq!(
         ::std::mem::size_of::<rtls_evidence__bindgen_ty_1>(),
-        64usize,
+        80usize,
         concat!("Size of: ", stringify!(rtls_evidence__bindgen_ty_1))
     );
     assert_eq!(
@@ -842,7 +953,7 @@ fn bindgen_test_layout_rtls_evidence__bindgen_ty_1() {
 fn bindgen_test_layout_rtls_evidence() {
     assert_eq!(
         ::std::mem::size_of::<rtls_evidence>(),
-        128usize,
+        144usize,
         concat!("Size of: ", stringify!(rtls_evidence))
     );
     assert_eq!(
@@ -897,6 +1008,7 @@ pub type rats_tls_callback_t = ::std::option::Option<
     unsafe extern "C" fn(arg1: *mut ::std::os::raw::c_void) -> ::std::os::raw::c_int,
 >;
 
+#[cfg(not(feature = "mock-rats-tls"))]
 extern "C" {
     pub fn rats_tls_init(
         conf: *const rats_tls_conf_t,
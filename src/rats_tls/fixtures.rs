@@ -0,0 +1,123 @@
+//! Evidence fixtures as rats-tls hands them to the verification callback.
+use super::ffi::*;
+use super::tdx::{QUOTE_HEADER_LEN, QUOTE_VERSION, TD_REPORT_LEN, TEE_TYPE_TDX};
+
+/// An evidence and the buffers its pointers refer to
+pub struct Fixture {
+    pub evidence: rtls_evidence,
+    buffers: Vec<Vec<u8>>,
+}
+
+// The evidence only points into the buffers the fixture owns
+unsafe impl Send for Fixture {}
+
+impl Fixture {
+    fn new(type_: enclave_evidence_type_t) -> Self {
+        let mut evidence: rtls_evidence = unsafe { std::mem::zeroed() };
+        evidence.type_ = type_;
+        Fixture {
+            evidence,
+            buffers: Vec::new(),
+        }
+    }
+
+    /// Keep `bytes` alive as long as the fixture and return a pointer to them
+    fn buffer(&mut self, bytes: Vec<u8>) -> *mut u8 {
+        self.buffers.push(bytes);
+        self.buffers.last_mut().unwrap().as_mut_ptr()
+    }
+}
+
+/// A TDX quote whose report body fields are filled with their own byte
+pub fn tdx_quote(td_attributes: u64) -> Vec<u8> {
+    let mut quote = vec![0u8; QUOTE_HEADER_LEN + TD_REPORT_LEN + 64];
+    quote[0..2].copy_from_slice(&QUOTE_VERSION.to_le_bytes());
+    quote[4..8].copy_from_slice(&TEE_TYPE_TDX.to_le_bytes());
+
+    let body = &mut quote[QUOTE_HEADER_LEN..];
+    for (i, (offset, len)) in [
+        (0, 16),
+        (136, 48),
+        (184, 48),
+        (232, 48),
+        (328, 48),
+        (472, 48),
+    ]
+    .iter()
+    .enumerate()
+    {
+        body[*offset..offset + len].fill(i as u8 + 1);
+    }
+    body[120..128].copy_from_slice(&td_attributes.to_le_bytes());
+    body[128..136].copy_from_slice(&0xe7u64.to_le_bytes());
+    quote
+}
+
+/// SGX SECS attributes
+pub fn sgx_attributes(flags: u64, xfrm: u64) -> Vec<u8> {
+    let mut attributes = flags.to_le_bytes().to_vec();
+    attributes.extend_from_slice(&xfrm.to_le_bytes());
+    attributes
+}
+
+pub fn sgx(flags: u64, collateral: &str) -> Fixture {
+    let mut fixture = Fixture::new(enclave_evidence_type_t_SGX_ECDSA);
+    let mr_enclave = fixture.buffer(vec![1u8; 32]);
+    let mr_signer = fixture.buffer(vec![2u8; 32]);
+    let attributes = fixture.buffer(sgx_attributes(flags, 0x3));
+    let collateral_size = collateral.len();
+    let collateral = fixture.buffer(collateral.as_bytes().to_vec());
+
+    let sgx = unsafe { &mut fixture.evidence.__bindgen_anon_1.sgx };
+    sgx.mr_enclave = mr_enclave;
+    sgx.mr_signer = mr_signer;
+    sgx.product_id = 1;
+    sgx.security_version = 2;
+    sgx.attributes = attributes;
+    sgx.collateral_size = collateral_size as _;
+    sgx.collateral = collateral as *mut _;
+    fixture
+}
+
+/// A TDX evidence as rats-tls 0.6.4 hands it, the fields of
+/// `rtls_tdx_evidence` point into the TD report body of the quote
+pub fn tdx(td_attributes: u64) -> Fixture {
+    let mut fixture = Fixture::new(enclave_evidence_type_t_TDX);
+    let quote = tdx_quote(td_attributes);
+    let quote_size = quote.len();
+    let quote = fixture.buffer(quote);
+    let cpu_svn = fixture.buffer(vec![7u8; 16]);
+    fixture.evidence.quote_size = quote_size as _;
+    fixture.evidence.quote = quote as *mut _;
+
+    let tdx = unsafe { &mut fixture.evidence.__bindgen_anon_1.tdx };
+    let body = unsafe { quote.add(QUOTE_HEADER_LEN) };
+    unsafe {
+        tdx.tcb_svns = body;
+        tdx.mrseam = body.add(16);
+        tdx.mrseamsigner = body.add(64);
+        tdx.mrtd = body.add(136);
+        tdx.rtmr = body.add(328);
+    }
+    tdx.cpu_svn = cpu_svn;
+    fixture
+}
+
+pub fn csv(policy: u32) -> Fixture {
+    let mut fixture = Fixture::new(enclave_evidence_type_t_CSV);
+    let vm_id = fixture.buffer(vec![1u8; 16]);
+    let vm_version = fixture.buffer(vec![2u8; 16]);
+    let measure = fixture.buffer(vec![3u8; 32]);
+    let policy = fixture.buffer(policy.to_le_bytes().to_vec());
+
+    let csv = unsafe { &mut fixture.evidence.__bindgen_anon_1.csv };
+    csv.vm_id = vm_id;
+    csv.vm_id_sz = 16;
+    csv.vm_version = vm_version;
+    csv.vm_version_sz = 16;
+    csv.measure = measure;
+    csv.measure_sz = 32;
+    csv.policy = policy;
+    csv.policy_sz = 4;
+    fixture
+}
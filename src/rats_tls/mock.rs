@@ -0,0 +1,205 @@
+//! Pure Rust stand-in for librats_tls, built with the `mock-rats-tls`
+//! feature so the attestation path can be tested without the C library.
+//!
+//! Negotiation runs no TLS handshake. An endpoint hands the evidence
+//! scripted for its socket with `script_evidence` to the verification
+//! callback and fails, as rats-tls does, when the callback rejects it. A
+//! server endpoint with mutual attestation fails without scripted evidence.
+//! Receive and transmit pass the data through the socket in plain text, so
+//! two endpoints on a socket pair talk to each other.
+//!
+//! Release builds refuse the feature, a verdictd built with it would serve
+//! keys without attestation.
+#[cfg(all(feature = "mock-rats-tls", not(debug_assertions)))]
+compile_error!("mock-rats-tls skips attestation and TLS, it can't be enabled in release builds");
+
+use super::ffi::*;
+use super::fixtures::Fixture;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::mem::ManuallyDrop;
+use std::os::unix::io::{FromRawFd, RawFd};
+
+lazy_static! {
+    // Evidence the next negotiation on a socket presents
+    static ref SCRIPTS: Mutex<HashMap<RawFd, Fixture>> = Mutex::new(HashMap::new());
+}
+
+struct Handle {
+    flags: u64,
+    callback: rats_tls_callback_t,
+    fd: Option<RawFd>,
+}
+
+/// Present `evidence` in the next negotiation on the socket `fd`
+pub fn script_evidence(fd: RawFd, evidence: Fixture) {
+    SCRIPTS.lock().insert(fd, evidence);
+}
+
+unsafe fn handle<'a>(handle: *const rats_tls_handle) -> Option<&'a mut Handle> {
+    (handle as *mut Handle).as_mut()
+}
+
+/// The socket `fd`, without closing it on drop
+unsafe fn socket(fd: RawFd) -> ManuallyDrop<File> {
+    ManuallyDrop::new(File::from_raw_fd(fd))
+}
+
+pub unsafe fn rats_tls_init(
+    conf: *const rats_tls_conf_t,
+    handle: *mut *mut rats_tls_handle,
+) -> rats_tls_err_t {
+    if conf.is_null() || handle.is_null() {
+        return RATS_TLS_ERR_INVALID;
+    }
+
+    *handle = Box::into_raw(Box::new(Handle {
+        flags: (*conf).flags,
+        callback: None,
+        fd: None,
+    })) as *mut rats_tls_handle;
+    RATS_TLS_ERR_NONE
+}
+
+pub unsafe fn rats_tls_set_verification_callback(
+    tls: *mut *mut rats_tls_handle,
+    user_callback: rats_tls_callback_t,
+) -> rats_tls_err_t {
+    match tls.as_ref().and_then(|tls| handle(*tls)) {
+        Some(handle) => {
+            handle.callback = user_callback;
+            RATS_TLS_ERR_NONE
+        }
+        None => RATS_TLS_ERR_INVALID,
+    }
+}
+
+pub unsafe fn rats_tls_negotiate(
+    tls: *const rats_tls_handle,
+    fd: ::std::os::raw::c_int,
+) -> rats_tls_err_t {
+    let handle = match handle(tls) {
+        Some(handle) => handle,
+        None => return RATS_TLS_ERR_INVALID,
+    };
+    handle.fd = Some(fd);
+
+    let server = handle.flags & RATS_TLS_CONF_FLAGS_SERVER != 0;
+    let mutual = handle.flags & RATS_TLS_CONF_FLAGS_MUTUAL != 0;
+    let script = SCRIPTS.lock().remove(&fd);
+    match (script, handle.callback) {
+        (Some(mut fixture), Some(callback)) if !server || mutual => {
+            let evidence = &mut fixture.evidence as *mut rtls_evidence;
+            if callback(evidence as *mut ::std::os::raw::c_void) == 0 {
+                return RATS_TLS_ERR_UNKNOWN;
+            }
+            RATS_TLS_ERR_NONE
+        }
+        (None, _) if server && mutual => RATS_TLS_ERR_UNKNOWN,
+        _ => RATS_TLS_ERR_NONE,
+    }
+}
+
+pub unsafe fn rats_tls_receive(
+    tls: *const rats_tls_handle,
+    buf: *mut ::std::os::raw::c_void,
+    buf_size: *mut size_t,
+) -> rats_tls_err_t {
+    let fd = match handle(tls).and_then(|handle| handle.fd) {
+        Some(fd) => fd,
+        None => return RATS_TLS_ERR_INVALID,
+    };
+
+    let buf = std::slice::from_raw_parts_mut(buf as *mut u8, *buf_size as usize);
+    match socket(fd).read(buf) {
+        Ok(len) => {
+            *buf_size = len as size_t;
+            RATS_TLS_ERR_NONE
+        }
+        Err(_) => RATS_TLS_ERR_UNKNOWN,
+    }
+}
+
+pub unsafe fn rats_tls_transmit(
+    tls: *const rats_tls_handle,
+    buf: *const ::std::os::raw::c_void,
+    buf_size: *mut size_t,
+) -> rats_tls_err_t {
+    let fd = match handle(tls).and_then(|handle| handle.fd) {
+        Some(fd) => fd,
+        None => return RATS_TLS_ERR_INVALID,
+    };
+
+    let buf = std::slice::from_raw_parts(buf as *const u8, *buf_size as usize);
+    match socket(fd).write(buf) {
+        Ok(len) => {
+            *buf_size = len as size_t;
+            RATS_TLS_ERR_NONE
+        }
+        Err(_) => RATS_TLS_ERR_UNKNOWN,
+    }
+}
+
+pub unsafe fn rats_tls_cleanup(tls: *mut rats_tls_handle) -> rats_tls_err_t {
+    if !tls.is_null() {
+        drop(Box::from_raw(tls as *mut Handle));
+    }
+    RATS_TLS_ERR_NONE
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{fixtures, RatsTls};
+    use super::*;
+    use crate::resources::opa;
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::UnixStream;
+
+    fn endpoint(server: bool, mutual: bool) -> RatsTls {
+        RatsTls::new(server, 0, &None, &None, &None, &None, mutual).unwrap()
+    }
+
+    #[test]
+    fn test_negotiate() {
+        opa::default().unwrap();
+        let (server, _client) = UnixStream::pair().unwrap();
+
+        let tls = endpoint(true, true);
+        assert!(tls.negotiate(server.as_raw_fd()).is_err());
+
+        script_evidence(server.as_raw_fd(), fixtures::csv(0x1));
+        assert!(tls.negotiate(server.as_raw_fd()).is_ok());
+        assert_eq!(RatsTls::take_verified_evidence().unwrap().tee, "csv");
+
+        let mut unsupported = fixtures::csv(0x1);
+        unsupported.evidence.type_ = 0;
+        script_evidence(server.as_raw_fd(), unsupported);
+        assert!(tls.negotiate(server.as_raw_fd()).is_err());
+
+        // Without mutual attestation the server doesn't verify its peer
+        let tls = endpoint(true, false);
+        script_evidence(server.as_raw_fd(), fixtures::csv(0x1));
+        assert!(tls.negotiate(server.as_raw_fd()).is_ok());
+        assert!(RatsTls::take_verified_evidence().is_none());
+    }
+
+    #[test]
+    fn test_pipe() {
+        let (server, client) = UnixStream::pair().unwrap();
+        let server_tls = endpoint(true, false);
+        let client_tls = endpoint(false, false);
+        assert!(server_tls.negotiate(server.as_raw_fd()).is_ok());
+        assert!(client_tls.negotiate(client.as_raw_fd()).is_ok());
+
+        assert_eq!(client_tls.transmit(b"ping").unwrap(), 4);
+        let mut buf = [0u8; 16];
+        let len = server_tls.receive(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"ping");
+
+        drop(client);
+        assert_eq!(server_tls.receive(&mut buf).unwrap(), 0);
+    }
+}
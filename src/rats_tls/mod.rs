@@ -2,16 +2,83 @@
  *
  * SPDX-License-Identifier: Apache-2.0
  */
-use crate::policy_engine;
-use crate::resources;
-use base64;
+use crate::policy_engine::{self, cache, Decision};
+use crate::resources::{audit, policy_binding};
 use foreign_types::{ForeignType, ForeignTypeRef, Opaque};
+use std::cell::RefCell;
 use std::ops::{Deref, DerefMut};
 use std::os::unix::io::RawFd;
 use std::ptr::NonNull;
 
+mod csv;
 mod ffi;
+#[cfg(any(test, feature = "mock-rats-tls"))]
+pub mod fixtures;
+#[cfg(feature = "mock-rats-tls")]
+pub mod mock;
+mod sgx;
+mod tdx;
+mod verifier;
 use ffi::*;
+#[cfg(feature = "mock-rats-tls")]
+use mock::*;
+use verifier::PolicySelector;
+
+/// Claims of a peer whose evidence passed the attestation policy
+#[derive(Debug, Clone, Default)]
+pub struct PeerEvidence {
+    pub tee: String,
+    pub claims: serde_json::Value,
+    /// Name of the OPA policy the evidence passed
+    pub policy: String,
+    /// Name of the OPA reference the evidence passed
+    pub reference: String,
+    /// SHA-256 of the policy and the reference at the time of the decision,
+    /// of the archive if the policy is a bundle
+    pub policy_hash: String,
+    pub reference_hash: String,
+    /// Whether `policy` names an OPA bundle
+    pub bundle: bool,
+    /// Unix time of the decision
+    pub verified_at: u64,
+    /// Decision of the policy, its claims annotate the session
+    pub decision: Decision,
+}
+
+impl PeerEvidence {
+    /// Audit record of the decision on this evidence
+    pub fn audit_record(&self, peer: &str, decision: &str) -> audit::Record {
+        audit::Record {
+            timestamp: self.verified_at,
+            peer: peer.to_string(),
+            tee: self.tee.clone(),
+            claims: self.claims.clone(),
+            policy: self.policy.clone(),
+            policy_hash: self.policy_hash.clone(),
+            reference: self.reference.clone(),
+            reference_hash: self.reference_hash.clone(),
+            bundle: self.bundle,
+            decision: decision.to_string(),
+            deny_reasons: self.decision.deny_reasons.clone(),
+            policy_claims: self.decision.claims.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+/// Addresses of the connection negotiated on this thread
+#[derive(Debug, Clone, Default)]
+struct Connection {
+    listener: String,
+    peer: String,
+}
+
+thread_local! {
+    // rats-tls calls the verification callback on the thread negotiating
+    // the connection, so the result is handed over through a thread local.
+    static VERIFIED_EVIDENCE: RefCell<Option<PeerEvidence>> = const { RefCell::new(None) };
+    static CONNECTION: RefCell<Option<Connection>> = const { RefCell::new(None) };
+}
 
 pub struct RatsTlsRef(Opaque);
 
@@ -118,7 +185,10 @@ impl RatsTls {
         }
     }
 
+    /// Negotiate the connection, `take_verified_evidence` returns the
+    /// verified peer evidence afterwards
     pub fn negotiate(&self, fd: RawFd) -> Result<(), rats_tls_err_t> {
+        VERIFIED_EVIDENCE.with(|evidence| evidence.borrow_mut().take());
         let err = unsafe { rats_tls_negotiate(self.as_ptr(), fd) };
         if err == RATS_TLS_ERR_NONE {
             Ok(())
@@ -159,71 +229,158 @@ impl RatsTls {
         }
     }
 
-    fn sgx_callback(ev: rtls_sgx_evidence_t) -> Result<(), String> {
-        let mr_enclave =
-            base64::encode(unsafe { std::slice::from_raw_parts(ev.mr_enclave, 32).to_vec() });
-        let mr_signer =
-            base64::encode(unsafe { std::slice::from_raw_parts(ev.mr_signer, 32).to_vec() });
-
-        let input = serde_json::json!({
-            "mrEnclave": mr_enclave,
-            "mrSigner": mr_signer,
-            "productId": ev.product_id,
-            "svn": ev.security_version
+    /// Record the listener the connection negotiated on this thread was
+    /// accepted on and its peer. Policy bindings select the attestation
+    /// policy by the listener, the audit log records the peer.
+    pub fn set_connection(listener: &str, peer: &str) {
+        CONNECTION.with(|c| {
+            *c.borrow_mut() = Some(Connection {
+                listener: listener.to_string(),
+                peer: peer.to_string(),
+            })
         });
+    }
 
-        policy_engine::opa::opa_engine::make_decision(
-            resources::opa::OPA_POLICY_SGX,
-            resources::opa::OPA_DATA_SGX,
-            &input.to_string(),
-        )
-        .map_err(|e| format!("make_decision error: {}", e))
-        .and_then(|res| serde_json::from_str(&res).map_err(|_| "Json unmashall failed".to_string()))
-        .and_then(|res: serde_json::Value| {
-            if res["allow"] == true {
-                Ok(())
-            } else {
-                error!("parseInfo: {}", res["parseInfo"].to_string());
-                Err("decision is false".to_string())
-            }
+    /// Run `f` with `listener` and `peer` as the connection of this thread,
+    /// e.g. to negotiate an outgoing connection inside a session. The
+    /// connection and the verified evidence of the session are restored
+    /// afterwards.
+    pub fn with_connection<T>(listener: &str, peer: &str, f: impl FnOnce() -> T) -> T {
+        let connection = CONNECTION.with(|c| c.borrow_mut().take());
+        let evidence = Self::take_verified_evidence();
+        Self::set_connection(listener, peer);
+
+        let res = f();
+
+        CONNECTION.with(|c| *c.borrow_mut() = connection);
+        VERIFIED_EVIDENCE.with(|verified| *verified.borrow_mut() = evidence);
+        res
+    }
+
+    /// Peer of the connection negotiated on this thread
+    pub fn peer_address() -> String {
+        CONNECTION.with(|c| {
+            c.borrow()
+                .as_ref()
+                .map(|c| c.peer.clone())
+                .unwrap_or_default()
         })
     }
 
-    fn csv_callback(ev: rtls_csv_evidence_t) -> Result<(), String> {
-        let measure_b64 =
-            base64::encode(unsafe { std::slice::from_raw_parts(ev.measure, 32).to_vec() });
-
-        let input = serde_json::json!({ "measure": measure_b64 });
-
-        policy_engine::opa::opa_engine::make_decision(
-            resources::opa::OPA_POLICY_CSV,
-            resources::opa::OPA_DATA_CSV,
-            &input.to_string(),
-        )
-        .map_err(|e| format!("make_decision error: {}", e))
-        .and_then(|res| serde_json::from_str(&res).map_err(|_| "Json unmashall failed".to_string()))
-        .and_then(|res: serde_json::Value| {
-            if res["allow"] == true {
-                Ok(())
-            } else {
-                error!("parseInfo: {}", res["parseInfo"].to_string());
-                Err("decision is false".to_string())
+    /// Take the evidence verified by the last `negotiate` on this thread
+    pub fn take_verified_evidence() -> Option<PeerEvidence> {
+        VERIFIED_EVIDENCE.with(|evidence| evidence.borrow_mut().take())
+    }
+
+    /// Evaluate the policy of `evidence` and keep its decision in it
+    fn verify(evidence: &mut PeerEvidence) -> Result<(), String> {
+        let input = evidence.claims.to_string();
+        evidence.decision = match evidence.bundle {
+            true => policy_engine::opa::opa_engine::make_decision_bundle(&evidence.policy, &input),
+            false => policy_engine::opa::opa_engine::make_decision(
+                &evidence.policy,
+                &evidence.reference,
+                &input,
+            ),
+        }
+        .map_err(|e| format!("make_decision error: {}", e))?;
+
+        if !evidence.decision.claims.is_empty() {
+            info!(
+                "policy claims: {}",
+                serde_json::Value::Object(evidence.decision.claims.clone())
+            );
+        }
+        match evidence.decision.allow {
+            true => Ok(()),
+            false => {
+                for reason in evidence.decision.deny_reasons.iter() {
+                    error!("deny reason: {}", reason);
+                }
+                Err(format!(
+                    "evidence is denied by {}: {}",
+                    evidence.policy,
+                    evidence.decision.deny_reasons.join("; ")
+                ))
             }
-        })
+        }
     }
 
     #[no_mangle]
     extern "C" fn callback(evidence: *mut ::std::os::raw::c_void) -> ::std::os::raw::c_int {
         info!("Verdictd Rats-TLS callback function is called.");
-        let evidence = evidence as *mut rtls_evidence;
-        let res = if unsafe { (*evidence).type_ } == enclave_evidence_type_t_SGX_ECDSA {
-            Self::sgx_callback(unsafe { (*evidence).__bindgen_anon_1.sgx })
-        } else if unsafe { (*evidence).type_ } == enclave_evidence_type_t_CSV {
-            Self::csv_callback(unsafe { (*evidence).__bindgen_anon_1.csv })
-        } else {
-            Err("Not implemented".to_string())
+        let connection = CONNECTION.with(|c| c.borrow().clone()).unwrap_or_default();
+        // What is known of the evidence when it's rejected
+        let mut rejected = PeerEvidence {
+            tee: "unknown".to_string(),
+            verified_at: audit::now(),
+            ..Default::default()
         };
 
+        // A panic must never unwind into rats-tls
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let evidence =
+                unsafe { (evidence as *const rtls_evidence).as_ref() }.ok_or("evidence is null")?;
+            let verifier = verifier::lookup(evidence.type_)?;
+            rejected.tee = verifier.tee().to_string();
+            let claims = unsafe { verifier.claims(evidence) }?;
+            rejected.claims = claims.clone();
+            let signer = verifier.signer(&claims);
+            let selector = match policy_binding::select(
+                verifier.tee(),
+                Some(connection.listener.as_str()).filter(|l| !l.is_empty()),
+                signer.as_deref(),
+            )? {
+                Some(binding) => {
+                    info!("evidence is evaluated with policy binding {}", binding.name);
+                    match &binding.bundle {
+                        Some(bundle) => PolicySelector::bundle(bundle),
+                        None => PolicySelector::new(&binding.policy, &binding.reference),
+                    }
+                }
+                None => verifier.policy(&claims),
+            };
+            let (policy_hash, reference_hash) = match selector.bundle {
+                true => (
+                    cache::load_bundle(&selector.policy)
+                        .map(|bundle| bundle.hash.clone())
+                        .unwrap_or_else(|_| audit::digest(b"")),
+                    String::new(),
+                ),
+                false => (
+                    cache::load(&selector.policy)
+                        .map(|source| source.hash)
+                        .unwrap_or_else(|_| audit::digest(b"")),
+                    cache::load(&selector.reference)
+                        .map(|source| source.hash)
+                        .unwrap_or_else(|_| audit::digest(b"")),
+                ),
+            };
+            let mut evidence = PeerEvidence {
+                tee: verifier.tee().to_string(),
+                claims,
+                policy_hash,
+                reference_hash,
+                bundle: selector.bundle,
+                policy: selector.policy,
+                reference: selector.reference,
+                verified_at: rejected.verified_at,
+                ..Default::default()
+            };
+            let res = Self::verify(&mut evidence);
+            rejected = evidence.clone();
+            res.map(|_| VERIFIED_EVIDENCE.with(|verified| *verified.borrow_mut() = Some(evidence)))
+        }))
+        .unwrap_or_else(|_| Err("evidence verifier panicked".to_string()));
+
+        if let Err(e) = &res {
+            let mut record = rejected.audit_record(&connection.peer, audit::DECISION_DENY);
+            record.error = Some(e.clone());
+            if let Err(e) = audit::append(record) {
+                error!("audit the rejected evidence failed: {}", e);
+            }
+        }
+
         let allow = match res {
             Ok(_) => 1,
             Err(e) => {
@@ -0,0 +1,280 @@
+//! Claims of an SGX enclave beyond its identity.
+//!
+//! The SECS attributes are 16 bytes, `FLAGS` then `XFRM`, both little endian:
+//!
+//! ```text
+//! FLAGS bit 1  DEBUG          the enclave is debuggable
+//! FLAGS bit 2  MODE64BIT      the enclave runs in 64-bit mode
+//! FLAGS bit 4  PROVISIONKEY   the enclave can get the provisioning key
+//! FLAGS bit 5  EINITTOKENKEY  the enclave can get the launch key
+//! FLAGS bit 7  KSS            key separation and sharing is enabled
+//! ```
+//!
+//! rats-tls only hands the collateral over as bytes. It's read as the JSON
+//! result of a DCAP quote verification if it is one, e.g. `{"tcbStatus": "UpToDate", "advisoryIDs": [], "tcbInfo": {...}}`.
+//! Without a top-level `tcbStatus`, the status is the one of the first of
+//! Intel's `tcbInfo.tcbLevels` the platform TCB in `pckTcb` reaches. The
+//! TCB status is null when the collateral is empty (no DCAP quote
+//! verification), isn't JSON or has no matching TCB level. The default SGX
+//! reference data accepts any TCB status, a policy requiring one also denies
+//! a null status.
+use super::ffi::*;
+use super::verifier::{raw_slice, EvidenceVerifier, PolicySelector};
+use crate::resources::opa;
+use serde_json::Value;
+
+const SGX_MEASUREMENT_LEN: usize = 32;
+const SGX_ATTRIBUTES_LEN: usize = 16;
+
+const SGX_FLAGS_DEBUG: u64 = 1 << 1;
+const SGX_FLAGS_MODE64BIT: u64 = 1 << 2;
+const SGX_FLAGS_PROVISIONKEY: u64 = 1 << 4;
+const SGX_FLAGS_EINITTOKENKEY: u64 = 1 << 5;
+const SGX_FLAGS_KSS: u64 = 1 << 7;
+
+fn u64_le(bytes: &[u8]) -> u64 {
+    let mut value = [0u8; 8];
+    value.copy_from_slice(&bytes[..8]);
+    u64::from_le_bytes(value)
+}
+
+pub fn parse_attributes(attributes: &[u8]) -> Result<Value, String> {
+    if attributes.len() < SGX_ATTRIBUTES_LEN {
+        return Err(format!(
+            "SGX attributes are too short: {} bytes",
+            attributes.len()
+        ));
+    }
+
+    let flags = u64_le(&attributes[0..8]);
+    let xfrm = u64_le(&attributes[8..16]);
+
+    Ok(serde_json::json!({
+        "attributes": format!("{:016x}", flags),
+        "xfrm": format!("{:016x}", xfrm),
+        "debug": flags & SGX_FLAGS_DEBUG != 0,
+        "mode64bit": flags & SGX_FLAGS_MODE64BIT != 0,
+        "provisionKey": flags & SGX_FLAGS_PROVISIONKEY != 0,
+        "einitTokenKey": flags & SGX_FLAGS_EINITTOKENKEY != 0,
+        "kss": flags & SGX_FLAGS_KSS != 0,
+    }))
+}
+
+/// SVNs of the 16 SGX TCB components and the PCE SVN of a TCB, in the
+/// format of Intel's TCB info v2 (`sgxtcbcomp01svn`...) or v3
+/// (`sgxtcbcomponents: [{"svn": ...}]`)
+fn tcb_svns(tcb: &Value) -> Option<(Vec<u64>, u64)> {
+    let components: Vec<u64> = match tcb.get("sgxtcbcomponents") {
+        Some(Value::Array(components)) => components
+            .iter()
+            .map(|component| component["svn"].as_u64())
+            .collect::<Option<Vec<u64>>>()?,
+        _ => (1..=16)
+            .map(|i| tcb[format!("sgxtcbcomp{:02}svn", i).as_str()].as_u64())
+            .collect::<Option<Vec<u64>>>()?,
+    };
+    if components.len() != 16 {
+        return None;
+    }
+    Some((components, tcb["pcesvn"].as_u64()?))
+}
+
+/// Status of the first TCB level `platform` reaches, the levels are
+/// ordered from the highest
+fn tcb_level_status(tcb_levels: &[Value], platform: &Value) -> Option<String> {
+    let (platform_components, platform_pce_svn) = tcb_svns(platform)?;
+    tcb_levels.iter().find_map(|level| {
+        let (components, pce_svn) = tcb_svns(&level["tcb"])?;
+        let reached = platform_components
+            .iter()
+            .zip(components.iter())
+            .all(|(platform, level)| platform >= level)
+            && platform_pce_svn >= pce_svn;
+        match reached {
+            true => level["tcbStatus"].as_str().map(String::from),
+            false => None,
+        }
+    })
+}
+
+pub fn parse_collateral(collateral: &[u8]) -> Value {
+    let empty = serde_json::json!({
+        "tcbStatus": Value::Null,
+        "advisoryIds": [],
+        "tcbEvaluationDataNumber": Value::Null,
+    });
+    if collateral.is_empty() {
+        return empty;
+    }
+
+    let collateral: Value = match serde_json::from_slice(collateral) {
+        Ok(collateral) => collateral,
+        Err(_) => {
+            warn!("SGX collateral isn't JSON, its TCB status is unknown");
+            return empty;
+        }
+    };
+    let tcb_status = match collateral
+        .get("tcbStatus")
+        .and_then(|status| status.as_str())
+    {
+        Some(status) => Some(status.to_string()),
+        None => match collateral.pointer("/tcbInfo/tcbLevels") {
+            Some(Value::Array(levels)) => tcb_level_status(levels, &collateral["pckTcb"]),
+            _ => None,
+        },
+    };
+    if tcb_status.is_none() {
+        warn!("SGX collateral has no TCB status for the platform");
+    }
+
+    serde_json::json!({
+        "tcbStatus": tcb_status,
+        "advisoryIds": collateral.get("advisoryIDs").cloned().unwrap_or(serde_json::json!([])),
+        "tcbEvaluationDataNumber": collateral
+            .pointer("/tcbInfo/tcbEvaluationDataNumber")
+            .cloned()
+            .unwrap_or(Value::Null),
+    })
+}
+
+pub struct SgxVerifier;
+
+impl EvidenceVerifier for SgxVerifier {
+    fn tee(&self) -> &'static str {
+        "sgx"
+    }
+
+    fn evidence_type(&self) -> enclave_evidence_type_t {
+        enclave_evidence_type_t_SGX_ECDSA
+    }
+
+    unsafe fn claims(&self, evidence: &rtls_evidence) -> Result<Value, String> {
+        let ev = &evidence.__bindgen_anon_1.sgx;
+        let mr_enclave = raw_slice(ev.mr_enclave, SGX_MEASUREMENT_LEN);
+        let mr_signer = raw_slice(ev.mr_signer, SGX_MEASUREMENT_LEN);
+        if mr_enclave.is_empty() || mr_signer.is_empty() {
+            return Err("SGX evidence has no mrEnclave or mrSigner".to_string());
+        }
+
+        let mut claims = serde_json::json!({
+            "mrEnclave": base64::encode(mr_enclave),
+            "mrSigner": base64::encode(mr_signer),
+            "productId": ev.product_id,
+            "svn": ev.security_version
+        });
+
+        let attributes = parse_attributes(raw_slice(ev.attributes, SGX_ATTRIBUTES_LEN))?;
+        let collateral = parse_collateral(raw_slice(
+            ev.collateral as *const u8,
+            ev.collateral_size as usize,
+        ));
+        for extra in [attributes, collateral] {
+            if let (Some(claims), Value::Object(extra)) = (claims.as_object_mut(), extra) {
+                claims.extend(extra);
+            }
+        }
+
+        Ok(claims)
+    }
+
+    fn policy(&self, _claims: &Value) -> PolicySelector {
+        PolicySelector::new(opa::OPA_POLICY_SGX, opa::OPA_DATA_SGX)
+    }
+
+    fn signer(&self, claims: &Value) -> Option<String> {
+        claims["mrSigner"].as_str().map(|s| s.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fixtures::sgx_attributes as attributes;
+    use super::*;
+
+    #[test]
+    fn test_parse_attributes() {
+        let claims = parse_attributes(&attributes(0x5, 0x3)).unwrap();
+        assert_eq!(claims["attributes"], "0000000000000005");
+        assert_eq!(claims["xfrm"], "0000000000000003");
+        assert_eq!(claims["debug"], false);
+        assert_eq!(claims["mode64bit"], true);
+        assert_eq!(claims["kss"], false);
+
+        let claims = parse_attributes(&attributes(0x7, 0x3)).unwrap();
+        assert_eq!(claims["debug"], true);
+
+        assert!(parse_attributes(&[0u8; 8]).is_err());
+    }
+
+    #[test]
+    fn test_parse_collateral() {
+        let claims = parse_collateral(b"");
+        assert_eq!(claims["tcbStatus"], Value::Null);
+
+        let collateral = serde_json::json!({
+            "tcbStatus": "SWHardeningNeeded",
+            "advisoryIDs": ["INTEL-SA-00334"],
+            "tcbInfo": { "tcbEvaluationDataNumber": 12 }
+        });
+        let claims = parse_collateral(collateral.to_string().as_bytes());
+        assert_eq!(claims["tcbStatus"], "SWHardeningNeeded");
+        assert_eq!(claims["advisoryIds"], serde_json::json!(["INTEL-SA-00334"]));
+        assert_eq!(claims["tcbEvaluationDataNumber"], 12);
+
+        // Binary collateral and collateral without status are left to the policy
+        for collateral in [
+            &b"\x01\x00binary"[..],
+            b"{}",
+            br#"{"tcbInfo": {"tcbStatus": "UpToDate"}}"#,
+        ] {
+            let claims = parse_collateral(collateral);
+            assert_eq!(claims["tcbStatus"], Value::Null);
+        }
+    }
+
+    fn tcb_v3(svn: u64, pce_svn: u64) -> Value {
+        let components: Vec<Value> = (0..16).map(|_| serde_json::json!({ "svn": svn })).collect();
+        serde_json::json!({ "sgxtcbcomponents": components, "pcesvn": pce_svn })
+    }
+
+    #[test]
+    fn test_tcb_levels() {
+        // Intel's TCB info v3, levels ordered from the highest
+        let collateral = |platform: Value| {
+            serde_json::json!({
+                "tcbInfo": {
+                    "version": 3,
+                    "tcbEvaluationDataNumber": 15,
+                    "tcbLevels": [
+                        { "tcb": tcb_v3(5, 13), "tcbStatus": "UpToDate" },
+                        { "tcb": tcb_v3(4, 13), "tcbStatus": "SWHardeningNeeded" },
+                        { "tcb": tcb_v3(2, 10), "tcbStatus": "OutOfDate" }
+                    ]
+                },
+                "pckTcb": platform
+            })
+            .to_string()
+        };
+
+        let claims = parse_collateral(collateral(tcb_v3(5, 13)).as_bytes());
+        assert_eq!(claims["tcbStatus"], "UpToDate");
+        assert_eq!(claims["tcbEvaluationDataNumber"], 15);
+        let claims = parse_collateral(collateral(tcb_v3(4, 14)).as_bytes());
+        assert_eq!(claims["tcbStatus"], "SWHardeningNeeded");
+        let claims = parse_collateral(collateral(tcb_v3(5, 10)).as_bytes());
+        assert_eq!(claims["tcbStatus"], "OutOfDate");
+        let claims = parse_collateral(collateral(tcb_v3(1, 13)).as_bytes());
+        assert_eq!(claims["tcbStatus"], Value::Null);
+        let claims = parse_collateral(collateral(Value::Null).as_bytes());
+        assert_eq!(claims["tcbStatus"], Value::Null);
+
+        // TCB info v2 names every component
+        let mut v2 = serde_json::json!({ "pcesvn": 13 });
+        for i in 1..=16 {
+            v2[format!("sgxtcbcomp{:02}svn", i)] = serde_json::json!(4);
+        }
+        let claims = parse_collateral(collateral(v2).as_bytes());
+        assert_eq!(claims["tcbStatus"], "SWHardeningNeeded");
+    }
+}
@@ -0,0 +1,197 @@
+//! Claims of a TDX quote (version 4).
+//!
+//! The quote starts with a 48 bytes header followed by the TD report body:
+//!
+//! ```text
+//! offset  size  field
+//!      0    16  TEE_TCB_SVN
+//!     16    48  MRSEAM
+//!     64    48  MRSIGNERSEAM
+//!    112     8  SEAMATTRIBUTES
+//!    120     8  TDATTRIBUTES
+//!    128     8  XFAM
+//!    136    48  MRTD
+//!    184    48  MRCONFIGID
+//!    232    48  MROWNER
+//!    280    48  MROWNERCONFIG
+//!    328   192  RTMR0-3
+//!    520    64  REPORTDATA
+//! ```
+//!
+//! The rats-tls tdx_ecdsa verifier hands the raw quote it verified in
+//! `rtls_evidence.quote` and points the fields of `rtls_tdx_evidence` into
+//! its TD report body. The fields are checked against the parsed quote, so
+//! the claims can't come from a quote other than the verified one.
+use super::ffi::*;
+use super::verifier::{quote, raw_slice, EvidenceVerifier, PolicySelector};
+use crate::resources::opa;
+use serde_json::Value;
+
+pub(super) const QUOTE_HEADER_LEN: usize = 48;
+pub(super) const TD_REPORT_LEN: usize = 584;
+pub(super) const QUOTE_VERSION: u16 = 4;
+pub(super) const TEE_TYPE_TDX: u32 = 0x81;
+
+/// TDATTRIBUTES.DEBUG, the TD is debuggable
+const TD_ATTRIBUTES_DEBUG: u64 = 1 << 0;
+
+fn u64_le(bytes: &[u8]) -> u64 {
+    let mut value = [0u8; 8];
+    value.copy_from_slice(&bytes[..8]);
+    u64::from_le_bytes(value)
+}
+
+pub fn parse_quote(quote: &[u8]) -> Result<Value, String> {
+    if quote.len() < QUOTE_HEADER_LEN + TD_REPORT_LEN {
+        return Err(format!("TDX quote is too short: {} bytes", quote.len()));
+    }
+
+    let version = u16::from_le_bytes([quote[0], quote[1]]);
+    let tee_type = u32::from_le_bytes([quote[4], quote[5], quote[6], quote[7]]);
+    if version != QUOTE_VERSION || tee_type != TEE_TYPE_TDX {
+        return Err(format!(
+            "unsupported TDX quote: version {}, tee type {:#x}",
+            version, tee_type
+        ));
+    }
+
+    let body = &quote[QUOTE_HEADER_LEN..QUOTE_HEADER_LEN + TD_REPORT_LEN];
+    let field = |offset: usize, len: usize| base64::encode(&body[offset..offset + len]);
+    let td_attributes = u64_le(&body[120..128]);
+    let xfam = u64_le(&body[128..136]);
+
+    Ok(serde_json::json!({
+        "teeTcbSvn": body[0..16].to_vec(),
+        "mrSeam": field(16, 48),
+        "mrSignerSeam": field(64, 48),
+        "seamAttributes": format!("{:016x}", u64_le(&body[112..120])),
+        "tdAttributes": format!("{:016x}", td_attributes),
+        "debug": td_attributes & TD_ATTRIBUTES_DEBUG != 0,
+        "xfam": format!("{:016x}", xfam),
+        "mrTd": field(136, 48),
+        "mrConfigId": field(184, 48),
+        "mrOwner": field(232, 48),
+        "mrOwnerConfig": field(280, 48),
+        "rtmr0": field(328, 48),
+        "rtmr1": field(376, 48),
+        "rtmr2": field(424, 48),
+        "rtmr3": field(472, 48),
+        "reportData": field(520, 64),
+    }))
+}
+
+/// `rtls_tdx_evidence` fields with their size and the claim of the quote
+/// they must match, RTMR0-3 are contiguous
+const EVIDENCE_FIELDS: [(&str, usize, &str); 5] = [
+    ("mrseam", 48, "mrSeam"),
+    ("mrseamsigner", 48, "mrSignerSeam"),
+    ("tcb_svns", 16, "teeTcbSvn"),
+    ("mrtd", 48, "mrTd"),
+    ("rtmr", 192, "rtmr0"),
+];
+
+/// Claims of the quote of `evidence`, cross-checked with the fields of the
+/// TDX evidence. Null fields are skipped, older rats-tls doesn't set them.
+///
+/// # Safety
+///
+/// The non-null pointers of `evidence` must be valid for their sizes.
+unsafe fn evidence_claims(evidence: &rtls_evidence) -> Result<Value, String> {
+    let mut claims = parse_quote(quote(evidence))?;
+    let tdx = &evidence.__bindgen_anon_1.tdx;
+
+    for (ptr, (name, len, claim)) in [
+        tdx.mrseam,
+        tdx.mrseamsigner,
+        tdx.tcb_svns,
+        tdx.mrtd,
+        tdx.rtmr,
+    ]
+    .iter()
+    .zip(EVIDENCE_FIELDS.iter())
+    {
+        let field = raw_slice(*ptr, *len);
+        if field.is_empty() {
+            continue;
+        }
+        let expected = match *claim {
+            "teeTcbSvn" => serde_json::json!(field.to_vec()),
+            "rtmr0" => {
+                let rtmrs: Vec<Value> = field
+                    .chunks(48)
+                    .map(|rtmr| Value::String(base64::encode(rtmr)))
+                    .collect();
+                let quoted: Vec<Value> = (0..4)
+                    .map(|i| claims[format!("rtmr{}", i).as_str()].clone())
+                    .collect();
+                if rtmrs != quoted {
+                    return Err(format!("TDX evidence {} doesn't match the quote", name));
+                }
+                continue;
+            }
+            _ => Value::String(base64::encode(field)),
+        };
+        if claims[*claim] != expected {
+            return Err(format!("TDX evidence {} doesn't match the quote", name));
+        }
+    }
+
+    // The CPU SVN isn't part of the TD report body
+    let cpu_svn = raw_slice(tdx.cpu_svn, 16);
+    if !cpu_svn.is_empty() {
+        claims["cpuSvn"] = serde_json::json!(cpu_svn.to_vec());
+    }
+    Ok(claims)
+}
+
+pub struct TdxVerifier;
+
+impl EvidenceVerifier for TdxVerifier {
+    fn tee(&self) -> &'static str {
+        "tdx"
+    }
+
+    fn evidence_type(&self) -> enclave_evidence_type_t {
+        enclave_evidence_type_t_TDX
+    }
+
+    unsafe fn claims(&self, evidence: &rtls_evidence) -> Result<Value, String> {
+        evidence_claims(evidence)
+    }
+
+    fn policy(&self, _claims: &Value) -> PolicySelector {
+        PolicySelector::new(opa::OPA_POLICY_TDX, opa::OPA_DATA_TDX)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::fixtures::tdx_quote as quote;
+    use super::*;
+
+    #[test]
+    fn test_parse_quote() {
+        let claims = parse_quote(&quote(0x1000_0000)).unwrap();
+        assert_eq!(claims["teeTcbSvn"], serde_json::json!(vec![1u8; 16]));
+        assert_eq!(claims["mrTd"], base64::encode([2u8; 48]));
+        assert_eq!(claims["mrConfigId"], base64::encode([3u8; 48]));
+        assert_eq!(claims["mrOwner"], base64::encode([4u8; 48]));
+        assert_eq!(claims["rtmr0"], base64::encode([5u8; 48]));
+        assert_eq!(claims["rtmr3"], base64::encode([6u8; 48]));
+        assert_eq!(claims["tdAttributes"], "0000000010000000");
+        assert_eq!(claims["xfam"], "00000000000000e7");
+        assert_eq!(claims["debug"], false);
+
+        let claims = parse_quote(&quote(TD_ATTRIBUTES_DEBUG)).unwrap();
+        assert_eq!(claims["debug"], true);
+    }
+
+    #[test]
+    fn test_invalid_quote() {
+        assert!(parse_quote(&[0u8; 100]).is_err());
+
+        let mut sgx_quote = quote(0);
+        sgx_quote[4..8].copy_from_slice(&0u32.to_le_bytes());
+        assert!(parse_quote(&sgx_quote).is_err());
+    }
+}
@@ -0,0 +1,209 @@
+//! Evidence verifiers of the TEEs verdictd attests.
+//!
+//! A verifier turns the raw evidence rats-tls hands to the verification
+//! callback into the claims the attestation policy is evaluated against, and
+//! selects that policy. Supporting another TEE means implementing
+//! `EvidenceVerifier` and adding it to `VERIFIERS`.
+use super::ffi::*;
+use super::{csv, sgx, tdx};
+use serde_json::Value;
+
+/// The OPA policy and reference data an evidence is checked against, or
+/// the OPA bundle named by `policy` if `bundle` is set
+#[derive(Debug, Clone, PartialEq)]
+pub struct PolicySelector {
+    pub policy: String,
+    pub reference: String,
+    pub bundle: bool,
+}
+
+impl PolicySelector {
+    pub fn new(policy: &str, reference: &str) -> Self {
+        PolicySelector {
+            policy: policy.to_string(),
+            reference: reference.to_string(),
+            bundle: false,
+        }
+    }
+
+    pub fn bundle(name: &str) -> Self {
+        PolicySelector {
+            policy: name.to_string(),
+            reference: String::new(),
+            bundle: true,
+        }
+    }
+}
+
+pub trait EvidenceVerifier: Sync {
+    /// Name of the TEE, e.g. `sgx`
+    fn tee(&self) -> &'static str;
+
+    fn evidence_type(&self) -> enclave_evidence_type_t;
+
+    /// Normalized claims of `evidence`
+    ///
+    /// # Safety
+    ///
+    /// `evidence` must be a rats-tls evidence of `evidence_type()` whose
+    /// pointers are valid for their sizes.
+    unsafe fn claims(&self, evidence: &rtls_evidence) -> Result<Value, String>;
+
+    /// Default policy of the evidence, used when no policy binding matches
+    fn policy(&self, claims: &Value) -> PolicySelector;
+
+    /// Signer of the workload policy bindings match against
+    fn signer(&self, _claims: &Value) -> Option<String> {
+        None
+    }
+}
+
+static VERIFIERS: [&dyn EvidenceVerifier; 3] =
+    [&sgx::SgxVerifier, &tdx::TdxVerifier, &csv::CsvVerifier];
+
+pub fn lookup(
+    evidence_type: enclave_evidence_type_t,
+) -> Result<&'static dyn EvidenceVerifier, String> {
+    VERIFIERS
+        .iter()
+        .find(|verifier| verifier.evidence_type() == evidence_type)
+        .copied()
+        .ok_or(format!("evidence type {} isn't supported", evidence_type))
+}
+
+/// The `len` bytes at `ptr` provided by rats-tls, empty if `ptr` is null
+pub unsafe fn raw_slice<'a>(ptr: *const u8, len: usize) -> &'a [u8] {
+    if ptr.is_null() || len == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(ptr, len)
+    }
+}
+
+/// The quote of `evidence`
+pub unsafe fn quote(evidence: &rtls_evidence) -> &[u8] {
+    raw_slice(
+        evidence.quote as *const u8,
+        evidence.quote_size.max(0) as usize,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{fixtures, RatsTls};
+    use super::*;
+    use crate::resources::{audit, opa};
+
+    #[test]
+    fn test_lookup() {
+        for (evidence_type, tee) in [
+            (enclave_evidence_type_t_SGX_ECDSA, "sgx"),
+            (enclave_evidence_type_t_TDX, "tdx"),
+            (enclave_evidence_type_t_CSV, "csv"),
+        ] {
+            let verifier = lookup(evidence_type).unwrap();
+            assert_eq!(verifier.tee(), tee);
+            assert_eq!(verifier.evidence_type(), evidence_type);
+        }
+        assert!(lookup(0).is_err());
+    }
+
+    #[test]
+    fn test_sgx_verifier() {
+        let fixture = fixtures::sgx(0x7, r#"{"tcbStatus": "UpToDate"}"#);
+        let verifier = lookup(fixture.evidence.type_).unwrap();
+        let claims = unsafe { verifier.claims(&fixture.evidence) }.unwrap();
+
+        assert_eq!(claims["mrEnclave"], base64::encode([1u8; 32]));
+        assert_eq!(claims["mrSigner"], base64::encode([2u8; 32]));
+        assert_eq!(claims["productId"], 1);
+        assert_eq!(claims["svn"], 2);
+        assert_eq!(claims["debug"], true);
+        assert_eq!(claims["tcbStatus"], "UpToDate");
+        assert_eq!(
+            verifier.policy(&claims),
+            PolicySelector::new(opa::OPA_POLICY_SGX, opa::OPA_DATA_SGX)
+        );
+
+        let mut fixture = fixtures::sgx(0x5, "");
+        fixture.evidence.__bindgen_anon_1.sgx.mr_enclave = std::ptr::null_mut();
+        assert!(unsafe { verifier.claims(&fixture.evidence) }.is_err());
+    }
+
+    #[test]
+    fn test_tdx_verifier() {
+        let fixture = fixtures::tdx(1);
+        let verifier = lookup(fixture.evidence.type_).unwrap();
+        let claims = unsafe { verifier.claims(&fixture.evidence) }.unwrap();
+
+        assert_eq!(claims["mrTd"], base64::encode([2u8; 48]));
+        assert_eq!(claims["debug"], true);
+        assert_eq!(
+            verifier.policy(&claims),
+            PolicySelector::new(opa::OPA_POLICY_TDX, opa::OPA_DATA_TDX)
+        );
+
+        assert_eq!(claims["cpuSvn"], serde_json::json!(vec![7u8; 16]));
+
+        // Fields not taken from the verified quote are rejected
+        let mut fixture = fixtures::tdx(0);
+        let other = [9u8; 48];
+        fixture.evidence.__bindgen_anon_1.tdx.mrtd = other.as_ptr() as *mut _;
+        assert!(unsafe { verifier.claims(&fixture.evidence) }.is_err());
+
+        let mut fixture = fixtures::tdx(0);
+        let mut rtmr =
+            unsafe { raw_slice(fixture.evidence.__bindgen_anon_1.tdx.rtmr, 192) }.to_vec();
+        rtmr[100] ^= 1;
+        fixture.evidence.__bindgen_anon_1.tdx.rtmr = rtmr.as_mut_ptr();
+        assert!(unsafe { verifier.claims(&fixture.evidence) }.is_err());
+
+        // Without the fields, the claims come from the quote only
+        let mut fixture = fixtures::tdx(0);
+        fixture.evidence.__bindgen_anon_1.tdx = unsafe { std::mem::zeroed() };
+        let claims = unsafe { verifier.claims(&fixture.evidence) }.unwrap();
+        assert_eq!(claims["mrTd"], base64::encode([2u8; 48]));
+        assert!(claims.get("cpuSvn").is_none());
+
+        let mut fixture = fixtures::tdx(0);
+        fixture.evidence.quote = std::ptr::null_mut();
+        assert!(unsafe { verifier.claims(&fixture.evidence) }.is_err());
+    }
+
+    #[test]
+    fn test_csv_verifier() {
+        let fixture = fixtures::csv(0x1);
+        let verifier = lookup(fixture.evidence.type_).unwrap();
+        let claims = unsafe { verifier.claims(&fixture.evidence) }.unwrap();
+
+        assert_eq!(claims["measure"], base64::encode([3u8; 32]));
+        assert_eq!(claims["debug"], false);
+        assert_eq!(claims["migratable"], true);
+        assert_eq!(
+            verifier.policy(&claims),
+            PolicySelector::new(opa::OPA_POLICY_CSV, opa::OPA_DATA_CSV)
+        );
+    }
+
+    #[test]
+    fn test_callback() {
+        opa::default().unwrap();
+
+        let mut fixture = fixtures::csv(0x1);
+        let evidence = &mut fixture.evidence as *mut rtls_evidence;
+        assert_eq!(RatsTls::callback(evidence as *mut _), 1);
+        let verified = RatsTls::take_verified_evidence().unwrap();
+        assert_eq!(verified.tee, "csv");
+        assert_eq!(verified.policy, opa::OPA_POLICY_CSV);
+
+        fixture.evidence.type_ = 0;
+        assert_eq!(RatsTls::callback(evidence as *mut _), 0);
+        assert_eq!(RatsTls::callback(std::ptr::null_mut()), 0);
+        assert!(RatsTls::take_verified_evidence().is_none());
+
+        let (entries, _) = audit::query(0, 0, Some(audit::DECISION_DENY)).unwrap();
+        assert!(entries
+            .iter()
+            .any(|e| e.record.error.as_deref() == Some("evidence type 0 isn't supported")));
+    }
+}
@@ -0,0 +1,316 @@
+//! Append-only attestation audit log, `/opt/verdictd/audit/attestation.jsonl`.
+//!
+//! Every line is the JSON entry of one attestation: a rejected evidence is
+//! logged when it is rejected, an attested session when it ends, with the
+//! keys, resources and secrets released in it. The entries are hash
+//! chained, `hash` is the hex encoded SHA-256 of `prevHash` followed by the
+//! entry serialized with an empty `hash`, so editing or dropping an entry
+//! breaks the chain of all the following ones.
+extern crate crypto;
+
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use lazy_static::lazy_static;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+lazy_static! {
+    // Global file lock
+    pub static ref FILE_LOCK: RwLock<u32> = RwLock::new(0);
+}
+
+pub const AUDIT_PATH: &str = "/opt/verdictd/audit/";
+pub const AUDIT_LOG: &str = "/opt/verdictd/audit/attestation.jsonl";
+
+pub const DECISION_ALLOW: &str = "allow";
+pub const DECISION_DENY: &str = "deny";
+/// The peer wasn't attested, e.g. rats-tls without mutual attestation
+pub const DECISION_NONE: &str = "none";
+
+/// Hash of the entry before the first one
+const GENESIS: &str = "";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Release {
+    /// `key`, `resource` or `secret`
+    pub kind: String,
+    pub name: String,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Record {
+    /// Unix time of the decision
+    pub timestamp: u64,
+    pub peer: String,
+    pub tee: String,
+    pub claims: Value,
+    pub policy: String,
+    pub policy_hash: String,
+    pub reference: String,
+    pub reference_hash: String,
+    /// Whether `policy` names an OPA bundle
+    #[serde(default, skip_serializing_if = "is_false")]
+    pub bundle: bool,
+    pub decision: String,
+    /// Why the policy denied the evidence
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub deny_reasons: Vec<String>,
+    /// Claims the policy attached to its decision
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub policy_claims: Map<String, Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default)]
+    pub released: Vec<Release>,
+}
+
+fn is_false(value: &bool) -> bool {
+    !*value
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Entry {
+    pub seq: u64,
+    #[serde(flatten)]
+    pub record: Record,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+impl Entry {
+    fn digest(&self) -> Result<String, String> {
+        let mut entry = self.clone();
+        entry.hash = String::new();
+        let content = serde_json::to_string(&entry).map_err(|e| e.to_string())?;
+        Ok(digest((self.prev_hash.clone() + &content).as_bytes()))
+    }
+}
+
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+/// Hex encoded SHA-256 of `data`
+pub fn digest(data: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.input(data);
+    hasher.result_str()
+}
+
+/// The last line of `file`, None if it's empty
+fn last_line(file: &mut File) -> Result<Option<Vec<u8>>, String> {
+    const CHUNK: u64 = 4096;
+
+    let mut pos = file.seek(SeekFrom::End(0)).map_err(|e| e.to_string())?;
+    let mut tail: Vec<u8> = Vec::new();
+    while pos > 0 {
+        let start = pos.saturating_sub(CHUNK);
+        let mut chunk = vec![0u8; (pos - start) as usize];
+        file.seek(SeekFrom::Start(start))
+            .and_then(|_| file.read_exact(&mut chunk))
+            .map_err(|e| e.to_string())?;
+        chunk.extend_from_slice(&tail);
+        tail = chunk;
+        pos = start;
+
+        let line = tail.strip_suffix(b"\n").unwrap_or(&tail);
+        if let Some(i) = line.iter().rposition(|b| *b == b'\n') {
+            return Ok(Some(line[i + 1..].to_vec()));
+        }
+    }
+
+    let line = tail.strip_suffix(b"\n").unwrap_or(&tail);
+    Ok(if line.is_empty() {
+        None
+    } else {
+        Some(line.to_vec())
+    })
+}
+
+fn append_to(path: &str, record: Record) -> Result<Entry, String> {
+    let lock = FILE_LOCK.write();
+    assert_eq!(*lock, 0);
+
+    if let Some(dir) = Path::new(path).parent() {
+        fs::create_dir_all(dir).map_err(|e| format!("create {:?} failed: {}", dir, e))?;
+    }
+    let mut file = OpenOptions::new()
+        .read(true)
+        .append(true)
+        .create(true)
+        .open(path)
+        .map_err(|e| format!("open {} failed: {}", path, e))?;
+
+    let (seq, prev_hash) = match last_line(&mut file)? {
+        Some(line) => {
+            let last: Entry = serde_json::from_slice(&line)
+                .map_err(|e| format!("parse the last entry of {} failed: {}", path, e))?;
+            (last.seq + 1, last.hash)
+        }
+        None => (0, GENESIS.to_string()),
+    };
+
+    let mut entry = Entry {
+        seq,
+        record,
+        prev_hash,
+        hash: String::new(),
+    };
+    entry.hash = entry.digest()?;
+
+    let mut line = serde_json::to_vec(&entry).map_err(|e| e.to_string())?;
+    line.push(b'\n');
+    file.write_all(&line)
+        .and_then(|_| file.sync_data())
+        .map_err(|e| format!("append to {} failed: {}", path, e))?;
+
+    Ok(entry)
+}
+
+/// Read the entries of `path` and check their chain, `keep` filters the
+/// returned entries
+fn read_from<F>(path: &str, keep: F) -> Result<(Vec<Entry>, Result<(), String>), String>
+where
+    F: Fn(&Entry) -> bool,
+{
+    let lock = FILE_LOCK.read();
+    assert_eq!(*lock, 0);
+
+    if !Path::new(path).exists() {
+        return Ok((Vec::new(), Ok(())));
+    }
+    let file = File::open(path).map_err(|e| format!("open {} failed: {}", path, e))?;
+
+    let mut entries = Vec::new();
+    let mut chain = Ok(());
+    let mut prev_hash = GENESIS.to_string();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| format!("read {} failed: {}", path, e))?;
+        let entry: Entry = serde_json::from_str(&line)
+            .map_err(|e| format!("parse entry {} of {} failed: {}", i, path, e))?;
+
+        if chain.is_ok()
+            && (entry.seq != i as u64
+                || entry.prev_hash != prev_hash
+                || entry.digest()? != entry.hash)
+        {
+            chain = Err(format!("audit log chain is broken at entry {}", i));
+        }
+        prev_hash = entry.hash.clone();
+
+        if keep(&entry) {
+            entries.push(entry);
+        }
+    }
+
+    Ok((entries, chain))
+}
+
+/// Append `record` to the audit log
+pub fn append(record: Record) -> Result<Entry, String> {
+    append_to(AUDIT_LOG, record)
+}
+
+/// Entries decided within `[from, to]` and with `decision` if set, 0 leaves
+/// a bound open. The second result tells whether the chain of the whole log
+/// is intact.
+pub fn query(
+    from: u64,
+    to: u64,
+    decision: Option<&str>,
+) -> Result<(Vec<Entry>, Result<(), String>), String> {
+    read_from(AUDIT_LOG, |entry| {
+        entry.record.timestamp >= from
+            && (to == 0 || entry.record.timestamp <= to)
+            && (decision.is_none() || decision == Some(entry.record.decision.as_str()))
+    })
+}
+
+pub fn default() -> Result<(), String> {
+    if !Path::new(AUDIT_PATH).exists() {
+        fs::create_dir_all(AUDIT_PATH).map_err(|_| format!("create {:?} failed", AUDIT_PATH))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn record(timestamp: u64, decision: &str) -> Record {
+        Record {
+            timestamp,
+            peer: "127.0.0.1:40000".to_string(),
+            tee: "sgx".to_string(),
+            claims: serde_json::json!({ "mrEnclave": "xxx" }),
+            policy: "sgxPolicy.rego".to_string(),
+            decision: decision.to_string(),
+            released: vec![Release {
+                kind: "key".to_string(),
+                name: "kid".to_string(),
+            }],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_append_query() {
+        let dir = TempDir::new("verdictd-audit").unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let path = path.to_str().unwrap();
+
+        let first = append_to(path, record(100, DECISION_ALLOW)).unwrap();
+        let second = append_to(path, record(200, DECISION_DENY)).unwrap();
+        append_to(path, record(300, DECISION_ALLOW)).unwrap();
+        assert_eq!(first.seq, 0);
+        assert_eq!(first.prev_hash, GENESIS);
+        assert_eq!(second.seq, 1);
+        assert_eq!(second.prev_hash, first.hash);
+
+        let (entries, chain) = read_from(path, |_| true).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(entries[1], second);
+        assert!(chain.is_ok());
+
+        let (entries, _) = read_from(path, |e| {
+            e.record.timestamp >= 150 && e.record.decision == DECISION_ALLOW
+        })
+        .unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].record.timestamp, 300);
+    }
+
+    #[test]
+    fn test_tamper() {
+        let dir = TempDir::new("verdictd-audit").unwrap();
+        let path = dir.path().join("audit.jsonl");
+        let path = path.to_str().unwrap();
+
+        for timestamp in [100, 200, 300] {
+            append_to(path, record(timestamp, DECISION_DENY)).unwrap();
+        }
+
+        let content = fs::read_to_string(path).unwrap();
+        fs::write(path, content.replacen("\"deny\"", "\"allow\"", 1)).unwrap();
+        let (entries, chain) = read_from(path, |_| true).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert_eq!(chain.unwrap_err(), "audit log chain is broken at entry 0");
+
+        let lines: Vec<&str> = content.lines().collect();
+        fs::write(path, format!("{}\n{}\n", lines[0], lines[2])).unwrap();
+        let (_, chain) = read_from(path, |_| true).unwrap();
+        assert_eq!(chain.unwrap_err(), "audit log chain is broken at entry 1");
+    }
+}
@@ -0,0 +1,209 @@
+//! OPA bundles uploaded by `SetOpaBundle`, stored as `<name>.tar.gz` under
+//! `/opt/verdictd/opa/bundles/`.
+//!
+//! A bundle is checked as a whole before it's stored, and a bundle a policy
+//! binding selects can't be deleted.
+use crate::policy_engine::bundle::Bundle;
+use crate::policy_engine::{self, cache, Diagnostic};
+use crate::resources::policy_binding;
+use lazy_static::lazy_static;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+lazy_static! {
+    // Global file lock
+    pub static ref FILE_LOCK: RwLock<u32> = RwLock::new(0);
+}
+
+pub const BUNDLE_PATH: &str = "/opt/verdictd/opa/bundles/";
+const BUNDLE_SUFFIX: &str = ".tar.gz";
+
+/// Summary of a stored bundle
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Info {
+    pub name: String,
+    pub revision: String,
+    pub entrypoint: String,
+    pub modules: Vec<String>,
+    pub hash: String,
+}
+
+fn check_name(name: &str) -> Result<(), String> {
+    if name.is_empty()
+        || name.starts_with('.')
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_')
+    {
+        return Err(format!("invalid bundle name {:?}", name));
+    }
+    Ok(())
+}
+
+pub fn path(name: &str) -> String {
+    String::from(BUNDLE_PATH) + name + BUNDLE_SUFFIX
+}
+
+/// Check every module of the bundle `archive` with the policy engine,
+/// nothing is returned if it's valid
+pub fn check(archive: &[u8]) -> Result<Vec<Diagnostic>, String> {
+    let bundle = Bundle::from_archive(archive)?;
+    Ok(policy_engine::engine().check_modules(&bundle.modules, &bundle.entrypoint))
+}
+
+/// Save the bundle `archive` as `name` after checking it
+pub fn set(name: &str, archive: &[u8]) -> Result<Vec<Diagnostic>, String> {
+    check_name(name)?;
+    let diagnostics = check(archive)?;
+    if !diagnostics.is_empty() {
+        return Ok(diagnostics);
+    }
+
+    let lock = FILE_LOCK.write();
+    assert_eq!(*lock, 0);
+
+    cache::invalidate_bundle(name);
+    let path = path(name);
+    let tmp = path.clone() + ".tmp";
+    fs::write(&tmp, archive)
+        .and_then(|_| fs::rename(&tmp, &path))
+        .map_err(|e| {
+            let _ = fs::remove_file(&tmp);
+            format!("Store bundle {} failed: {}", name, e)
+        })?;
+    Ok(diagnostics)
+}
+
+// Export existing bundle from verdictd
+pub fn export(name: &str) -> Result<Vec<u8>, String> {
+    check_name(name)?;
+
+    let lock = FILE_LOCK.read();
+    assert_eq!(*lock, 0);
+    fs::read(path(name)).map_err(|_| format!("bundle {} not found", name))
+}
+
+pub fn exists(name: &str) -> bool {
+    if check_name(name).is_err() {
+        return false;
+    }
+
+    let lock = FILE_LOCK.read();
+    assert_eq!(*lock, 0);
+    Path::new(&path(name)).exists()
+}
+
+pub fn delete(name: &str) -> Result<(), String> {
+    check_name(name)?;
+    if let Some(binding) = policy_binding::list()?
+        .iter()
+        .find(|b| b.bundle.as_deref() == Some(name))
+    {
+        return Err(format!(
+            "bundle {} is used by policy binding {}",
+            name, binding.name
+        ));
+    }
+
+    let lock = FILE_LOCK.write();
+    assert_eq!(*lock, 0);
+
+    cache::invalidate_bundle(name);
+    fs::remove_file(path(name)).map_err(|_| format!("bundle {} not found", name))
+}
+
+pub fn list() -> Result<Vec<Info>, String> {
+    let mut names: Vec<String> = {
+        let lock = FILE_LOCK.read();
+        assert_eq!(*lock, 0);
+
+        fs::read_dir(BUNDLE_PATH)
+            .map_err(|e| format!("read {} failed: {}", BUNDLE_PATH, e))?
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let file = entry.file_name().to_string_lossy().to_string();
+                file.strip_suffix(BUNDLE_SUFFIX).map(String::from)
+            })
+            .collect()
+    };
+    names.sort();
+
+    let mut infos = Vec::new();
+    for name in names {
+        let bundle = match cache::load_bundle(&name) {
+            Ok(bundle) => bundle,
+            Err(e) => {
+                warn!("bundle {}: {}", name, e);
+                continue;
+            }
+        };
+        infos.push(Info {
+            name,
+            revision: bundle.manifest.revision.clone(),
+            entrypoint: bundle.entrypoint.clone(),
+            modules: bundle.modules.iter().map(|m| m.file.clone()).collect(),
+            hash: bundle.hash.clone(),
+        });
+    }
+    Ok(infos)
+}
+
+pub fn default() -> Result<(), String> {
+    if !Path::new(&BUNDLE_PATH.to_string()).exists() {
+        fs::create_dir_all(BUNDLE_PATH).map_err(|_| format!("create {:?} failed", BUNDLE_PATH))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::policy_engine::bundle::tests::{archive, tenant_bundle};
+
+    #[test]
+    fn test_set_export_delete() {
+        default().unwrap();
+        let name = "verdictd-test-bundle";
+        let _ = delete(name);
+
+        let content = tenant_bundle();
+        assert_eq!(set(name, &content), Ok(vec![]));
+        assert!(exists(name));
+        assert_eq!(export(name).unwrap(), content);
+
+        let info = list()
+            .unwrap()
+            .into_iter()
+            .find(|info| info.name == name)
+            .unwrap();
+        assert_eq!(info.revision, "r1");
+        assert_eq!(info.entrypoint, "tenants.a");
+        assert_eq!(info.modules.len(), 2);
+
+        assert!(delete(name).is_ok());
+        assert!(delete(name).is_err());
+        assert!(!exists(name));
+    }
+
+    #[test]
+    fn test_set_invalid() {
+        default().unwrap();
+        let name = "verdictd-test-invalid-bundle";
+
+        assert!(set("../escape", &tenant_bundle()).is_err());
+        assert!(set(name, b"not a bundle").is_err());
+
+        // A module failing the check isn't stored
+        let broken = archive(&[("policy.rego", "package policy\nallow {")]);
+        assert!(!set(name, &broken).unwrap().is_empty());
+        assert!(!exists(name));
+
+        // The entrypoint package must be defined by one of the modules
+        let other = archive(&[("lib.rego", "package lib\nallow = true")]);
+        assert!(!set(name, &other).unwrap().is_empty());
+    }
+}
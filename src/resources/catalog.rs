@@ -0,0 +1,300 @@
+//! Resource catalog stored under `/opt/verdictd/resources/`.
+//!
+//! Every resource is addressed by `<repository>/<type>/<tag>` and stored in
+//! the file of the same relative path. The files verdictd manages through
+//! dedicated services (image policy, sigstore config, GPG keyring, ...) are
+//! exposed under the `default` repository as read-only built-in resources.
+//! Name parts can't start with a dot, dot-prefixed files are verdictd's own
+//! temporary files.
+use crate::resources::{gpg, image};
+use lazy_static::lazy_static;
+use parking_lot::RwLock;
+use std::fs;
+use std::path::Path;
+
+lazy_static! {
+    // Global file lock
+    pub static ref FILE_LOCK: RwLock<u32> = RwLock::new(0);
+}
+
+pub const RESOURCE_PATH: &str = "/opt/verdictd/resources/";
+
+pub const POLICY: &str = "default/image/policy";
+pub const SIGSTORE: &str = "default/image/sigstore";
+pub const COSIGN: &str = "default/image/cosign-key";
+pub const CREDENTIAL: &str = "default/image/credential";
+pub const GPG_KEYRING: &str = "default/gpg/keyring";
+
+const BUILTIN: [(&str, &str); 5] = [
+    (POLICY, image::POLICY),
+    (SIGSTORE, image::SIGSTORE),
+    (COSIGN, image::COSIGN),
+    (CREDENTIAL, image::CREDENTIAL),
+    (GPG_KEYRING, gpg::GPG_KEYRING),
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResourceId {
+    pub repository: String,
+    pub type_: String,
+    pub tag: String,
+}
+
+impl ResourceId {
+    pub fn parse(name: &str) -> Result<ResourceId, String> {
+        let parts: Vec<&str> = name.split('/').collect();
+        if parts.len() != 3 {
+            return Err(format!(
+                "resource name {} isn't in <repository>/<type>/<tag> format",
+                name
+            ));
+        }
+
+        for part in &parts {
+            if part.is_empty()
+                || part.starts_with('.')
+                || !part
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_')
+            {
+                return Err(format!("invalid resource name {}", name));
+            }
+        }
+
+        Ok(ResourceId {
+            repository: parts[0].to_string(),
+            type_: parts[1].to_string(),
+            tag: parts[2].to_string(),
+        })
+    }
+
+    pub fn name(&self) -> String {
+        format!("{}/{}/{}", self.repository, self.type_, self.tag)
+    }
+
+    fn path(&self) -> String {
+        String::from(RESOURCE_PATH) + &self.name()
+    }
+
+    fn builtin(&self) -> Option<&'static str> {
+        let name = self.name();
+        BUILTIN
+            .iter()
+            .find(|(alias, _)| *alias == name)
+            .map(|(_, path)| *path)
+    }
+}
+
+pub fn export_raw(name: &str) -> Result<Vec<u8>, String> {
+    let id = ResourceId::parse(name)?;
+
+    match id.builtin() {
+        Some(gpg::GPG_KEYRING) => {
+            let lock = gpg::FILE_LOCK.read();
+            assert_eq!(*lock, 0);
+            fs::read(gpg::GPG_KEYRING).map_err(|e| e.to_string())
+        }
+        Some(path) => {
+            let lock = image::FILE_LOCK.read();
+            assert_eq!(*lock, 0);
+            fs::read(path).map_err(|e| e.to_string())
+        }
+        None => {
+            let lock = FILE_LOCK.read();
+            assert_eq!(*lock, 0);
+            fs::read(id.path()).map_err(|e| e.to_string())
+        }
+    }
+}
+
+pub fn export_base64(name: &str) -> Result<String, String> {
+    export_raw(name).map(base64::encode)
+}
+
+pub fn size_base64(name: &str) -> Result<usize, String> {
+    export_base64(name)
+        .map_err(|e| format!("Fetch {} size failed:{:?}", name, e))
+        .map(|content| content.len())
+}
+
+/// `name` is a valid resource name this verdictd doesn't hold
+pub fn missing(name: &str) -> bool {
+    let id = match ResourceId::parse(name) {
+        Ok(id) => id,
+        Err(_) => return false,
+    };
+
+    let lock = FILE_LOCK.read();
+    assert_eq!(*lock, 0);
+    match id.builtin() {
+        Some(path) => !Path::new(path).exists(),
+        None => !Path::new(&id.path()).exists(),
+    }
+}
+
+pub fn set(name: &str, content: &[u8]) -> Result<(), String> {
+    let id = ResourceId::parse(name)?;
+    if id.builtin().is_some() {
+        return Err(format!("{} is a built-in resource and can't be set", name));
+    }
+
+    let lock = FILE_LOCK.write();
+    assert_eq!(*lock, 0);
+
+    let path = id.path();
+    let dir = Path::new(&path).parent().unwrap();
+    fs::create_dir_all(dir).map_err(|e| format!("create {:?} failed: {}", dir, e))?;
+
+    // Write to a temporary file first so a reader never sees a partial
+    // resource, its name is outside of the tag namespace
+    let tmp = format!("{}/.{}.tmp", dir.display(), id.tag);
+    fs::write(&tmp, content)
+        .and_then(|_| fs::rename(&tmp, &path))
+        .map_err(|e| {
+            let _ = fs::remove_file(&tmp);
+            format!("Store resource {} failed: {}", name, e)
+        })
+}
+
+pub fn delete(name: &str) -> Result<(), String> {
+    let id = ResourceId::parse(name)?;
+    if id.builtin().is_some() {
+        return Err(format!(
+            "{} is a built-in resource and can't be deleted",
+            name
+        ));
+    }
+
+    let lock = FILE_LOCK.write();
+    assert_eq!(*lock, 0);
+
+    fs::remove_file(id.path()).map_err(|e| format!("Delete resource {} failed: {}", name, e))?;
+
+    // Prune the directories left empty
+    let type_dir = String::from(RESOURCE_PATH) + &id.repository + "/" + &id.type_;
+    let _ = fs::remove_dir(&type_dir);
+    let _ = fs::remove_dir(String::from(RESOURCE_PATH) + &id.repository);
+    Ok(())
+}
+
+/// List the names of the existing resources, optionally only in `repository`
+pub fn list(repository: Option<&str>) -> Result<Vec<String>, String> {
+    let mut names: Vec<String> = BUILTIN
+        .iter()
+        .filter(|(_, path)| Path::new(path).exists())
+        .map(|(name, _)| name.to_string())
+        .collect();
+
+    {
+        let lock = FILE_LOCK.read();
+        assert_eq!(*lock, 0);
+
+        for repo in read_dir_names(RESOURCE_PATH)? {
+            for type_ in read_dir_names(&(String::from(RESOURCE_PATH) + &repo))? {
+                let dir = String::from(RESOURCE_PATH) + &repo + "/" + &type_;
+                for tag in read_dir_names(&dir)? {
+                    if !tag.starts_with('.') {
+                        names.push(format!("{}/{}/{}", repo, type_, tag));
+                    }
+                }
+            }
+        }
+    }
+
+    if let Some(repository) = repository {
+        let prefix = format!("{}/", repository);
+        names.retain(|name| name.starts_with(&prefix));
+    }
+    names.sort();
+    names.dedup();
+
+    Ok(names)
+}
+
+fn read_dir_names(dir: &str) -> Result<Vec<String>, String> {
+    if !Path::new(dir).is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let mut names = Vec::new();
+    for entry in fs::read_dir(dir).map_err(|e| format!("read {} failed: {}", dir, e))? {
+        let entry = entry.map_err(|e| e.to_string())?;
+        if let Some(name) = entry.file_name().to_str() {
+            names.push(name.to_string());
+        }
+    }
+    Ok(names)
+}
+
+pub fn default() -> Result<(), String> {
+    if !Path::new(&RESOURCE_PATH.to_string()).exists() {
+        fs::create_dir_all(RESOURCE_PATH)
+            .map_err(|_| format!("create {:?} failed", RESOURCE_PATH))?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let id = ResourceId::parse("tenant-a/key/v1.0").unwrap();
+        assert_eq!(id.repository, "tenant-a");
+        assert_eq!(id.type_, "key");
+        assert_eq!(id.tag, "v1.0");
+        assert_eq!(id.name(), "tenant-a/key/v1.0");
+
+        assert!(ResourceId::parse("tenant-a/key").is_err());
+        assert!(ResourceId::parse("tenant-a/key/v1/extra").is_err());
+        assert!(ResourceId::parse("../key/v1").is_err());
+        assert!(ResourceId::parse("tenant-a//v1").is_err());
+        assert!(ResourceId::parse("tenant a/key/v1").is_err());
+        assert!(ResourceId::parse("tenant-a/key/.v1.tmp").is_err());
+    }
+
+    #[test]
+    fn test_builtin() {
+        assert_eq!(
+            ResourceId::parse(POLICY).unwrap().builtin(),
+            Some(image::POLICY)
+        );
+        assert!(set(GPG_KEYRING, b"content").is_err());
+        assert!(delete(SIGSTORE).is_err());
+    }
+
+    #[test]
+    fn test_set_export_delete() {
+        let name = "verdictd-test/secret/tag";
+
+        assert!(missing(name));
+        assert!(!missing("verdictd-test/secret/../tag"));
+        assert!(set(name, b"resource content").is_ok());
+        assert!(!missing(name));
+        assert_eq!(export_raw(name).unwrap(), b"resource content".to_vec());
+        assert_eq!(
+            export_base64(name).unwrap(),
+            base64::encode(b"resource content")
+        );
+        assert!(list(Some("verdictd-test"))
+            .unwrap()
+            .contains(&name.to_string()));
+
+        // A tag ending like a temporary file is a resource of its own
+        let tmp = "verdictd-test/secret/tag.tmp";
+        assert!(set(tmp, b"other content").is_ok());
+        assert!(set(name, b"new content").is_ok());
+        assert_eq!(export_raw(tmp).unwrap(), b"other content".to_vec());
+        assert_eq!(
+            list(Some("verdictd-test")).unwrap(),
+            vec![name.to_string(), tmp.to_string()]
+        );
+        assert!(delete(tmp).is_ok());
+
+        assert!(delete(name).is_ok());
+        assert!(export_raw(name).is_err());
+        assert!(list(Some("verdictd-test")).unwrap().is_empty());
+    }
+}
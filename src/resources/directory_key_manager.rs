@@ -21,8 +21,8 @@ pub fn set_key(kid: &String, key: &[u8]) -> std::io::Result<()> {
     let path = VERDICTD_KEY_PATH.to_string() + kid;
     info!("set key for keyFile: {}", path);
 
-    fs::write(path, key).expect("Unable to write file");
-    Ok(())
+    fs::create_dir_all(VERDICTD_KEY_PATH)?;
+    fs::write(path, key)
 }
 
 #[cfg(test)]
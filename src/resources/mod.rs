@@ -1,5 +1,12 @@
+pub mod audit;
+pub mod bundle;
+pub mod catalog;
 pub mod directory_key_manager;
 pub mod file;
 pub mod gpg;
 pub mod image;
 pub mod opa;
+pub mod policy_binding;
+pub mod secret;
+pub mod sigstruct;
+pub mod token;
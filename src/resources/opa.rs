@@ -1,9 +1,10 @@
-use crate::resources::file;
+use crate::policy_engine::{self, cache, Diagnostic};
+use crate::resources::{audit, file};
 use lazy_static::lazy_static;
 use parking_lot::RwLock;
+use serde_json::{Map, Value};
 use std::fs;
 use std::path::Path;
-use std::process::Command;
 
 lazy_static! {
     // Global file lock
@@ -14,64 +15,93 @@ pub const OPA_PATH: &str = "/opt/verdictd/opa/";
 pub const OPA_POLICY_SGX: &str = "sgxPolicy.rego";
 pub const OPA_DATA_SGX: &str = "sgxData";
 
+pub const OPA_POLICY_TDX: &str = "tdxPolicy.rego";
+pub const OPA_DATA_TDX: &str = "tdxData";
+
 pub const OPA_POLICY_CSV: &str = "csvPolicy.rego";
 pub const OPA_DATA_CSV: &str = "csvData";
 
+pub const OPA_POLICY_AUTH: &str = "authPolicy.rego";
+pub const OPA_DATA_AUTH: &str = "authData";
+
 pub fn set_reference(name: &str, reference: &str) -> Result<(), String> {
     let lock = FILE_LOCK.write();
     assert_eq!(*lock, 0);
 
+    cache::invalidate(name);
     let name = String::from(OPA_PATH) + name;
     file::set(&name, reference)
 }
 
-/// Save the input raw policy file
-/// Note that the OPA binary program needs to be installed and placed in the system path
-pub fn set_policy(name: &str, policy: &str) -> Result<(), String> {
+/// Check the policy with the policy engine, nothing is returned if it's valid
+pub fn check_policy(name: &str, policy: &str) -> Vec<Diagnostic> {
+    policy_engine::engine().check(name, policy)
+}
+
+/// Save the input raw policy file after checking it, nothing is stored if
+/// diagnostics are returned
+pub fn set_policy(name: &str, policy: &str) -> Result<Vec<Diagnostic>, String> {
+    let diagnostics = check_policy(name, policy);
+    if !diagnostics.is_empty() {
+        return Ok(diagnostics);
+    }
+
     let lock = FILE_LOCK.write();
     assert_eq!(*lock, 0);
 
-    let src = String::from(OPA_PATH) + name;
-    let bak = String::from(OPA_PATH) + name + ".bak";
-
-    if Path::new(&src).exists() {
-        fs::copy(&src, &bak).unwrap();
-    }
+    cache::invalidate(name);
+    let path = String::from(OPA_PATH) + name;
+    file::set(&path, policy).map_err(|e| format!("Store policy failed: {}", e))?;
+    Ok(diagnostics)
+}
 
-    file::write(&src, policy)
-        .map_err(|e| {
-            if Path::new(&bak).exists() {
-                fs::copy(&bak, &src).unwrap();
-            }
-            format!("Store policy failed: {}", e)
-        })
-        .and_then(|_| {
-            let status = Command::new("opa")
-                .arg("check")
-                .arg(&src)
-                .status()
-                .map_err(|_e| {
-                    if Path::new(&bak).exists() {
-                        fs::copy(&bak, &src).unwrap();
-                    }
-                    format!("Policy syntax check execution failed: {}", _e.to_string())
-                });
-            status
-        })
-        .and_then(|status| match status.success() {
-            true => {
-                if Path::new(&bak).exists() {
-                    fs::remove_file(&bak).unwrap();
-                }
+/// Release `kid` only to peers matching `binding` by adding it to the
+/// kid's bindings in `authData`
+pub fn bind_kid(kid: &str, binding: Value) -> Result<(), String> {
+    update_kids(
+        |kids| match kids.entry(kid).or_insert_with(|| Value::Array(Vec::new())) {
+            Value::Array(bindings) => {
+                bindings.push(binding);
                 Ok(())
             }
-            false => {
-                if Path::new(&bak).exists() {
-                    fs::copy(&bak, &src).unwrap();
-                }
-                Err(format!("Policy syntax check failed"))
-            }
-        })
+            _ => Err(format!(
+                "kids of {} aren't in the binding format",
+                OPA_DATA_AUTH
+            )),
+        },
+    )
+}
+
+/// Drop all bindings of `kid` from `authData`
+pub fn unbind_kid(kid: &str) -> Result<(), String> {
+    update_kids(|kids| {
+        kids.remove(kid);
+        Ok(())
+    })
+}
+
+fn update_kids(
+    update: impl FnOnce(&mut Map<String, Value>) -> Result<(), String>,
+) -> Result<(), String> {
+    let lock = FILE_LOCK.write();
+    assert_eq!(*lock, 0);
+
+    let name = String::from(OPA_PATH) + OPA_DATA_AUTH;
+    let mut data: Value = file::export_string(&name)
+        .and_then(|content| serde_json::from_str(&content).map_err(|e| e.to_string()))
+        .map_err(|e| format!("read {} failed: {}", OPA_DATA_AUTH, e))?;
+    let kids = data
+        .as_object_mut()
+        .ok_or_else(|| format!("{} isn't a JSON object", OPA_DATA_AUTH))?
+        .entry("kids")
+        .or_insert_with(|| Value::Object(Map::new()))
+        .as_object_mut()
+        .ok_or_else(|| format!("kids of {} aren't in the binding format", OPA_DATA_AUTH))?;
+    update(kids)?;
+
+    cache::invalidate(OPA_DATA_AUTH);
+    let content = serde_json::to_string_pretty(&data).map_err(|e| e.to_string())?;
+    file::set(&name, &content).map_err(|e| format!("Store {} failed: {}", OPA_DATA_AUTH, e))
 }
 
 // Export existing policy from verdictd
@@ -82,14 +112,14 @@ pub fn export(name: &str) -> Result<String, String> {
     file::export_string(&name)
 }
 
-pub fn default() -> Result<(), String> {
-    if !Path::new(&OPA_PATH.to_string()).exists() {
-        fs::create_dir_all(OPA_PATH).map_err(|_| format!("create {:?} failed", OPA_PATH))?;
-    }
+pub fn exists(name: &str) -> bool {
+    let lock = FILE_LOCK.read();
+    assert_eq!(*lock, 0);
+    Path::new(&(String::from(OPA_PATH) + name)).exists()
+}
 
-    if !Path::new(&(OPA_PATH.to_string() + OPA_POLICY_SGX)).exists() {
-        info!("{} isn't exist", OPA_POLICY_SGX);
-        let policy = r#"
+/// Default policy of SGX evidence
+pub const SGX_POLICY: &str = r#"
 package policy
 
 # By default, deny requests.
@@ -100,6 +130,23 @@ allow {
     mrSigner_is_grant
     input.productId >= data.productId
     input.svn >= data.svn
+    debug_is_grant
+    tcbStatus_is_grant
+}
+
+debug_is_grant {
+    input.debug == false
+}
+debug_is_grant {
+    data.allowDebug == true
+}
+
+tcbStatus_is_grant {
+    count(data.tcbStatus) == 0
+}
+tcbStatus_is_grant {
+    count(data.tcbStatus) > 0
+    input.tcbStatus == data.tcbStatus[_]
 }
 
 mrEnclave_is_grant {
@@ -117,32 +164,384 @@ mrSigner_is_grant {
     count(data.mrSigner) > 0
     input.mrSigner == data.mrSigner[_]
 }
+
+deny_reasons[reason] {
+    not mrEnclave_is_grant
+    reason := sprintf("mrEnclave %v isn't a reference value", [input.mrEnclave])
+}
+deny_reasons[reason] {
+    not mrSigner_is_grant
+    reason := sprintf("mrSigner %v isn't a reference value", [input.mrSigner])
+}
+deny_reasons[reason] {
+    not input.productId >= data.productId
+    reason := sprintf("productId %v is lower than %v", [input.productId, data.productId])
+}
+deny_reasons[reason] {
+    not input.svn >= data.svn
+    reason := sprintf("svn %v is lower than %v", [input.svn, data.svn])
+}
+deny_reasons["debug enclaves aren't allowed"] {
+    not debug_is_grant
+}
+deny_reasons[reason] {
+    not tcbStatus_is_grant
+    reason := sprintf("tcbStatus %v isn't allowed", [input.tcbStatus])
+}
 "#;
-        file::write(
-            &(String::from(OPA_PATH) + OPA_POLICY_SGX),
-            &policy.to_string(),
-        )
-        .map_err(|e| format!("Set {} failed with error {:?}", OPA_POLICY_SGX, e))?;
-    }
 
-    if !Path::new(&(OPA_PATH.to_string() + OPA_DATA_SGX)).exists() {
-        info!("{} isn't exist", OPA_DATA_SGX);
-        let sgx_data = r#"{
+/// Default reference of SGX evidence
+pub const SGX_DATA: &str = r#"{
     "mrEnclave": [],
     "mrSigner": [],
     "productId": 0,
-    "svn": 0
+    "svn": 0,
+    "allowDebug": false,
+    "tcbStatus": []
+}"#;
+
+/// Default policy of TDX evidence
+pub const TDX_POLICY: &str = r#"
+package policy
+
+# By default, deny requests.
+default allow = false
+
+allow {
+    debug_is_grant
+    mrTd_is_grant
+    mrConfigId_is_grant
+    mrOwner_is_grant
+    rtmr_is_grant
+    teeTcbSvn_is_grant
+}
+
+debug_is_grant {
+    input.debug == false
+}
+debug_is_grant {
+    data.allowDebug == true
+}
+
+mrTd_is_grant {
+    count(data.mrTd) == 0
+}
+mrTd_is_grant {
+    count(data.mrTd) > 0
+    input.mrTd == data.mrTd[_]
+}
+
+mrConfigId_is_grant {
+    count(data.mrConfigId) == 0
+}
+mrConfigId_is_grant {
+    count(data.mrConfigId) > 0
+    input.mrConfigId == data.mrConfigId[_]
+}
+
+mrOwner_is_grant {
+    count(data.mrOwner) == 0
+}
+mrOwner_is_grant {
+    count(data.mrOwner) > 0
+    input.mrOwner == data.mrOwner[_]
+}
+
+# data.rtmr maps "rtmr0".."rtmr3" to their expected values
+rtmr_is_grant {
+    mismatches := [name | value := data.rtmr[name]; input[name] != value]
+    count(mismatches) == 0
+}
+
+# Every TCB SVN component must be at least the reference one
+teeTcbSvn_is_grant {
+    count(data.teeTcbSvn) == 0
+}
+teeTcbSvn_is_grant {
+    count(data.teeTcbSvn) == 16
+    lower := [i | data.teeTcbSvn[i] > input.teeTcbSvn[i]]
+    count(lower) == 0
+}
+
+deny_reasons["debug TDs aren't allowed"] {
+    not debug_is_grant
+}
+deny_reasons[reason] {
+    not mrTd_is_grant
+    reason := sprintf("mrTd %v isn't a reference value", [input.mrTd])
+}
+deny_reasons[reason] {
+    not mrConfigId_is_grant
+    reason := sprintf("mrConfigId %v isn't a reference value", [input.mrConfigId])
+}
+deny_reasons[reason] {
+    not mrOwner_is_grant
+    reason := sprintf("mrOwner %v isn't a reference value", [input.mrOwner])
+}
+deny_reasons[reason] {
+    value := data.rtmr[name]
+    input[name] != value
+    reason := sprintf("%v %v isn't the reference value", [name, input[name]])
+}
+deny_reasons["teeTcbSvn is lower than the reference"] {
+    not teeTcbSvn_is_grant
+}
+"#;
+
+/// Default reference of TDX evidence
+pub const TDX_DATA: &str = r#"{
+    "mrTd": [],
+    "mrConfigId": [],
+    "mrOwner": [],
+    "rtmr": {},
+    "teeTcbSvn": [],
+    "allowDebug": false
+}"#;
+
+/// Default policy of CSV evidence
+pub const CSV_POLICY: &str = r#"
+package policy
+
+# By default, deny requests.
+default allow = false
+
+allow {
+    debug_is_grant
+    migration_is_grant
+    measure_is_grant
+    vmId_is_grant
+    input.policy.apiMajor >= data.apiMajor
+}
+
+debug_is_grant {
+    input.debug == false
+}
+debug_is_grant {
+    data.allowDebug == true
+}
+
+migration_is_grant {
+    input.migratable == false
+}
+migration_is_grant {
+    data.allowMigration == true
+}
+
+measure_is_grant {
+    count(data.measure) == 0
+}
+measure_is_grant {
+    count(data.measure) > 0
+    input.measure == data.measure[_]
+}
+
+vmId_is_grant {
+    count(data.vmId) == 0
+}
+vmId_is_grant {
+    count(data.vmId) > 0
+    input.vmId == data.vmId[_]
+}
+
+deny_reasons["debug guests aren't allowed"] {
+    not debug_is_grant
+}
+deny_reasons["migratable guests aren't allowed"] {
+    not migration_is_grant
+}
+deny_reasons[reason] {
+    not measure_is_grant
+    reason := sprintf("measure %v isn't a reference value", [input.measure])
+}
+deny_reasons[reason] {
+    not vmId_is_grant
+    reason := sprintf("vmId %v isn't a reference value", [input.vmId])
+}
+deny_reasons[reason] {
+    not input.policy.apiMajor >= data.apiMajor
+    reason := sprintf("apiMajor %v is lower than %v", [input.policy.apiMajor, data.apiMajor])
+}
+"#;
+
+/// Default reference of CSV evidence
+pub const CSV_DATA: &str = r#"{
+    "measure": [],
+    "vmId": [],
+    "apiMajor": 0,
+    "allowDebug": false,
+    "allowMigration": true
 }"#;
 
-        let lock = FILE_LOCK.write();
-        assert_eq!(*lock, 0);
+/// Default authorization policy of the attestation protocol
+pub const AUTH_POLICY: &str = r#"
+package policy
+
+# Kids, resources and secrets without bindings can be fetched by any
+# attested peer, a peer whose evidence isn't verified has tee "none".
+# Otherwise the peer's claims must match all fields of one of the bindings.
+default allow = false
+
+bindings[binding] {
+    binding := data.kids[input.kid][_]
+}
+
+bindings[binding] {
+    binding := data.resources[input.resource][_]
+}
+
+bindings[binding] {
+    binding := data.secrets[input.secret][_]
+}
+
+allow {
+    count(bindings) == 0
+    input.tee != "none"
+}
 
-        file::write(
-            &(String::from(OPA_PATH) + OPA_DATA_SGX),
-            &sgx_data.to_string(),
-        )
-        .map_err(|e| format!("Set {} failed with error {:?}", OPA_DATA_SGX, e))?;
+allow {
+    binding := bindings[_]
+    claims_match(binding)
+}
+
+claims_match(binding) {
+    mismatches := [name | value := binding[name]; not claim_matches(name, value)]
+    count(mismatches) == 0
+}
+
+claim_matches("tee", value) {
+    input.tee == value
+}
+
+claim_matches(name, value) {
+    input.claims[name] == value
+}
+"#;
+
+/// Default reference of the authorization policy
+pub const AUTH_DATA: &str = r#"{
+    "kids": {},
+    "resources": {},
+    "secrets": {}
+}"#;
+
+/// SHA-256 of the defaults shipped by earlier versions. A file still holding
+/// one of them was never customized and is replaced by the current default:
+/// the earlier authorization policies release unbound items to unattested
+/// peers, the earlier SGX reference denies evidence without TCB status.
+const SUPERSEDED: &[(&str, &[&str])] = &[
+    (
+        OPA_POLICY_AUTH,
+        &[
+            "a3a3ce7a940289960c06c058b364b10111760a41c3ca7d2b71b1e6248889cdbb",
+            "4c4403b4ae9ab666888d8571d29b299cb5c05ae253d023436ea8443dcf0f5b75",
+        ],
+    ),
+    (
+        OPA_DATA_SGX,
+        &["7cdc4f4fc53ab09d41f48ce1e7a8d9b58eca8a37d6a2bc24536baf5fa189e813"],
+    ),
+];
+
+/// Write the default `content` of `name` to `path` if it's missing or still
+/// an earlier default
+fn install(path: &str, name: &str, content: &str) -> Result<(), String> {
+    let lock = FILE_LOCK.write();
+    assert_eq!(*lock, 0);
+
+    if Path::new(path).exists() {
+        let superseded = SUPERSEDED
+            .iter()
+            .filter(|(file, _)| *file == name)
+            .flat_map(|(_, digests)| digests.iter())
+            .collect::<Vec<_>>();
+        if superseded.is_empty() {
+            return Ok(());
+        }
+        let current = file::export_raw(path)?;
+        if !superseded.contains(&&audit::digest(&current).as_str()) {
+            return Ok(());
+        }
+        info!("{} is an earlier default, replace it", name);
+    } else {
+        info!("{} isn't exist", name);
+    }
+
+    cache::invalidate(name);
+    file::write(path, content).map_err(|e| format!("Set {} failed with error {:?}", name, e))
+}
+
+pub fn default() -> Result<(), String> {
+    if !Path::new(&OPA_PATH.to_string()).exists() {
+        fs::create_dir_all(OPA_PATH).map_err(|_| format!("create {:?} failed", OPA_PATH))?;
+    }
+
+    for (name, content) in [
+        (OPA_POLICY_SGX, SGX_POLICY),
+        (OPA_DATA_SGX, SGX_DATA),
+        (OPA_POLICY_TDX, TDX_POLICY),
+        (OPA_DATA_TDX, TDX_DATA),
+        (OPA_POLICY_CSV, CSV_POLICY),
+        (OPA_DATA_CSV, CSV_DATA),
+        (OPA_POLICY_AUTH, AUTH_POLICY),
+        (OPA_DATA_AUTH, AUTH_DATA),
+    ] {
+        install(&(String::from(OPA_PATH) + name), name, content)?;
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_set_policy() {
+        default().unwrap();
+        let name = "verdictd-check-test.rego";
+        let _ = fs::remove_file(String::from(OPA_PATH) + name);
+
+        let diagnostics = set_policy(name, "package policy\n\nallow {\n    input.a ==\n}").unwrap();
+        assert!(!diagnostics.is_empty());
+        assert!(diagnostics[0].to_string().contains(name));
+        assert!(!exists(name));
+
+        let diagnostics = check_policy(name, "package policy\n\ndeny { true }\n");
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].message, policy_engine::MISSING_ALLOW);
+
+        assert_eq!(set_policy(name, SGX_POLICY), Ok(vec![]));
+        assert_eq!(export(name).unwrap(), SGX_POLICY);
+        let _ = fs::remove_file(String::from(OPA_PATH) + name);
+    }
+
+    #[test]
+    fn test_install() {
+        let dir = tempdir::TempDir::new("verdictd-opa").unwrap();
+        let path = dir.path().join(OPA_DATA_SGX);
+        let path = path.to_str().unwrap();
+
+        install(path, OPA_DATA_SGX, SGX_DATA).unwrap();
+        assert_eq!(file::export_string(path).unwrap(), SGX_DATA);
+
+        // The SGX reference shipped requiring a TCB status is replaced
+        let earlier = SGX_DATA.replace(
+            r#""tcbStatus": []"#,
+            r#""tcbStatus": ["UpToDate", "SWHardeningNeeded"]"#,
+        );
+        fs::write(path, &earlier).unwrap();
+        install(path, OPA_DATA_SGX, SGX_DATA).unwrap();
+        assert_eq!(file::export_string(path).unwrap(), SGX_DATA);
+
+        // A customized reference is kept
+        let custom = SGX_DATA.replace(r#""svn": 0"#, r#""svn": 3"#);
+        fs::write(path, &custom).unwrap();
+        install(path, OPA_DATA_SGX, SGX_DATA).unwrap();
+        assert_eq!(file::export_string(path).unwrap(), custom);
+
+        // Earlier defaults of other files aren't confused with it
+        fs::write(path, &earlier).unwrap();
+        install(path, OPA_DATA_TDX, SGX_DATA).unwrap();
+        assert_eq!(file::export_string(path).unwrap(), earlier);
+    }
+}
@@ -0,0 +1,266 @@
+//! Bindings choosing the OPA policy and reference, or the OPA bundle, an
+//! attestation is evaluated against, stored in
+//! `/opt/verdictd/policy_bindings.json`.
+//!
+//! A binding matches the evidence of a TEE type, accepted on a listener
+//! and/or signed by a signer; a field that is not set matches anything.
+//! When several bindings match, the one setting the most fields wins, then
+//! the one created first. Evidence no binding matches is evaluated against
+//! the default policy of its TEE type.
+use crate::resources::{bundle, opa};
+use lazy_static::lazy_static;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+
+lazy_static! {
+    // Global file lock
+    pub static ref FILE_LOCK: RwLock<u32> = RwLock::new(0);
+}
+
+pub const POLICY_BINDINGS: &str = "/opt/verdictd/policy_bindings.json";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Binding {
+    pub name: String,
+    /// TEE type, e.g. `sgx`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tee: Option<String>,
+    /// Address of the attestation listener, e.g. `0.0.0.0:1111`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub listener: Option<String>,
+    /// Signer of the workload, the base64 encoded mrSigner of SGX enclaves
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub signer: Option<String>,
+    /// Name of the OPA policy file
+    #[serde(default)]
+    pub policy: String,
+    /// Name of the OPA reference file
+    #[serde(default)]
+    pub reference: String,
+    /// Name of the OPA bundle, instead of a policy and a reference
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bundle: Option<String>,
+}
+
+impl Binding {
+    fn matches(&self, tee: &str, listener: Option<&str>, signer: Option<&str>) -> bool {
+        let field_matches = |field: &Option<String>, value: Option<&str>| match field {
+            Some(field) => Some(field.as_str()) == value,
+            None => true,
+        };
+
+        field_matches(&self.tee, Some(tee))
+            && field_matches(&self.listener, listener)
+            && field_matches(&self.signer, signer)
+    }
+
+    fn specificity(&self) -> usize {
+        [&self.tee, &self.listener, &self.signer]
+            .iter()
+            .filter(|field| field.is_some())
+            .count()
+    }
+}
+
+fn read() -> Result<Vec<Binding>, String> {
+    if !Path::new(POLICY_BINDINGS).exists() {
+        return Ok(Vec::new());
+    }
+
+    let content =
+        fs::read(POLICY_BINDINGS).map_err(|e| format!("read {} failed: {}", POLICY_BINDINGS, e))?;
+    serde_json::from_slice(&content).map_err(|e| format!("parse {} failed: {}", POLICY_BINDINGS, e))
+}
+
+fn write(bindings: &[Binding]) -> Result<(), String> {
+    let tmp = String::from(POLICY_BINDINGS) + ".tmp";
+    let content = serde_json::to_vec_pretty(bindings).map_err(|e| e.to_string())?;
+    fs::write(&tmp, content)
+        .and_then(|_| fs::rename(&tmp, POLICY_BINDINGS))
+        .map_err(|e| {
+            let _ = fs::remove_file(&tmp);
+            format!("Store policy bindings failed: {}", e)
+        })
+}
+
+/// Add `binding`, or replace the binding of the same name
+pub fn set(binding: Binding) -> Result<(), String> {
+    if binding.name.is_empty() {
+        return Err("policy binding name is empty".to_string());
+    }
+    match &binding.bundle {
+        Some(name) => {
+            if !binding.policy.is_empty() || !binding.reference.is_empty() {
+                return Err("a bundle binding can't set a policy or a reference".to_string());
+            }
+            if !bundle::exists(name) {
+                return Err(format!("OPA bundle {:?} doesn't exist", name));
+            }
+        }
+        None => {
+            for file in [&binding.policy, &binding.reference] {
+                if file.is_empty() || file.contains('/') || !opa::exists(file) {
+                    return Err(format!("OPA file {:?} doesn't exist", file));
+                }
+            }
+        }
+    }
+
+    let lock = FILE_LOCK.write();
+    assert_eq!(*lock, 0);
+
+    let mut bindings = read()?;
+    match bindings.iter_mut().find(|b| b.name == binding.name) {
+        Some(old) => *old = binding,
+        None => bindings.push(binding),
+    }
+    write(&bindings)
+}
+
+pub fn delete(name: &str) -> Result<(), String> {
+    let lock = FILE_LOCK.write();
+    assert_eq!(*lock, 0);
+
+    let mut bindings = read()?;
+    let len = bindings.len();
+    bindings.retain(|b| b.name != name);
+    if bindings.len() == len {
+        return Err(format!("policy binding {} not found", name));
+    }
+    write(&bindings)
+}
+
+pub fn list() -> Result<Vec<Binding>, String> {
+    let lock = FILE_LOCK.read();
+    assert_eq!(*lock, 0);
+
+    read()
+}
+
+/// The binding an evidence of `tee` accepted on `listener` and signed by
+/// `signer` is evaluated with, None if it should use the default policy
+pub fn select(
+    tee: &str,
+    listener: Option<&str>,
+    signer: Option<&str>,
+) -> Result<Option<Binding>, String> {
+    let mut selected: Option<Binding> = None;
+    for binding in list()? {
+        if !binding.matches(tee, listener, signer) {
+            continue;
+        }
+        match &selected {
+            Some(s) if s.specificity() >= binding.specificity() => {}
+            _ => selected = Some(binding),
+        }
+    }
+    Ok(selected)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn binding(
+        name: &str,
+        tee: Option<&str>,
+        listener: Option<&str>,
+        signer: Option<&str>,
+    ) -> Binding {
+        Binding {
+            name: name.to_string(),
+            tee: tee.map(|s| s.to_string()),
+            listener: listener.map(|s| s.to_string()),
+            signer: signer.map(|s| s.to_string()),
+            policy: opa::OPA_POLICY_SGX.to_string(),
+            reference: opa::OPA_DATA_SGX.to_string(),
+            bundle: None,
+        }
+    }
+
+    #[test]
+    fn test_matches() {
+        let any = binding("any", None, None, None);
+        let signer = binding("signer", Some("sgx"), None, Some("verdictd-test-signer"));
+        let listener = binding("listener", None, Some("0.0.0.0:1111"), None);
+
+        assert!(any.matches("tdx", None, None));
+        assert!(signer.matches("sgx", Some("0.0.0.0:1111"), Some("verdictd-test-signer")));
+        assert!(!signer.matches("sgx", None, None));
+        assert!(!signer.matches("tdx", None, Some("verdictd-test-signer")));
+        assert!(listener.matches("csv", Some("0.0.0.0:1111"), None));
+        assert!(!listener.matches("csv", Some("127.0.0.1:1111"), None));
+        assert_eq!(any.specificity(), 0);
+        assert_eq!(signer.specificity(), 2);
+    }
+
+    #[test]
+    fn test_set_select_delete() {
+        opa::default().unwrap();
+
+        let signer = Some("verdictd-test-signer");
+        let general = binding("verdictd-test-general", Some("sgx"), None, signer);
+        let mut specific = binding(
+            "verdictd-test-specific",
+            Some("sgx"),
+            Some("verdictd-test-listener"),
+            signer,
+        );
+        assert!(set(general.clone()).is_ok());
+        assert!(set(specific.clone()).is_ok());
+
+        assert_eq!(select("sgx", None, signer).unwrap(), Some(general.clone()));
+        assert_eq!(
+            select("sgx", Some("verdictd-test-listener"), signer).unwrap(),
+            Some(specific.clone())
+        );
+
+        specific.reference = opa::OPA_DATA_TDX.to_string();
+        assert!(set(specific.clone()).is_ok());
+        assert_eq!(
+            select("sgx", Some("verdictd-test-listener"), signer).unwrap(),
+            Some(specific.clone())
+        );
+
+        specific.policy = "verdictd-test-missing.rego".to_string();
+        assert!(set(specific.clone()).is_err());
+
+        assert!(delete(&specific.name).is_ok());
+        assert!(delete(&specific.name).is_err());
+        assert_eq!(
+            select("sgx", Some("verdictd-test-listener"), signer).unwrap(),
+            Some(general.clone())
+        );
+        assert!(delete(&general.name).is_ok());
+
+        // A bundle binding names an existing bundle only
+        bundle::default().unwrap();
+        let bundle_name = "verdictd-test-binding-bundle";
+        assert_eq!(
+            bundle::set(
+                bundle_name,
+                &crate::policy_engine::bundle::tests::tenant_bundle()
+            ),
+            Ok(vec![])
+        );
+        let mut tenant = binding("verdictd-test-bundle", Some("sgx"), None, signer);
+        tenant.bundle = Some(bundle_name.to_string());
+        assert!(set(tenant.clone()).is_err());
+        tenant.policy = String::new();
+        tenant.reference = String::new();
+        assert!(set(tenant.clone()).is_ok());
+        assert_eq!(select("sgx", None, signer).unwrap(), Some(tenant.clone()));
+        assert!(bundle::delete(bundle_name).is_err());
+        tenant.bundle = Some("verdictd-test-missing-bundle".to_string());
+        assert!(set(tenant.clone()).is_err());
+        assert!(delete(&tenant.name).is_ok());
+        assert!(bundle::delete(bundle_name).is_ok());
+
+        assert!(!list()
+            .unwrap()
+            .iter()
+            .any(|b| b.name.starts_with("verdictd-test")));
+    }
+}
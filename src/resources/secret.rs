@@ -0,0 +1,265 @@
+//! Secrets released to attested peers a limited number of times or until a
+//! deadline, stored under `/opt/verdictd/secrets/`.
+//!
+//! Every secret is a JSON file with its content and usage state. A use is
+//! recorded under the global lock and persisted before the content is
+//! released, so concurrent sessions can't spend a secret twice. Secrets
+//! that are used up or expired are deleted.
+use lazy_static::lazy_static;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+lazy_static! {
+    // Global file lock
+    pub static ref FILE_LOCK: RwLock<u32> = RwLock::new(0);
+}
+
+pub const SECRET_PATH: &str = "/opt/verdictd/secrets/";
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Secret {
+    /// Base64 encoded content
+    content: String,
+    /// Max number of uses, 0 means unlimited
+    pub max_uses: u32,
+    pub uses: u32,
+    pub created_at: u64,
+    /// Unix time after which the secret is destroyed, 0 means never
+    pub expires_at: u64,
+}
+
+impl Secret {
+    /// Remaining uses, None if the number of uses is unlimited
+    pub fn remaining_uses(&self) -> Option<u32> {
+        match self.max_uses {
+            0 => None,
+            max_uses => Some(max_uses.saturating_sub(self.uses)),
+        }
+    }
+
+    fn expired(&self, now: u64) -> bool {
+        self.expires_at != 0 && now >= self.expires_at
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn check_id(id: &str) -> Result<(), String> {
+    if id.is_empty()
+        || id.starts_with('.')
+        || !id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '.' || c == '-' || c == '_')
+    {
+        return Err(format!("invalid secret id {:?}", id));
+    }
+    Ok(())
+}
+
+fn path(id: &str) -> String {
+    String::from(SECRET_PATH) + id + ".json"
+}
+
+fn read(id: &str) -> Result<Secret, String> {
+    let content = fs::read(path(id)).map_err(|_| format!("secret {} not found", id))?;
+    serde_json::from_slice(&content).map_err(|e| format!("parse secret {} failed: {}", id, e))
+}
+
+fn write(id: &str, secret: &Secret) -> Result<(), String> {
+    let path = path(id);
+    let tmp = path.clone() + ".tmp";
+    let content = serde_json::to_vec(secret).map_err(|e| e.to_string())?;
+
+    // Replace the file atomically, a crash never leaves a stale counter
+    fs::write(&tmp, content)
+        .and_then(|_| fs::rename(&tmp, &path))
+        .map_err(|e| {
+            let _ = fs::remove_file(&tmp);
+            format!("Store secret {} failed: {}", id, e)
+        })
+}
+
+fn destroy(id: &str) -> Result<(), String> {
+    fs::remove_file(path(id)).map_err(|e| format!("Delete secret {} failed: {}", id, e))
+}
+
+/// Create secret `id` released at most `max_uses` times (0: unlimited) and
+/// only within `ttl` seconds (0: no deadline)
+pub fn create(id: &str, content: &[u8], max_uses: u32, ttl: u64) -> Result<(), String> {
+    check_id(id)?;
+    if max_uses == 0 && ttl == 0 {
+        return Err("a secret needs a max number of uses or a ttl".to_string());
+    }
+
+    let lock = FILE_LOCK.write();
+    assert_eq!(*lock, 0);
+
+    if Path::new(&path(id)).exists() {
+        return Err(format!("secret {} already exists", id));
+    }
+
+    let created_at = now();
+    let secret = Secret {
+        content: base64::encode(content),
+        max_uses,
+        uses: 0,
+        created_at,
+        expires_at: if ttl == 0 { 0 } else { created_at + ttl },
+    };
+    write(id, &secret)
+}
+
+/// Usage state of secret `id`, an expired secret is destroyed
+pub fn status(id: &str) -> Result<Secret, String> {
+    check_id(id)?;
+
+    let lock = FILE_LOCK.write();
+    assert_eq!(*lock, 0);
+
+    let secret = read(id)?;
+    if secret.expired(now()) {
+        destroy(id)?;
+        return Err(format!("secret {} expired", id));
+    }
+    Ok(secret)
+}
+
+/// Spend one use of secret `id` and return its content along with the
+/// updated state. The secret is destroyed once used up or expired.
+pub fn consume(id: &str) -> Result<(Vec<u8>, Secret), String> {
+    check_id(id)?;
+
+    let lock = FILE_LOCK.write();
+    assert_eq!(*lock, 0);
+
+    let mut secret = read(id)?;
+    if secret.expired(now()) {
+        destroy(id)?;
+        return Err(format!("secret {} expired", id));
+    }
+    if secret.remaining_uses() == Some(0) {
+        destroy(id)?;
+        return Err(format!("secret {} is used up", id));
+    }
+
+    let content = base64::decode(&secret.content)
+        .map_err(|e| format!("decode secret {} failed: {}", id, e))?;
+
+    secret.uses += 1;
+    if secret.remaining_uses() == Some(0) {
+        destroy(id)?;
+    } else {
+        write(id, &secret)?;
+    }
+    info!("secret {} consumed, uses: {}", id, secret.uses);
+
+    Ok((content, secret))
+}
+
+pub fn delete(id: &str) -> Result<(), String> {
+    check_id(id)?;
+
+    let lock = FILE_LOCK.write();
+    assert_eq!(*lock, 0);
+
+    destroy(id)
+}
+
+/// Destroy the expired secrets
+pub fn purge() -> Result<(), String> {
+    let lock = FILE_LOCK.write();
+    assert_eq!(*lock, 0);
+
+    let now = now();
+    let entries = fs::read_dir(SECRET_PATH).map_err(|e| format!("read secrets failed: {}", e))?;
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if let Some(id) = name.strip_suffix(".json") {
+            if read(id).map(|secret| secret.expired(now)).unwrap_or(false) {
+                info!("secret {} expired", id);
+                destroy(id)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+pub fn default() -> Result<(), String> {
+    if !Path::new(&SECRET_PATH.to_string()).exists() {
+        fs::create_dir_all(SECRET_PATH).map_err(|_| format!("create {:?} failed", SECRET_PATH))?;
+    }
+
+    purge()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_use_counter() {
+        default().unwrap();
+        let id = "verdictd-test-counter";
+        let _ = delete(id);
+
+        assert!(create(id, b"token", 0, 0).is_err());
+        assert!(create("../escape", b"token", 1, 0).is_err());
+
+        create(id, b"token", 2, 0).unwrap();
+        assert!(create(id, b"token", 2, 0).is_err());
+        assert_eq!(status(id).unwrap().remaining_uses(), Some(2));
+
+        let (content, secret) = consume(id).unwrap();
+        assert_eq!(content, b"token".to_vec());
+        assert_eq!(secret.remaining_uses(), Some(1));
+
+        consume(id).unwrap();
+        assert!(consume(id).is_err());
+        assert!(status(id).is_err());
+    }
+
+    #[test]
+    fn test_ttl() {
+        default().unwrap();
+        let id = "verdictd-test-ttl";
+        let _ = delete(id);
+
+        create(id, b"token", 0, 3600).unwrap();
+        assert_eq!(consume(id).unwrap().1.remaining_uses(), None);
+        assert_eq!(consume(id).unwrap().1.uses, 2);
+
+        // Let it expire
+        let mut secret = read(id).unwrap();
+        secret.expires_at = now() - 1;
+        write(id, &secret).unwrap();
+        assert!(consume(id).is_err());
+        assert!(!Path::new(&path(id)).exists());
+    }
+
+    #[test]
+    fn test_concurrent_consume() {
+        default().unwrap();
+        let id = "verdictd-test-concurrent";
+        let _ = delete(id);
+        create(id, b"token", 5, 0).unwrap();
+
+        let handles: Vec<_> = (0..16)
+            .map(|_| std::thread::spawn(move || consume(id).is_ok()))
+            .collect();
+        let consumed = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .filter(|ok| *ok)
+            .count();
+        assert_eq!(consumed, 5);
+    }
+}
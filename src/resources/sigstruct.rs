@@ -0,0 +1,359 @@
+//! SGX reference values imported from signed enclaves.
+//!
+//! The SIGSTRUCT of a signed enclave carries everything the `sgxData`
+//! reference matches on: the enclave hash (MRENCLAVE), the signer's RSA
+//! modulus whose SHA-256 is MRSIGNER, ISVPRODID and ISVSVN. It's read
+//! either from a standalone sigstruct file or from the `.note.sgxmeta`
+//! section `sgx_sign` adds to the enclave `.so`.
+extern crate crypto;
+
+use crate::resources::opa;
+use crypto::digest::Digest;
+use crypto::sha2::Sha256;
+use serde_json::Value;
+
+pub const SIGSTRUCT_SIZE: usize = 1808;
+
+const SIGSTRUCT_HEADER: [u8; 16] = [
+    0x06, 0x00, 0x00, 0x00, 0xe1, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00,
+];
+const MODULUS_OFFSET: usize = 128;
+const MODULUS_SIZE: usize = 384;
+const ENCLAVE_HASH_OFFSET: usize = 960;
+const ISV_PROD_ID_OFFSET: usize = 1024;
+const ISV_SVN_OFFSET: usize = 1026;
+
+const ELF_MAGIC: &[u8] = b"\x7fELF";
+const SGXMETA_SECTION: &str = ".note.sgxmeta";
+const SGXMETA_NOTE_NAME: &[u8] = b"sgx_metadata\0";
+const METADATA_MAGIC: u64 = 0x86a8_0294_635d_0e4c;
+// The SIGSTRUCT follows the fixed fields of the SDK's metadata_t
+const METADATA_SIGSTRUCT_OFFSET: usize = 64;
+
+/// Reference values of a signed enclave
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sigstruct {
+    pub mr_enclave: [u8; 32],
+    pub mr_signer: [u8; 32],
+    pub product_id: u16,
+    pub svn: u16,
+}
+
+/// How imported values are merged into an existing reference
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Merge {
+    /// Add the enclave to the values already accepted
+    Append,
+    /// Accept only the imported enclave
+    Replace,
+}
+
+fn slice(data: &[u8], offset: usize, size: usize) -> Option<&[u8]> {
+    offset
+        .checked_add(size)
+        .and_then(|end| data.get(offset..end))
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Result<u16, String> {
+    data.get(offset..offset + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| "ELF file is truncated".to_string())
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Result<u32, String> {
+    data.get(offset..offset + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| "ELF file is truncated".to_string())
+}
+
+fn u64_at(data: &[u8], offset: usize) -> Result<u64, String> {
+    data.get(offset..offset + 8)
+        .map(|b| {
+            let mut bytes = [0u8; 8];
+            bytes.copy_from_slice(b);
+            u64::from_le_bytes(bytes)
+        })
+        .ok_or_else(|| "ELF file is truncated".to_string())
+}
+
+impl Sigstruct {
+    /// Parse a signed enclave ELF or a standalone SIGSTRUCT
+    pub fn parse(content: &[u8]) -> Result<Sigstruct, String> {
+        if content.starts_with(ELF_MAGIC) {
+            Self::from_elf(content)
+        } else {
+            Self::from_sigstruct(content)
+        }
+    }
+
+    pub fn from_sigstruct(sigstruct: &[u8]) -> Result<Sigstruct, String> {
+        if sigstruct.len() < SIGSTRUCT_SIZE {
+            return Err(format!(
+                "SIGSTRUCT must be {} bytes, got {}",
+                SIGSTRUCT_SIZE,
+                sigstruct.len()
+            ));
+        }
+        if sigstruct[..SIGSTRUCT_HEADER.len()] != SIGSTRUCT_HEADER {
+            return Err("SIGSTRUCT header is invalid".to_string());
+        }
+
+        let mut mr_enclave = [0u8; 32];
+        mr_enclave.copy_from_slice(&sigstruct[ENCLAVE_HASH_OFFSET..ENCLAVE_HASH_OFFSET + 32]);
+
+        // MRSIGNER is the SHA-256 of the modulus as stored, little endian
+        let mut mr_signer = [0u8; 32];
+        let mut hasher = Sha256::new();
+        hasher.input(&sigstruct[MODULUS_OFFSET..MODULUS_OFFSET + MODULUS_SIZE]);
+        hasher.result(&mut mr_signer);
+
+        Ok(Sigstruct {
+            mr_enclave,
+            mr_signer,
+            product_id: u16_at(sigstruct, ISV_PROD_ID_OFFSET)?,
+            svn: u16_at(sigstruct, ISV_SVN_OFFSET)?,
+        })
+    }
+
+    /// Find the SIGSTRUCT in the `.note.sgxmeta` section of an ELF64 enclave
+    pub fn from_elf(elf: &[u8]) -> Result<Sigstruct, String> {
+        if elf.get(4) != Some(&2) {
+            return Err("only ELF64 enclaves are supported".to_string());
+        }
+
+        let shoff = u64_at(elf, 0x28)? as usize;
+        let shentsize = u16_at(elf, 0x3a)? as usize;
+        let shnum = u16_at(elf, 0x3c)? as usize;
+        let shstrndx = u16_at(elf, 0x3e)? as usize;
+        let section = |index: usize| -> Result<(u32, usize, usize), String> {
+            let header = shoff
+                .checked_add(index * shentsize)
+                .and_then(|offset| slice(elf, offset, 40))
+                .ok_or("ELF section header is truncated")?;
+            Ok((
+                u32_at(header, 0)?,
+                u64_at(header, 24)? as usize,
+                u64_at(header, 32)? as usize,
+            ))
+        };
+
+        let (_, strtab, strtab_size) = section(shstrndx)?;
+        let strtab = slice(elf, strtab, strtab_size).ok_or("ELF file is truncated")?;
+        let mut note = None;
+        for index in 0..shnum {
+            let (name, offset, size) = section(index)?;
+            let name = strtab
+                .get(name as usize..)
+                .and_then(|s| s.split(|c| *c == 0).next())
+                .unwrap_or_default();
+            if name == SGXMETA_SECTION.as_bytes() {
+                note = slice(elf, offset, size);
+                break;
+            }
+        }
+        let note = note.ok_or_else(|| {
+            format!(
+                "{} section isn't found, is the enclave signed?",
+                SGXMETA_SECTION
+            )
+        })?;
+
+        // Note header: name size, descriptor size, type, then the padded name
+        let namesz = u32_at(note, 0)? as usize;
+        if note.get(12..12 + namesz) != Some(SGXMETA_NOTE_NAME) {
+            return Err(format!("{} isn't an SGX metadata note", SGXMETA_SECTION));
+        }
+        let metadata = note
+            .get(12 + ((namesz + 3) & !3)..)
+            .ok_or("SGX metadata is truncated")?;
+        if u64_at(metadata, 0)? != METADATA_MAGIC {
+            return Err("SGX metadata magic number is invalid".to_string());
+        }
+
+        metadata
+            .get(METADATA_SIGSTRUCT_OFFSET..)
+            .ok_or_else(|| "SGX metadata is truncated".to_string())
+            .and_then(Self::from_sigstruct)
+    }
+}
+
+impl Merge {
+    pub fn parse(mode: &str) -> Result<Merge, String> {
+        match mode {
+            "append" => Ok(Merge::Append),
+            "replace" => Ok(Merge::Replace),
+            _ => Err(format!("merge mode {} isn't append or replace", mode)),
+        }
+    }
+}
+
+/// Merge `sigstruct` into the `sgxData` shaped `reference`. Appending adds
+/// the measurements and lowers productId and svn so every imported enclave
+/// still passes, replacing accepts the imported enclave only. Other fields
+/// of the reference are kept.
+pub fn merge(reference: &str, sigstruct: &Sigstruct, mode: Merge) -> Result<String, String> {
+    let mut reference: Value = match reference.trim() {
+        "" => serde_json::json!({}),
+        reference => serde_json::from_str(reference)
+            .map_err(|e| format!("reference isn't in json format: {}", e))?,
+    };
+    let object = reference
+        .as_object_mut()
+        .ok_or("reference isn't a json object")?;
+
+    for (field, value) in [
+        ("mrEnclave", base64::encode(sigstruct.mr_enclave)),
+        ("mrSigner", base64::encode(sigstruct.mr_signer)),
+    ] {
+        let mut values = match (mode, object.get(field)) {
+            (Merge::Append, Some(Value::Array(values))) => values.clone(),
+            (Merge::Append, Some(_)) => return Err(format!("{} isn't an array", field)),
+            _ => Vec::new(),
+        };
+        if !values.contains(&Value::String(value.clone())) {
+            values.push(Value::String(value));
+        }
+        object.insert(field.to_string(), Value::Array(values));
+    }
+
+    for (field, value) in [("productId", sigstruct.product_id), ("svn", sigstruct.svn)] {
+        let value = match (mode, object.get(field).and_then(|v| v.as_u64())) {
+            (Merge::Append, Some(current)) => current.min(value as u64),
+            _ => value as u64,
+        };
+        object.insert(field.to_string(), Value::from(value));
+    }
+
+    serde_json::to_string_pretty(&reference).map_err(|e| e.to_string())
+}
+
+/// Merge the reference values of the signed enclave or SIGSTRUCT `content`
+/// into the OPA reference `name` and return the new reference
+pub fn import(name: &str, content: &[u8], mode: Merge) -> Result<String, String> {
+    let sigstruct = Sigstruct::parse(content)?;
+    info!(
+        "import mrEnclave {}, mrSigner {}, productId {}, svn {} into {}",
+        base64::encode(sigstruct.mr_enclave),
+        base64::encode(sigstruct.mr_signer),
+        sigstruct.product_id,
+        sigstruct.svn,
+        name
+    );
+
+    let current = match opa::exists(name) {
+        true => opa::export(name)?,
+        false => String::new(),
+    };
+    let reference = merge(&current, &sigstruct, mode)?;
+    opa::set_reference(name, &reference)?;
+    Ok(reference)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sigstruct() -> Vec<u8> {
+        let mut sigstruct = vec![0u8; SIGSTRUCT_SIZE];
+        sigstruct[..16].copy_from_slice(&SIGSTRUCT_HEADER);
+        sigstruct[MODULUS_OFFSET..MODULUS_OFFSET + MODULUS_SIZE].copy_from_slice(&[7u8; 384]);
+        sigstruct[ENCLAVE_HASH_OFFSET..ENCLAVE_HASH_OFFSET + 32].copy_from_slice(&[1u8; 32]);
+        sigstruct[ISV_PROD_ID_OFFSET..ISV_PROD_ID_OFFSET + 2].copy_from_slice(&3u16.to_le_bytes());
+        sigstruct[ISV_SVN_OFFSET..ISV_SVN_OFFSET + 2].copy_from_slice(&5u16.to_le_bytes());
+        sigstruct
+    }
+
+    // Minimal ELF64 with a section header table of a null section, the
+    // section name table and the .note.sgxmeta section
+    fn elf(sigstruct: &[u8]) -> Vec<u8> {
+        let mut note = Vec::new();
+        note.extend_from_slice(&(SGXMETA_NOTE_NAME.len() as u32).to_le_bytes());
+        note.extend_from_slice(
+            &((METADATA_SIGSTRUCT_OFFSET + sigstruct.len()) as u32).to_le_bytes(),
+        );
+        note.extend_from_slice(&1u32.to_le_bytes());
+        note.extend_from_slice(SGXMETA_NOTE_NAME);
+        note.resize(12 + 16, 0);
+        let mut metadata = vec![0u8; METADATA_SIGSTRUCT_OFFSET];
+        metadata[..8].copy_from_slice(&METADATA_MAGIC.to_le_bytes());
+        note.extend_from_slice(&metadata);
+        note.extend_from_slice(sigstruct);
+
+        let strtab = b"\0.shstrtab\0.note.sgxmeta\0".to_vec();
+        let strtab_offset = 64;
+        let note_offset = strtab_offset + strtab.len();
+        let shoff = note_offset + note.len();
+
+        let mut elf = vec![0u8; 64];
+        elf[..4].copy_from_slice(ELF_MAGIC);
+        elf[4] = 2;
+        elf[0x28..0x30].copy_from_slice(&(shoff as u64).to_le_bytes());
+        elf[0x3a..0x3c].copy_from_slice(&64u16.to_le_bytes());
+        elf[0x3c..0x3e].copy_from_slice(&3u16.to_le_bytes());
+        elf[0x3e..0x40].copy_from_slice(&1u16.to_le_bytes());
+        elf.extend_from_slice(&strtab);
+        elf.extend_from_slice(&note);
+        for (name, offset, size) in [
+            (0u32, 0usize, 0usize),
+            (1, strtab_offset, strtab.len()),
+            (11, note_offset, note.len()),
+        ] {
+            let mut header = vec![0u8; 64];
+            header[..4].copy_from_slice(&name.to_le_bytes());
+            header[24..32].copy_from_slice(&(offset as u64).to_le_bytes());
+            header[32..40].copy_from_slice(&(size as u64).to_le_bytes());
+            elf.extend_from_slice(&header);
+        }
+        elf
+    }
+
+    #[test]
+    fn test_parse() {
+        let mut hasher = Sha256::new();
+        hasher.input(&[7u8; 384]);
+        let mut mr_signer = [0u8; 32];
+        hasher.result(&mut mr_signer);
+        let expected = Sigstruct {
+            mr_enclave: [1u8; 32],
+            mr_signer,
+            product_id: 3,
+            svn: 5,
+        };
+
+        assert_eq!(Sigstruct::parse(&sigstruct()).unwrap(), expected);
+        assert_eq!(Sigstruct::parse(&elf(&sigstruct())).unwrap(), expected);
+        assert!(Sigstruct::parse(&sigstruct()[..1000]).is_err());
+        assert!(Sigstruct::parse(&vec![0u8; SIGSTRUCT_SIZE]).is_err());
+        assert!(Sigstruct::parse(&elf(&[0u8; SIGSTRUCT_SIZE])).is_err());
+    }
+
+    #[test]
+    fn test_merge() {
+        let sigstruct = Sigstruct::parse(&sigstruct()).unwrap();
+        let mr_enclave = base64::encode([1u8; 32]);
+        let reference = r#"{"mrEnclave": ["xxx"], "mrSigner": [], "productId": 1, "svn": 9,
+            "allowDebug": false, "tcbStatus": []}"#;
+
+        let appended: Value =
+            serde_json::from_str(&merge(reference, &sigstruct, Merge::Append).unwrap()).unwrap();
+        assert_eq!(
+            appended["mrEnclave"],
+            serde_json::json!(["xxx", mr_enclave])
+        );
+        assert_eq!(appended["mrSigner"].as_array().unwrap().len(), 1);
+        assert_eq!(appended["productId"], 1);
+        assert_eq!(appended["svn"], 5);
+        assert_eq!(appended["allowDebug"], false);
+
+        let replaced: Value =
+            serde_json::from_str(&merge(reference, &sigstruct, Merge::Replace).unwrap()).unwrap();
+        assert_eq!(replaced["mrEnclave"], serde_json::json!([mr_enclave]));
+        assert_eq!(replaced["productId"], 3);
+        assert_eq!(replaced["tcbStatus"], serde_json::json!([]));
+
+        let created: Value =
+            serde_json::from_str(&merge("", &sigstruct, Merge::Append).unwrap()).unwrap();
+        assert_eq!(created["svn"], 5);
+        assert!(merge("[]", &sigstruct, Merge::Append).is_err());
+    }
+}
@@ -0,0 +1,142 @@
+//! Attestation result tokens signed by verdictd.
+//!
+//! The Ed25519 signing key is generated on first start and its seed is kept
+//! in `/opt/verdictd/token/signing.key`.
+use crate::crypto::jwt::{self, SigningKey};
+use lazy_static::lazy_static;
+use parking_lot::RwLock;
+use rand::RngCore;
+use serde_json::Value;
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+lazy_static! {
+    // Global file lock
+    pub static ref FILE_LOCK: RwLock<u32> = RwLock::new(0);
+}
+
+pub const TOKEN_PATH: &str = "/opt/verdictd/token/";
+pub const SIGNING_KEY: &str = "/opt/verdictd/token/signing.key";
+
+pub const ISSUER: &str = "verdictd";
+/// Lifetime of an issued token in seconds
+pub const TOKEN_TTL: u64 = 3600;
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+fn signing_key() -> Result<SigningKey, String> {
+    let lock = FILE_LOCK.read();
+    assert_eq!(*lock, 0);
+
+    fs::read(SIGNING_KEY)
+        .map_err(|e| format!("read signing key failed: {}", e))
+        .and_then(|seed| SigningKey::from_seed(&seed))
+}
+
+/// Issue a token for a peer whose evidence `claims` of `tee` passed `policy`
+pub fn issue(
+    tee: &str,
+    claims: &Value,
+    policy: &str,
+    nonce: Option<&str>,
+) -> Result<String, String> {
+    let key = signing_key()?;
+    let iat = now();
+
+    let mut token = serde_json::json!({
+        "iss": ISSUER,
+        "iat": iat,
+        "exp": iat + TOKEN_TTL,
+        "tee": tee,
+        "evidence": claims,
+        "policy": policy,
+        "decision": "allow",
+    });
+    if let Some(nonce) = nonce {
+        token["nonce"] = Value::String(nonce.to_string());
+    }
+
+    Ok(key.sign(&token))
+}
+
+/// Verify the signature and expiry of `token`, return its claims
+pub fn verify(token: &str) -> Result<Value, String> {
+    let key = signing_key()?;
+
+    let kid = jwt::header(token)?["kid"].clone();
+    if kid != key.kid.as_str() {
+        return Err(format!("unknown signing key {}", kid));
+    }
+
+    let claims = jwt::verify(token, &key.public)?;
+    if claims["iss"] != ISSUER {
+        return Err("token isn't issued by verdictd".to_string());
+    }
+    match claims["exp"].as_u64() {
+        Some(exp) if exp > now() => Ok(claims),
+        _ => Err("token expired".to_string()),
+    }
+}
+
+/// The public keys verifying the issued tokens, in JWK Set format
+pub fn jwks() -> Result<Value, String> {
+    signing_key().map(|key| serde_json::json!({ "keys": [key.jwk()] }))
+}
+
+pub fn default() -> Result<(), String> {
+    if !Path::new(&TOKEN_PATH.to_string()).exists() {
+        fs::create_dir_all(TOKEN_PATH).map_err(|_| format!("create {:?} failed", TOKEN_PATH))?;
+    }
+
+    let lock = FILE_LOCK.write();
+    assert_eq!(*lock, 0);
+
+    if !Path::new(SIGNING_KEY).exists() {
+        let mut seed = [0u8; 32];
+        rand::rngs::OsRng.fill_bytes(&mut seed);
+        fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(SIGNING_KEY)
+            .and_then(|mut file| file.write_all(&seed))
+            .map_err(|e| format!("create signing key failed: {}", e))?;
+        info!("Generated token signing key {}", SIGNING_KEY);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_issue_and_verify() {
+        default().unwrap();
+
+        let claims = serde_json::json!({"mrEnclave": "xxx", "svn": 1});
+        let token = issue("sgx", &claims, "sgxPolicy.rego", Some("nonce")).unwrap();
+
+        let verified = verify(&token).unwrap();
+        assert_eq!(verified["tee"], "sgx");
+        assert_eq!(verified["evidence"], claims);
+        assert_eq!(verified["policy"], "sgxPolicy.rego");
+        assert_eq!(verified["nonce"], "nonce");
+
+        let kid = jwks().unwrap()["keys"][0]["kid"].clone();
+        assert_eq!(jwt::header(&token).unwrap()["kid"], kid);
+
+        let mut forged = token.clone();
+        forged.push('A');
+        assert!(verify(&forged).is_err());
+    }
+}